@@ -0,0 +1,53 @@
+use actix_rt::Runtime;
+use criterion::{criterion_group, criterion_main, Criterion};
+
+const TASKS: usize = 1_000_000;
+
+fn bench_spawn(c: &mut Criterion) {
+    let mut group = c.benchmark_group("spawn");
+    group.sample_size(10);
+
+    group.bench_function("spawn and complete 1M tasks", |b| {
+        let mut rt = Runtime::new().unwrap();
+        b.iter(|| {
+            for _ in 0..TASKS {
+                rt.spawn(async {});
+            }
+            rt.run().unwrap();
+        })
+    });
+
+    // tasks spawned by a running task, e.g. one task per request
+    group.bench_function("spawn 1M tasks from a task", |b| {
+        let mut rt = Runtime::new().unwrap();
+        b.iter(|| {
+            rt.block_on(async {
+                for _ in 0..TASKS {
+                    actix_rt::spawn(async {}).unwrap();
+                }
+            });
+            rt.run().unwrap();
+        })
+    });
+
+    // few tasks alive at a time, completed tasks make room for new ones
+    group.bench_function("spawn 1M tasks, 64 alive", |b| {
+        let mut rt = Runtime::new().unwrap();
+        b.iter(|| {
+            rt.block_on(async {
+                for i in 0..TASKS {
+                    actix_rt::spawn(async {}).unwrap();
+                    if i % 64 == 63 {
+                        actix_rt::task::yield_now().await;
+                    }
+                }
+            });
+            rt.run().unwrap();
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_spawn);
+criterion_main!(benches);
@@ -0,0 +1,95 @@
+//! Runtime that parks on a `mio::Poll` owned by the application.
+//!
+//! Application registers its own event sources with the poll, the runtime
+//! blocks in that poll whenever it has no tasks ready to run.
+//! run: cargo run --example mio_park
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::time::delay_for;
+use actix_rt::System;
+use mio::{Events, Poll, PollOpt, Ready, Registration, SetReadiness, Token};
+use tokio_executor::park::{Park, Unpark};
+
+const WAKEUP: Token = Token(0);
+const APP: Token = Token(1);
+
+/// Park that polls the application's `mio::Poll`
+struct PollPark {
+    poll: Arc<Poll>,
+    events: Events,
+    wakeup: (Registration, SetReadiness),
+}
+
+impl PollPark {
+    fn new(poll: Arc<Poll>) -> io::Result<Self> {
+        let wakeup = Registration::new2();
+        poll.register(&wakeup.0, WAKEUP, Ready::readable(), PollOpt::edge())?;
+        Ok(PollPark {
+            poll,
+            events: Events::with_capacity(64),
+            wakeup,
+        })
+    }
+
+    fn turn(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        self.poll.poll(&mut self.events, timeout)?;
+        for event in &self.events {
+            match event.token() {
+                WAKEUP => self.wakeup.1.set_readiness(Ready::empty())?,
+                token => println!("application event: {:?}", token),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wakes the parked runtime from any thread
+struct PollUnpark(SetReadiness);
+
+impl Unpark for PollUnpark {
+    fn unpark(&self) {
+        let _ = self.0.set_readiness(Ready::readable());
+    }
+}
+
+impl Park for PollPark {
+    type Unpark = PollUnpark;
+    type Error = io::Error;
+
+    fn unpark(&self) -> Self::Unpark {
+        PollUnpark(self.wakeup.1.clone())
+    }
+
+    fn park(&mut self) -> io::Result<()> {
+        self.turn(None)
+    }
+
+    fn park_timeout(&mut self, duration: Duration) -> io::Result<()> {
+        self.turn(Some(duration))
+    }
+}
+
+fn main() -> io::Result<()> {
+    let poll = Arc::new(Poll::new()?);
+
+    // event source of the application, e.g. fd of a foreign event loop
+    let (registration, readiness) = Registration::new2();
+    poll.register(&registration, APP, Ready::readable(), PollOpt::edge())?;
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        readiness.set_readiness(Ready::readable()).unwrap();
+    });
+
+    let mut rt = System::builder()
+        .with_park(PollPark::new(poll.clone())?)
+        .build_rt()?;
+
+    rt.block_on(async {
+        delay_for(Duration::from_millis(100)).await;
+        println!("timer fired");
+    });
+    Ok(())
+}
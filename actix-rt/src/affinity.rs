@@ -0,0 +1,35 @@
+//! CPU affinity of the runtime thread, requires `core_affinity` feature.
+use std::io;
+
+/// Pin the current thread to the given cores.
+#[cfg(target_os = "linux")]
+pub(crate) fn set_for_current(cores: &[usize]) -> io::Result<()> {
+    use std::mem;
+
+    // safe, `cpu_set_t` is a plain bit mask
+    let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+    for &core in cores {
+        if core >= libc::CPU_SETSIZE as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("core id {} is out of range", core),
+            ));
+        }
+        unsafe { libc::CPU_SET(core, &mut set) };
+    }
+
+    let res = unsafe { libc::sched_setaffinity(0, mem::size_of::<libc::cpu_set_t>(), &set) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Pin the current thread to the given cores.
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set_for_current(_: &[usize]) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "cpu affinity is not supported on this platform",
+    ))
+}
@@ -1,16 +1,22 @@
 use std::any::{Any, TypeId};
 use std::cell::{Cell, RefCell};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::{fmt, thread};
+use std::sync::{Arc, Mutex};
+use std::{fmt, io, thread};
 
 use futures::channel::mpsc::{unbounded, UnboundedReceiver, UnboundedSender};
-use futures::channel::oneshot::{channel, Canceled, Sender};
+use futures::channel::oneshot::{channel, Canceled, Receiver, Sender};
+use futures::future::BoxFuture;
 use futures::{future, Future, Poll, Stream, TryFuture, TryFutureExt};
-use tokio_executor::current_thread::spawn;
 
 use crate::builder::Builder;
+use crate::panic::message;
+use crate::runtime::spawn_local;
 use crate::system::System;
+use crate::task::{self, JoinHandle};
+use crate::tasks;
 
 use copyless::BoxHelper;
 use std::pin::Pin;
@@ -45,7 +51,33 @@ impl fmt::Debug for ArbiterCommand {
 /// Arbiters provide an asynchronous execution environment for actors, functions
 /// and futures. When an Arbiter is created, they spawn a new OS thread, and
 /// host an event loop. Some Arbiter functions execute on the current thread.
-pub struct Arbiter(UnboundedSender<ArbiterCommand>);
+///
+/// Dropping the last handle returned by `Arbiter::new()` (or one of its clones)
+/// stops the arbiter after the already sent commands are processed.
+pub struct Arbiter {
+    sender: UnboundedSender<ArbiterCommand>,
+    /// Set on handles returned by `Arbiter::new()` and their clones
+    owner: Option<Arc<Owner>>,
+}
+
+/// Shared by the owning handles of the arbiter
+struct Owner {
+    sender: UnboundedSender<ArbiterCommand>,
+    /// Current thread of the arbiter, replaced on restart
+    thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+impl Drop for Owner {
+    fn drop(&mut self) {
+        let _ = self.sender.unbounded_send(ArbiterCommand::Stop);
+    }
+}
+
+impl fmt::Debug for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Owner").finish()
+    }
+}
 
 impl Default for Arbiter {
     fn default() -> Self {
@@ -57,15 +89,27 @@ impl Arbiter {
     pub(crate) fn new_system() -> Self {
         let (tx, rx) = unbounded();
 
-        let arb = Arbiter(tx);
+        let arb = Arbiter::from_sender(tx);
         ADDR.with(|cell| *cell.borrow_mut() = Some(arb.clone()));
         RUNNING.with(|cell| cell.set(false));
         STORAGE.with(|cell| cell.borrow_mut().clear());
-        Arbiter::spawn(ArbiterController { stop: None, rx });
+        Arbiter::spawn(ArbiterController {
+            stop: None,
+            rx: Some(rx),
+            recover: None,
+            executing: false,
+        });
 
         arb
     }
 
+    fn from_sender(sender: UnboundedSender<ArbiterCommand>) -> Self {
+        Arbiter {
+            sender,
+            owner: None,
+        }
+    }
+
     /// Returns the current thread's arbiter's address. If no Arbiter is present, then this
     /// function will panic!
     pub fn current() -> Arbiter {
@@ -77,53 +121,88 @@ impl Arbiter {
 
     /// Stop arbiter from continuing it's event loop.
     pub fn stop(&self) {
-        let _ = self.0.unbounded_send(ArbiterCommand::Stop);
+        let _ = self.sender.unbounded_send(ArbiterCommand::Stop);
     }
 
     /// Spawn new thread and run event loop in spawned thread.
     /// Returns address of newly created arbiter.
     pub fn new() -> Arbiter {
         let id = COUNT.fetch_add(1, Ordering::Relaxed);
-        let name = format!("actix-rt:worker:{}", id);
-        let sys = System::current();
+        Arbiter::start(id, format!("actix-rt:worker:{}", id), None, None, None)
+    }
+
+    /// Same as `Arbiter::new()`, but the arbiter thread gets the given name.
+    pub fn with_name<T: Into<String>>(name: T) -> Arbiter {
+        let id = COUNT.fetch_add(1, Ordering::Relaxed);
+        Arbiter::start(id, name.into(), None, None, None)
+    }
+
+    /// Create builder to configure arbiter threads and their runtimes.
+    pub fn builder() -> ArbiterBuilder {
+        ArbiterBuilder {
+            name: None,
+            stack_size: None,
+            runtime: None,
+            restart_on_panic: false,
+            on_restart: None,
+            index: AtomicUsize::new(0),
+        }
+    }
+
+    fn start(
+        id: usize,
+        name: String,
+        stack_size: Option<usize>,
+        runtime: Option<Arc<Builder>>,
+        restart: Option<Arc<Restart>>,
+    ) -> Arbiter {
         let (arb_tx, arb_rx) = unbounded();
-        let arb_tx2 = arb_tx.clone();
-
-        let _ = thread::Builder::new().name(name.clone()).spawn(move || {
-            let mut rt = Builder::new().build_rt().expect("Can not create Runtime");
-            let arb = Arbiter(arb_tx);
-
-            let (stop, stop_rx) = channel();
-            RUNNING.with(|cell| cell.set(true));
-            STORAGE.with(|cell| cell.borrow_mut().clear());
-
-            System::set_current(sys);
-
-            // start arbiter controller
-            rt.spawn(ArbiterController {
-                stop: Some(stop),
-                rx: arb_rx,
-            });
-            ADDR.with(|cell| *cell.borrow_mut() = Some(arb.clone()));
-
-            // register arbiter
-            let _ = System::current()
-                .sys()
-                .unbounded_send(SystemCommand::RegisterArbiter(id, arb.clone()));
-
-            // run loop
-            let _ = match rt.block_on(stop_rx) {
-                Ok(code) => code,
-                Err(_) => 1,
-            };
-
-            // unregister arbiter
-            let _ = System::current()
-                .sys()
-                .unbounded_send(SystemCommand::UnregisterArbiter(id));
-        });
+        let thread = Arc::new(Mutex::new(None));
+        let launch = Launch {
+            id,
+            name,
+            stack_size,
+            runtime,
+            sys: System::current(),
+            sender: arb_tx.clone(),
+            restart,
+            thread: thread.clone(),
+        };
+        if let Ok(handle) = launch.spawn(arb_rx, false) {
+            // thread of a restarted arbiter is replaced already
+            thread.lock().unwrap().get_or_insert(handle);
+        }
+
+        Arbiter {
+            sender: arb_tx.clone(),
+            owner: Some(Arc::new(Owner {
+                sender: arb_tx,
+                thread,
+            })),
+        }
+    }
 
-        Arbiter(arb_tx2)
+    /// Wait for the arbiter thread to exit.
+    ///
+    /// The arbiter has to be stopped with `stop()`, or this has to be the last
+    /// handle returned by `Arbiter::new()`, in which case joining stops it.
+    /// Returns immediately for addresses obtained with `Arbiter::current()`.
+    /// Restarted arbiter is joined once its last thread exits.
+    pub fn join(self) -> thread::Result<()> {
+        let slot = self.owner.as_ref().map(|owner| owner.thread.clone());
+        drop(self);
+        let mut res = Ok(());
+        if let Some(slot) = slot {
+            // panicked thread stores its replacement before it exits
+            loop {
+                let thread = slot.lock().unwrap().take();
+                match thread {
+                    Some(thread) => res = thread.join(),
+                    None => break,
+                }
+            }
+        }
+        res
     }
 
     pub(crate) fn run_system() {
@@ -131,7 +210,7 @@ impl Arbiter {
         Q.with(|cell| {
             let mut v = cell.borrow_mut();
             for fut in v.drain(..) {
-                spawn(fut);
+                spawn_local(fut);
             }
         });
     }
@@ -149,13 +228,27 @@ impl Arbiter {
     {
         RUNNING.with(move |cell| {
             if cell.get() {
-                spawn(Pin::from(Box::alloc().init(future)));
+                spawn_local(Pin::from(Box::alloc().init(future)));
             } else {
+                let future = tasks::in_current_span(future);
                 Q.with(move |cell| cell.borrow_mut().push(Pin::from(Box::alloc().init(future))));
             }
         });
     }
 
+    /// Spawn a future on the current thread and return handle to its output.
+    ///
+    /// Same as `Arbiter::spawn()`, but the handle resolves with the output of
+    /// the future and can abort it.
+    pub fn spawn_with_handle<F>(future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + 'static,
+    {
+        let (task, handle) = task::with_handle(future);
+        Arbiter::spawn(task);
+        handle
+    }
+
     /// Executes a future on the current thread. This does not create a new Arbiter
     /// or Arbiter address, it is simply a helper for executing futures on the current
     /// thread.
@@ -175,8 +268,20 @@ impl Arbiter {
         F: Future<Output = ()> + Send + 'static,
     {
         let _ = self
-            .0
-            .unbounded_send(ArbiterCommand::Execute(Box::pin(future)));
+            .sender
+            .unbounded_send(ArbiterCommand::Execute(Box::pin(tasks::in_current_span(future))));
+    }
+
+    /// Send a function to the Arbiter's thread and spawn the future it returns.
+    ///
+    /// Unlike `send()`, the future is constructed on the Arbiter's thread, so it
+    /// does not have to be `Send`.
+    pub fn spawn_fn<F, R>(&self, f: F)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Future<Output = ()> + 'static,
+    {
+        self.exec_fn(move || Arbiter::spawn(f()));
     }
 
     /// Send a function to the Arbiter's thread, and execute it. Any result from the function
@@ -186,7 +291,7 @@ impl Arbiter {
         F: FnOnce() + Send + 'static,
     {
         let _ = self
-            .0
+            .sender
             .unbounded_send(ArbiterCommand::ExecuteFn(Box::new(move || {
                 f();
             })));
@@ -202,7 +307,7 @@ impl Arbiter {
     {
         let (tx, rx) = channel();
         let _ = self
-            .0
+            .sender
             .unbounded_send(ArbiterCommand::ExecuteFn(Box::new(move || {
                 if !tx.is_canceled() {
                     let _ = tx.send(f());
@@ -256,14 +361,262 @@ impl Arbiter {
     }
 }
 
+/// Builder of arbiters with configured threads and runtimes.
+///
+/// Builder can start any number of identical arbiters.
+pub struct ArbiterBuilder {
+    name: Option<String>,
+    stack_size: Option<usize>,
+    runtime: Option<Arc<Builder>>,
+    restart_on_panic: bool,
+    on_restart: Option<Arc<dyn Fn() + Send + Sync>>,
+    /// Index of the next arbiter, fills the name placeholder
+    index: AtomicUsize,
+}
+
+impl ArbiterBuilder {
+    /// Set name of the arbiter threads, `{}` is replaced with the index of
+    /// the arbiter started by this builder, counting from zero.
+    ///
+    /// Defaults to `actix-rt:worker:{id}` with the global arbiter id.
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set stack size of the arbiter threads in bytes.
+    ///
+    /// Defaults to the stack size of `std::thread`.
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Build the runtime of each arbiter with `builder`.
+    ///
+    /// Name and clock of the builder apply to each runtime, callbacks set
+    /// with `on_start()` and `on_stop()` and a park set with `with_park()`
+    /// are taken by the first arbiter only.
+    pub fn runtime_builder(mut self, builder: Builder) -> Self {
+        self.runtime = Some(Arc::new(builder));
+        self
+    }
+
+    /// Restart the arbiter on a new thread if its thread panics.
+    ///
+    /// Replacement thread gets the same name and a new runtime, it takes
+    /// the place of the arbiter in the `System` and handles of the arbiter
+    /// send to it. Commands that were sent but not processed before the panic
+    /// are dropped and counted in an error log, futures returned by `exec()`
+    /// resolve with `Canceled` for them. Arbiter that panics after it is
+    /// asked to stop, or while it builds its runtime, is not restarted.
+    /// Restarted arbiters do not stop the system on panic.
+    ///
+    /// Defaults to false.
+    pub fn restart_on_panic(mut self, restart: bool) -> Self {
+        self.restart_on_panic = restart;
+        self
+    }
+
+    /// Set callback that is invoked on the replacement thread of a restarted
+    /// arbiter, before it processes commands.
+    ///
+    /// Callback runs within the new runtime, so it can re-seed arbiter
+    /// storage and spawn futures. Arbiter is not restarted again if the
+    /// callback panics.
+    pub fn on_restart<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_restart = Some(Arc::new(f));
+        self
+    }
+
+    /// Spawn new thread and run event loop in spawned thread.
+    ///
+    /// Arbiter thread panics if it can not build its runtime.
+    pub fn start(&self) -> Arbiter {
+        let id = COUNT.fetch_add(1, Ordering::Relaxed);
+        let index = self.index.fetch_add(1, Ordering::Relaxed);
+        let name = match self.name {
+            Some(ref name) => name.replace("{}", &index.to_string()),
+            None => format!("actix-rt:worker:{}", id),
+        };
+        let restart = if self.restart_on_panic {
+            Some(Arc::new(Restart {
+                on_restart: self.on_restart.clone(),
+            }))
+        } else {
+            None
+        };
+        Arbiter::start(id, name, self.stack_size, self.runtime.clone(), restart)
+    }
+}
+
+impl fmt::Debug for ArbiterBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ArbiterBuilder")
+            .field("name", &self.name)
+            .field("stack_size", &self.stack_size)
+            .field("restart_on_panic", &self.restart_on_panic)
+            .finish()
+    }
+}
+
+/// Restart settings of a supervised arbiter
+struct Restart {
+    on_restart: Option<Arc<dyn Fn() + Send + Sync>>,
+}
+
+/// Receiver of arbiter commands, handed back by a panicking arbiter
+type Recovered = Arc<Mutex<Option<UnboundedReceiver<ArbiterCommand>>>>;
+
+/// Everything needed to start the thread of an arbiter, and to start it
+/// again after a panic
+struct Launch {
+    id: usize,
+    name: String,
+    stack_size: Option<usize>,
+    runtime: Option<Arc<Builder>>,
+    sys: System,
+    sender: UnboundedSender<ArbiterCommand>,
+    restart: Option<Arc<Restart>>,
+    thread: Arc<Mutex<Option<thread::JoinHandle<()>>>>,
+}
+
+impl Launch {
+    fn spawn(
+        self,
+        rx: UnboundedReceiver<ArbiterCommand>,
+        restarted: bool,
+    ) -> io::Result<thread::JoinHandle<()>> {
+        let mut builder = thread::Builder::new().name(self.name.clone());
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        builder.spawn(move || {
+            if self.restart.is_none() {
+                return self.run(rx, restarted, None);
+            }
+
+            let recovered = Arc::new(Mutex::new(None));
+            let res = catch_unwind(AssertUnwindSafe(|| {
+                self.run(rx, restarted, Some(recovered.clone()))
+            }));
+            if let Err(err) = res {
+                let rx = recovered.lock().unwrap().take();
+                match rx {
+                    Some(rx) => self.restart(rx),
+                    None => resume_unwind(err),
+                }
+            }
+        })
+    }
+
+    /// Run arbiter until it gets stopped.
+    fn run(
+        &self,
+        rx: UnboundedReceiver<ArbiterCommand>,
+        restarted: bool,
+        recover: Option<Recovered>,
+    ) {
+        let mut rt = match self.runtime {
+            Some(ref runtime) => runtime.build_rt(),
+            None => Builder::new().build_rt(),
+        }
+        .expect("Can not create Runtime");
+        let arb = Arbiter::from_sender(self.sender.clone());
+        // dropped once the runtime is gone, even if the arbiter panics
+        let (exited, exited_rx) = channel::<()>();
+
+        let (stop, stop_rx) = channel();
+        RUNNING.with(|cell| cell.set(true));
+        STORAGE.with(|cell| cell.borrow_mut().clear());
+
+        System::set_current(self.sys.clone());
+        ADDR.with(|cell| *cell.borrow_mut() = Some(arb.clone()));
+
+        if restarted {
+            if let Some(on_restart) = self.restart.as_ref().and_then(|r| r.on_restart.clone()) {
+                rt.enter(|| on_restart());
+            }
+        }
+
+        // start arbiter controller
+        rt.spawn(ArbiterController {
+            stop: Some(stop),
+            rx: Some(rx),
+            recover,
+            executing: false,
+        });
+
+        // register arbiter, replaces the panicked thread of a restarted one
+        let _ = System::current()
+            .sys()
+            .unbounded_send(SystemCommand::RegisterArbiter(self.id, arb, exited_rx));
+
+        // run loop
+        let _ = rt.block_on(stop_rx);
+
+        drop(rt);
+
+        // unregister arbiter
+        let _ = System::current()
+            .sys()
+            .unbounded_send(SystemCommand::UnregisterArbiter(self.id));
+        drop(exited);
+    }
+
+    /// Start replacement of the panicked arbiter, unless it was asked to
+    /// stop.
+    fn restart(self, mut rx: UnboundedReceiver<ArbiterCommand>) {
+        let mut dropped = 0;
+        let mut stopped = false;
+        while let Ok(Some(cmd)) = rx.try_next() {
+            match cmd {
+                ArbiterCommand::Stop => stopped = true,
+                _ => dropped += 1,
+            }
+        }
+        if stopped {
+            log::error!("Arbiter {} panicked while stopping", self.name);
+            return;
+        }
+        if dropped != 0 {
+            log::error!(
+                "Arbiter {} panicked, restarting, {} undelivered commands dropped",
+                self.name,
+                dropped
+            );
+        } else {
+            log::error!("Arbiter {} panicked, restarting", self.name);
+        }
+
+        let (name, thread) = (self.name.clone(), self.thread.clone());
+        match self.spawn(rx, true) {
+            Ok(handle) => *thread.lock().unwrap() = Some(handle),
+            Err(err) => log::error!("Can not restart Arbiter {}: {}", name, err),
+        }
+    }
+}
+
 struct ArbiterController {
     stop: Option<Sender<i32>>,
-    rx: UnboundedReceiver<ArbiterCommand>,
+    rx: Option<UnboundedReceiver<ArbiterCommand>>,
+    /// Takes back the receiver if the arbiter panics and gets restarted
+    recover: Option<Recovered>,
+    /// Set while a function sent to the arbiter runs, its panic is caught
+    /// by the runtime and raised after the controller is dropped
+    executing: bool,
 }
 
 impl Drop for ArbiterController {
     fn drop(&mut self) {
-        if thread::panicking() {
+        if thread::panicking() || self.executing {
+            if let Some(ref recover) = self.recover {
+                *recover.lock().unwrap() = self.rx.take();
+                return;
+            }
             eprintln!("Panic in Arbiter thread, shutting down system.");
             if System::current().stop_on_panic() {
                 System::current().stop_with_code(1)
@@ -277,7 +630,8 @@ impl Future for ArbiterController {
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         loop {
-            match Pin::new(&mut self.rx).poll_next(cx) {
+            let rx = self.rx.as_mut().expect("receiver is taken on drop only");
+            match Pin::new(rx).poll_next(cx) {
                 Poll::Ready(None) => return Poll::Ready(()),
                 Poll::Ready(Some(item)) => match item {
                     ArbiterCommand::Stop => {
@@ -287,10 +641,12 @@ impl Future for ArbiterController {
                         return Poll::Ready(());
                     }
                     ArbiterCommand::Execute(fut) => {
-                        spawn(fut);
+                        spawn_local(fut);
                     }
                     ArbiterCommand::ExecuteFn(f) => {
+                        self.executing = true;
                         f.call_box();
+                        self.executing = false;
                     }
                 },
                 Poll::Pending => return Poll::Pending,
@@ -302,15 +658,73 @@ impl Future for ArbiterController {
 #[derive(Debug)]
 pub(crate) enum SystemCommand {
     Exit(i32),
-    RegisterArbiter(usize, Arbiter),
+    RegisterArbiter(usize, Arbiter, Receiver<()>),
     UnregisterArbiter(usize),
+    OnStop(StopHook),
+}
+
+/// Hook registered with `System::on_stop()`, runs on the system arbiter
+pub(crate) struct StopHook {
+    priority: i32,
+    start: Option<Box<dyn FnOnce() -> BoxFuture<'static, ()> + Send>>,
+    running: Option<BoxFuture<'static, ()>>,
+}
+
+impl StopHook {
+    pub(crate) fn new<F, R>(priority: i32, f: F) -> Self
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        StopHook {
+            priority,
+            start: Some(Box::new(move || Box::pin(f()))),
+            running: None,
+        }
+    }
+
+    /// Run the hook to completion, a panic completes the hook and is logged
+    fn poll_run(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            if let Some(start) = self.start.take() {
+                self.running = Some(start());
+            }
+            match self.running {
+                Some(ref mut fut) => fut.as_mut().poll(cx),
+                None => Poll::Ready(()),
+            }
+        }));
+        match res {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(())) => Poll::Ready(()),
+            Err(err) => {
+                log::error!("System stop hook panicked: {}", message(&err));
+                Poll::Ready(())
+            }
+        }
+    }
+}
+
+impl fmt::Debug for StopHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StopHook")
+            .field("priority", &self.priority)
+            .finish()
+    }
 }
 
 #[derive(Debug)]
 pub(crate) struct SystemArbiter {
     stop: Option<Sender<i32>>,
     commands: UnboundedReceiver<SystemCommand>,
-    arbiters: HashMap<usize, Arbiter>,
+    /// Registered arbiters in creation order, with their exit signal
+    arbiters: BTreeMap<usize, (Arbiter, Receiver<()>)>,
+    /// Exit code, set once the system is stopping
+    exit: Option<i32>,
+    /// Arbiter that has been told to stop and is awaited
+    stopping: Option<usize>,
+    /// Stop hooks in the order they run
+    hooks: Vec<StopHook>,
 }
 
 impl SystemArbiter {
@@ -318,7 +732,10 @@ impl SystemArbiter {
         SystemArbiter {
             commands,
             stop: Some(stop),
-            arbiters: HashMap::new(),
+            arbiters: BTreeMap::new(),
+            exit: None,
+            stopping: None,
+            hooks: Vec::new(),
         }
     }
 }
@@ -332,25 +749,60 @@ impl Future for SystemArbiter {
                 Poll::Ready(None) => return Poll::Ready(()),
                 Poll::Ready(Some(item)) => match item {
                     SystemCommand::Exit(code) => {
-                        // stop arbiters
-                        for arb in self.arbiters.values() {
-                            arb.stop();
-                        }
-                        // stop event loop
-                        if let Some(stop) = self.stop.take() {
-                            let _ = stop.send(code);
-                        }
+                        self.exit.get_or_insert(code);
                     }
-                    SystemCommand::RegisterArbiter(name, hnd) => {
-                        self.arbiters.insert(name, hnd);
+                    SystemCommand::RegisterArbiter(name, hnd, exited) => {
+                        self.arbiters.insert(name, (hnd, exited));
                     }
                     SystemCommand::UnregisterArbiter(name) => {
                         self.arbiters.remove(&name);
                     }
+                    SystemCommand::OnStop(hook) => {
+                        // equal priorities keep the registration order
+                        let idx = self
+                            .hooks
+                            .iter()
+                            .position(|h| h.priority > hook.priority)
+                            .unwrap_or_else(|| self.hooks.len());
+                        self.hooks.insert(idx, hook);
+                    }
                 },
-                Poll::Pending => return Poll::Pending,
+                Poll::Pending => break,
             }
         }
+
+        let code = match self.exit {
+            Some(code) => code,
+            None => return Poll::Pending,
+        };
+
+        // stop arbiters newest first, one at a time
+        while let Some(name) = self.arbiters.keys().next_back().cloned() {
+            let this = &mut *self;
+            let (arb, exited) = this.arbiters.get_mut(&name).unwrap();
+            if this.stopping != Some(name) {
+                this.stopping = Some(name);
+                arb.stop();
+            }
+            if Pin::new(exited).poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.arbiters.remove(&name);
+        }
+
+        // arbiters are gone, run stop hooks one at a time
+        while let Some(hook) = self.hooks.first_mut() {
+            if hook.poll_run(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.hooks.remove(0);
+        }
+
+        // stop event loop
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(code);
+        }
+        Poll::Ready(())
     }
 }
 
@@ -0,0 +1,22 @@
+//! Blocking code execution on the shared thread pool.
+//!
+//! Requires `blocking` feature.
+use std::convert::Infallible;
+use std::future::Future;
+
+#[doc(hidden)]
+pub use actix_threadpool::*;
+
+/// Execute blocking function on the shared thread pool, returned future
+/// resolves with the return value of the function.
+///
+/// Future can be awaited on any runtime. It resolves with
+/// `BlockingError::Panic` if the function panics and with
+/// `BlockingError::Overloaded` if the pool's job queue is full.
+pub fn spawn_blocking<F, R>(f: F) -> impl Future<Output = Result<R, BlockingError<Infallible>>>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    actix_threadpool::run_fn(f)
+}
@@ -1,5 +1,8 @@
 use std::borrow::Cow;
 use std::io;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 use futures::future;
 use futures::future::{lazy, Future};
@@ -7,13 +10,23 @@ use futures::channel::mpsc::unbounded;
 use futures::channel::oneshot::{channel, Receiver};
 
 use tokio_executor::current_thread::{CurrentThread, Handle};
+use tokio_executor::park::Park;
 use tokio_net::driver::Reactor;
 use tokio_timer::clock::Clock;
 use tokio_timer::timer::Timer;
 
 use crate::arbiter::{Arbiter, SystemArbiter};
-use crate::runtime::Runtime;
-use crate::system::System;
+#[cfg(feature = "core_affinity")]
+use crate::affinity;
+use crate::clock::{PausableClock, TestClock};
+use crate::park::{BoxedPark, Parker, ThreadPark, TurnHook, TurnStats, UtilizationHook};
+use crate::runtime::{self, OnStop, RunError, Runtime};
+use crate::system::{Registration, System};
+use crate::panic::{self, PanicPolicy};
+use crate::stop::DropPolicy;
+use crate::task::TaskMeta;
+use crate::tasks::{Hooks, Propagator, TickBudget};
+use crate::time::{self, TimerConfig};
 
 /// Builder struct for a actix runtime.
 ///
@@ -27,8 +40,41 @@ pub struct Builder {
     /// The clock to use
     clock: Clock,
 
+    /// Test clock backing `clock`, allows `Runtime::advance()`
+    test_clock: Option<TestClock>,
+
+    /// Granularity of timer deadlines
+    timer_resolution: Duration,
+
+    /// Longest timeout of a timer
+    max_timeout: Duration,
+
     /// Whether the Arbiter will stop the whole System on uncaught panic. Defaults to false.
     stop_on_panic: bool,
+
+    /// Task hooks of the runtime
+    hooks: Hooks,
+
+    /// Custom park under the timer, taken by the first built runtime
+    park: Option<Mutex<Option<BoxedPark>>>,
+
+    /// Whether the runtime gets a reactor and a timer. Both default to true.
+    enable_io: bool,
+    enable_time: bool,
+
+    /// Callback invoked after each reactor turn
+    on_reactor_turn: Option<TurnHook>,
+
+    /// Callback reporting utilization of the runtime thread
+    utilization: Option<(Duration, UtilizationHook)>,
+
+    /// Thread callbacks, taken by the first built runtime
+    on_start: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+    on_stop: Mutex<Option<Box<dyn FnOnce() + Send>>>,
+
+    /// Cores to pin the runtime thread to
+    #[cfg(feature = "core_affinity")]
+    cpu_affinity: Option<Vec<usize>>,
 }
 
 impl Builder {
@@ -36,7 +82,20 @@ impl Builder {
         Builder {
             name: Cow::Borrowed("actix"),
             clock: Clock::new(),
+            test_clock: None,
+            timer_resolution: time::DEFAULT_RESOLUTION,
+            max_timeout: time::MAX_TIMEOUT,
             stop_on_panic: false,
+            hooks: Hooks::default(),
+            park: None,
+            enable_io: true,
+            enable_time: true,
+            on_reactor_turn: None,
+            utilization: None,
+            on_start: Mutex::new(None),
+            on_stop: Mutex::new(None),
+            #[cfg(feature = "core_affinity")]
+            cpu_affinity: None,
         }
     }
 
@@ -51,6 +110,17 @@ impl Builder {
     /// Defaults to the system clock.
     pub fn clock(mut self, clock: Clock) -> Self {
         self.clock = clock;
+        self.test_clock = None;
+        self
+    }
+
+    /// Use manually advanced test clock, see `test_clock()`.
+    ///
+    /// Runtime built with a test clock can fire timers without real sleeping
+    /// with `Runtime::advance()`.
+    pub fn test_clock(mut self, clock: &TestClock) -> Self {
+        self.clock = clock.clock();
+        self.test_clock = Some(clock.clone());
         self
     }
 
@@ -63,6 +133,325 @@ impl Builder {
         self
     }
 
+    /// Set callback that is invoked with the duration of each task poll.
+    ///
+    /// Poll durations are not measured unless the callback is set.
+    pub fn on_task_poll<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Duration) + Send + Sync + 'static,
+    {
+        self.hooks.poll_hooks().on_poll = Some(Arc::new(f));
+        self
+    }
+
+    /// Set callback that is invoked with statistics of each reactor turn.
+    ///
+    /// Callback runs on the runtime thread right after the turn, it is
+    /// invoked for turns of a custom park as well. See also
+    /// `Runtime::reactor_stats()`.
+    pub fn on_reactor_turn<F>(mut self, f: F) -> Self
+    where
+        F: Fn(TurnStats) + Send + Sync + 'static,
+    {
+        self.on_reactor_turn = Some(Arc::new(f));
+        self
+    }
+
+    /// Report utilization of the runtime thread every `interval`.
+    ///
+    /// Callback runs on the runtime thread with the share of busy time of the
+    /// interval, from `0.0` for an idle runtime to `1.0` for a runtime that
+    /// never parks. Utilization is measured at parks of the runtime, it wakes
+    /// up at least once per interval to report while idle. See also
+    /// `RuntimeMetrics::busy_time()`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is zero.
+    pub fn utilization_callback<F>(mut self, interval: Duration, f: F) -> Self
+    where
+        F: Fn(f64) + Send + Sync + 'static,
+    {
+        assert!(
+            interval > Duration::from_millis(0),
+            "utilization interval must be greater than zero"
+        );
+        self.utilization = Some((interval, Arc::new(f)));
+        self
+    }
+
+    /// Sets limit of spawned tasks that are alive at the same time.
+    ///
+    /// Once the limit is reached, `Runtime::try_spawn()` and `Handle::spawn()`
+    /// fail with `SpawnErrorKind::AtCapacity`, spawn functions without an
+    /// error result panic. Tasks free their slot on completion.
+    ///
+    /// Defaults to unbounded.
+    pub fn max_spawned_tasks(mut self, max: usize) -> Self {
+        self.hooks.max_tasks = Some(max);
+        self
+    }
+
+    /// Sets number of task nodes the runtime allocates up front.
+    ///
+    /// Spawned tasks are stored in nodes of a slab owned by the runtime and
+    /// node of a completed task is reused by the next spawn. The slab grows
+    /// as needed, pre-sizing it avoids node allocations as long as fewer
+    /// than `capacity` tasks are alive.
+    ///
+    /// Defaults to 0.
+    pub fn task_slab_capacity(mut self, capacity: usize) -> Self {
+        self.hooks.task_slab_capacity = capacity;
+        self
+    }
+
+    /// Sets handling of unfinished tasks when the runtime is dropped.
+    ///
+    /// Defaults to `DropPolicy::Discard`.
+    pub fn on_drop(mut self, policy: DropPolicy) -> Self {
+        self.hooks.drop_policy = policy;
+        self
+    }
+
+    /// Set callback that is invoked before each poll of a spawned task.
+    ///
+    /// Root future of `block_on` is not instrumented unless
+    /// `instrument_block_on()` is enabled. Without poll callbacks polls are
+    /// not instrumented at all.
+    pub fn before_poll<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&TaskMeta) + Send + Sync + 'static,
+    {
+        self.hooks.poll_hooks().before = Some(Arc::new(f));
+        self
+    }
+
+    /// Set callback that is invoked after each poll of a spawned task with
+    /// the duration of the poll.
+    ///
+    /// See `Builder::before_poll()`.
+    pub fn after_poll<F>(mut self, f: F) -> Self
+    where
+        F: Fn(&TaskMeta, Duration) + Send + Sync + 'static,
+    {
+        self.hooks.poll_hooks().after = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets whether poll callbacks also wrap polls of the root future of
+    /// `block_on`.
+    ///
+    /// Defaults to false.
+    pub fn instrument_block_on(mut self, enabled: bool) -> Self {
+        self.hooks.poll_hooks().block_on = enabled;
+        self
+    }
+
+    /// Sets handling of panics in spawned tasks.
+    ///
+    /// Defaults to `PanicPolicy::Propagate`. Panics are caught around each
+    /// task poll with every policy, see `PanicPolicy::Propagate`. With catch
+    /// policies panicked task is dropped and the runtime keeps running other
+    /// tasks.
+    pub fn task_panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.hooks.panic_policy = policy;
+        self
+    }
+
+    /// Limit how many times a task can call `task::consume_budget()` in one
+    /// poll.
+    ///
+    /// Once the budget is exhausted, `task::consume_budget()` yields the task,
+    /// so the runtime can poll other tasks and turn its reactor and timer.
+    /// Budget is reset on each poll of the task. Defaults to unlimited.
+    ///
+    /// Budget is cooperative, it only applies to tasks that call
+    /// `task::consume_budget()`. The runtime can not interrupt a poll, a task
+    /// that loops over an always ready future without consuming its budget
+    /// still starves the runtime.
+    pub fn cooperative_budget(mut self, budget: u32) -> Self {
+        self.hooks.budget = Some(budget);
+        self
+    }
+
+    /// Limit polls between turns of the reactor and the timer.
+    ///
+    /// The root future of `block_on` is polled up to `max_root_polls` times
+    /// in a row while it wakes itself, and at most `max_task_polls` spawned
+    /// tasks are polled per tick, the rest wait for the next tick. Defaults
+    /// to `TickBudget::default()`.
+    pub fn tick_budget(mut self, budget: TickBudget) -> Self {
+        self.hooks.tick_budget = Some(budget);
+        self
+    }
+
+    /// Derive the order of task polls from `seed`, to replay failures that
+    /// depend on the order tasks run in.
+    ///
+    /// Before each poll, a generator seeded with `seed` decides whether the
+    /// woken task is moved to the back of the run queue instead, which also
+    /// reorders tasks woken by timers that fire together. The same seed gives
+    /// the same order of polls as long as tasks are woken on the runtime
+    /// thread only, e.g. with `Builder::test_clock()` instead of real time.
+    ///
+    /// Ids of the tasks polled in each tick are logged with `log::trace!`.
+    pub fn deterministic(mut self, seed: u64) -> Self {
+        self.hooks.seed = Some(seed);
+        self
+    }
+
+    /// Propagate thread-local context into tasks of the runtime.
+    ///
+    /// `capture` takes the context of the thread that spawns a task. Around
+    /// every poll of the task its context is installed with `restore`, after
+    /// the poll the task's context is captured again and the context of the
+    /// runtime thread is restored, so context does not leak across tasks.
+    /// Multiple contexts are installed in registration order and removed in
+    /// reverse order.
+    ///
+    /// Futures sent to an `Arbiter` capture the context of the arbiter
+    /// thread.
+    pub fn task_context<T, C, R>(mut self, capture: C, restore: R) -> Self
+    where
+        T: Send + 'static,
+        C: Fn() -> T + Send + Sync + 'static,
+        R: Fn(T) + Send + Sync + 'static,
+    {
+        self.hooks
+            .context
+            .push(Arc::new(Propagator::new(capture, restore)));
+        self
+    }
+
+    /// Report polls that block the runtime thread for longer than `threshold`.
+    ///
+    /// A monitor thread calls `f` with the duration of the poll so far and
+    /// the name of the task, if it is spawned with `spawn_named()`. Each
+    /// stalled poll is reported once. Polls are not monitored unless set.
+    pub fn stall_warning<F>(mut self, threshold: Duration, f: F) -> Self
+    where
+        F: Fn(Duration, Option<&str>) + Send + Sync + 'static,
+    {
+        self.hooks.stall = Some((threshold, Arc::new(f)));
+        self
+    }
+
+    /// Round timer deadlines up to multiples of `resolution`.
+    ///
+    /// Coarse resolution makes timers that are due close to each other fire
+    /// together, so the runtime wakes up less often. Defaults to 1ms, which
+    /// is the granularity of the timer wheel. `build_rt()` fails if the
+    /// resolution is below 1ms or above 60s.
+    pub fn timer_resolution(mut self, resolution: Duration) -> Self {
+        self.timer_resolution = resolution;
+        self
+    }
+
+    /// Longest timeout of a timer, further deadlines are clamped to it.
+    ///
+    /// Defaults to the horizon of the timer wheel, about 2 years. `build_rt()`
+    /// fails if the timeout is below the timer resolution or above the default.
+    pub fn max_timeout(mut self, timeout: Duration) -> Self {
+        self.max_timeout = timeout;
+        self
+    }
+
+    /// Set callback that is invoked on the runtime thread once the runtime
+    /// is built, before it runs any future.
+    ///
+    /// Useful for per-thread initialization of a runtime that is built on a
+    /// thread of a framework. Callback is called by the first runtime built
+    /// with this builder only. If it panics, `build_rt()` fails.
+    pub fn on_start<F>(self, f: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        *self.on_start.lock().unwrap() = Some(Box::new(f));
+        self
+    }
+
+    /// Set callback that is invoked on the runtime thread when the runtime
+    /// is dropped, after the `on_drop()` policy got applied.
+    ///
+    /// Callback is called by the first runtime built with this builder only.
+    /// Panic of the callback is logged.
+    pub fn on_stop<F>(self, f: F) -> Self
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        *self.on_stop.lock().unwrap() = Some(Box::new(f));
+        self
+    }
+
+    /// Park the runtime on a custom `Park` implementation instead of the
+    /// reactor.
+    ///
+    /// Park is composed under the timer, the same way the reactor is by
+    /// default, so the runtime blocks in `park` or `park_timeout` whenever
+    /// there are no tasks ready to run. Waking a task from another thread
+    /// calls the park's `Unpark` handle.
+    ///
+    /// Runtime with a custom park has no reactor, I/O objects of tokio-net
+    /// fail to register with it. Park can be used by a single runtime only,
+    /// building a second runtime with the same builder fails.
+    ///
+    /// Event batch size and registration capacity of the tokio-net reactor
+    /// are fixed, a custom park, e.g. over a `mio::Poll`, is the way to tune
+    /// them.
+    pub fn with_park<P>(mut self, park: P) -> Self
+    where
+        P: Park + Send + 'static,
+        P::Error: Into<io::Error>,
+    {
+        self.park = Some(Mutex::new(Some(BoxedPark::new(park))));
+        self
+    }
+
+    /// Build the runtime with or without the reactor.
+    ///
+    /// Runtime without reactor does not hold its file descriptors and blocks
+    /// on a condvar while idle. I/O objects of tokio-net fail to register
+    /// with it and `signal` listeners can not be created. Ignored if the
+    /// runtime parks on `with_park()`.
+    ///
+    /// Defaults to true.
+    pub fn enable_io(mut self, enabled: bool) -> Self {
+        self.enable_io = enabled;
+        self
+    }
+
+    /// Build the runtime with or without the timer.
+    ///
+    /// Functions of `actix_rt::time` panic on a runtime without timer and
+    /// `Runtime::block_on_timeout()` fails with `BlockOnError::NoTimer`.
+    ///
+    /// Defaults to true.
+    pub fn enable_time(mut self, enabled: bool) -> Self {
+        self.enable_time = enabled;
+        self
+    }
+
+    /// Pin the thread that builds the runtime to the core.
+    ///
+    /// Affinity is applied by `build_rt()`, `build()` and `run()` to the
+    /// calling thread. If it can not be applied, e.g. on unsupported
+    /// platforms, a warning is logged and the runtime is built anyway.
+    /// Applied affinity is available from `Runtime::cpu_affinity()`.
+    #[cfg(feature = "core_affinity")]
+    pub fn cpu_affinity(self, core_id: usize) -> Self {
+        self.cpu_affinity_mask(Some(vec![core_id]))
+    }
+
+    /// Pin the thread that builds the runtime to any of the cores, `None`
+    /// leaves the affinity unchanged.
+    ///
+    /// See `Builder::cpu_affinity()`.
+    #[cfg(feature = "core_affinity")]
+    pub fn cpu_affinity_mask(mut self, cores: Option<Vec<usize>>) -> Self {
+        self.cpu_affinity = cores;
+        self
+    }
+
     /// Create new System.
     ///
     /// This method panics if it can not create tokio runtime
@@ -80,18 +469,66 @@ impl Builder {
     /// This function will start tokio runtime and will finish once the
     /// `System::stop()` message get called.
     /// Function `f` get called within tokio runtime context.
-    pub fn run<F>(self, f: F) -> io::Result<()>
+    ///
+    /// Returns the exit code of the system, see `SystemRunner::run()`.
+    pub fn run<F>(self, f: F) -> io::Result<i32>
     where
         F: FnOnce() + 'static,
     {
         self.create_runtime(f).run()
     }
 
+    /// Create a system, run the future on it and tear the system down.
+    ///
+    /// Once the future completes, the system and its arbiters are stopped and
+    /// tasks spawned on the current thread run to completion. Can be called
+    /// repeatedly on the same thread.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the current thread already runs a system or a
+    /// runtime, or if a task panics.
+    pub fn block_on<F: Future>(self, fut: F) -> F::Output {
+        if System::is_set() || runtime::try_current().is_some() {
+            panic!(
+                "System::block_on() can not be called on a thread that already runs \
+                 a system or a runtime, spawn the future with actix_rt::spawn() instead"
+            );
+        }
+
+        let SystemRunner {
+            mut rt,
+            system,
+            _registration: registration,
+            ..
+        } = self.create_runtime(|| {});
+
+        let res = rt.block_on(async move {
+            Arbiter::run_system();
+            fut.await
+        });
+
+        // system tasks complete once stopped, the rest gets drained
+        system.stop();
+        system.arbiter().stop();
+        let drained = rt.run();
+        Arbiter::stop_system();
+        drop(rt);
+        drop(registration);
+
+        match drained {
+            Ok(()) => res,
+            Err(RunError::Panic(err)) => resume_unwind(err),
+            Err(err) => panic!("System::block_on() could not drain spawned tasks: {}", err),
+        }
+    }
+
     fn create_async_runtime(self, executor: Handle) -> AsyncSystemRunner {
         let (stop_tx, stop) = channel();
         let (sys_sender, sys_receiver) = unbounded();
 
-        let system = System::construct(sys_sender, Arbiter::new_system(), self.stop_on_panic);
+        let (system, registration) =
+            System::construct(sys_sender, Arbiter::new_system(), self.stop_on_panic);
 
         // system arbiter
         let arb = SystemArbiter::new(stop_tx, sys_receiver);
@@ -99,7 +536,11 @@ impl Builder {
         // start the system arbiter
         executor.spawn(arb).expect("could not start system arbiter");
 
-        AsyncSystemRunner { stop, system }
+        AsyncSystemRunner {
+            stop,
+            system,
+            registration,
+        }
     }
 
     fn create_runtime<F>(self, f: F) -> SystemRunner
@@ -109,7 +550,8 @@ impl Builder {
         let (stop_tx, stop) = channel();
         let (sys_sender, sys_receiver) = unbounded();
 
-        let system = System::construct(sys_sender, Arbiter::new_system(), self.stop_on_panic);
+        let (system, registration) =
+            System::construct(sys_sender, Arbiter::new_system(), self.stop_on_panic);
 
         // system arbiter
         let arb = SystemArbiter::new(stop_tx, sys_receiver);
@@ -123,30 +565,106 @@ impl Builder {
             Ok::<_, ()>(())
         }));
 
-        SystemRunner { rt, stop, system }
+        SystemRunner {
+            rt,
+            stop,
+            system,
+            _registration: registration,
+        }
     }
 
-    pub(crate) fn build_rt(&self) -> io::Result<Runtime> {
-        // We need a reactor to receive events about IO objects from kernel
-        let reactor = Reactor::new()?;
-        let reactor_handle = reactor.handle();
+    /// Create new single-threaded `Runtime` with the builder's clock.
+    ///
+    /// Runtime is not bound to a System.
+    pub fn build_rt(&self) -> io::Result<Runtime> {
+        // Runtime can pause its clock, the park then stops sleeping for timer deadlines.
+        // Clock stands still until the timer is created, so the wheel starts at the origin.
+        let pausable = PausableClock::new(self.clock.clone());
+        pausable.pause(|now| now);
+        let timer_config =
+            TimerConfig::new(self.timer_resolution, self.max_timeout, pausable.clock().now())?;
+
+        let (mut park, reactor_handle) = match self.park {
+            Some(ref park) => match park.lock().unwrap().take() {
+                Some(park) => (park, None),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::Other,
+                        "custom park is already used by another runtime",
+                    ))
+                }
+            },
+            None if !self.enable_io => (BoxedPark::new(ThreadPark::default()), None),
+            None => {
+                // We need a reactor to receive events about IO objects from kernel.
+                // tokio-net 0.2 alpha fixes the reactor's event batch at 1024 events
+                // per turn and grows its registration slab on demand, neither can be
+                // configured, so the builder has no knobs for them.
+                let reactor = Reactor::new()?;
+                let reactor_handle = reactor.handle();
+                (reactor.into(), Some(reactor_handle))
+            }
+        };
+        park.set_on_turn(self.on_reactor_turn.clone());
+        park.set_utilization(self.utilization.clone());
+        park.set_clock(pausable.clone());
 
         // Place a timer wheel on top of the reactor. If there are no timeouts to fire, it'll let the
         // reactor pick up some new external events.
-        let timer = Timer::new_with_now(reactor, self.clock.clone());
-        let timer_handle = timer.handle();
+        let (parker, timer_handle) = if self.enable_time {
+            let timer = Timer::new_with_now(park, pausable.clock());
+            let timer_handle = timer.handle();
+            (Parker::Timer(timer), Some(timer_handle))
+        } else {
+            (Parker::Base(park), None)
+        };
+        pausable.resume();
 
         // And now put a single-threaded executor on top of the timer. When there are no futures ready
         // to do something, it'll let the timer or the reactor to generate some new stimuli for the
         // futures to continue in their life.
-        let executor = CurrentThread::new_with_park(timer);
+        let executor = CurrentThread::new_with_park(parker);
 
-        Ok(Runtime::new2(
+        let mut rt = Runtime::new2(
             reactor_handle,
             timer_handle,
-            self.clock.clone(),
+            timer_config,
+            pausable,
+            self.test_clock.clone(),
+            self.hooks.clone(),
             executor,
-        ))
+        );
+
+        #[cfg(feature = "core_affinity")]
+        self.apply_cpu_affinity(&mut rt);
+        rt.watch_stalls()?;
+
+        if let Some(on_start) = self.on_start.lock().unwrap().take() {
+            if let Err(err) = catch_unwind(AssertUnwindSafe(on_start)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!(
+                        "Runtime on_start callback panicked: {}",
+                        panic::message(&err)
+                    ),
+                ));
+            }
+        }
+        rt.on_stop = self.on_stop.lock().unwrap().take().map(OnStop);
+        rt.name = self.name.clone();
+
+        Ok(rt)
+    }
+
+    /// Pin the current thread, falls back to unpinned runtime on failure.
+    #[cfg(feature = "core_affinity")]
+    fn apply_cpu_affinity(&self, rt: &mut Runtime) {
+        if let Some(ref cores) = self.cpu_affinity {
+            match affinity::set_for_current(cores) {
+                Ok(()) => rt.cpu_affinity = Some(cores.clone()),
+                Err(e) => log::warn!("Can not set cpu affinity {:?}: {}", cores, e),
+            }
+        }
     }
 }
 
@@ -154,31 +672,23 @@ impl Builder {
 pub(crate) struct AsyncSystemRunner {
     stop: Receiver<i32>,
     system: System,
+    registration: Registration,
 }
 
 impl AsyncSystemRunner {
     /// This function will start event loop and returns a future that
     /// resolves once the `System::stop()` function is called.
-    pub(crate) fn run_nonblocking(self) -> impl Future<Output=Result<(),io::Error>> + Send {
-        let AsyncSystemRunner { stop, .. } = self;
+    pub(crate) fn run_nonblocking(self) -> impl Future<Output=Result<i32,io::Error>> + Send {
+        let AsyncSystemRunner {
+            stop, registration, ..
+        } = self;
 
         // run loop
         async {
             Arbiter::run_system();
             let res = stop.await;
-            let res = match res {
-                Ok(code) => {
-                    if code != 0 {
-                        Err(io::Error::new(
-                            io::ErrorKind::Other,
-                            format!("Non-zero exit code: {}", code),
-                        ))
-                    } else {
-                        Ok(())
-                    }
-                }
-                Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
-            };
+            drop(registration);
+            let res = res.map_err(|e| io::Error::new(io::ErrorKind::Other, e));
             Arbiter::stop_system();
             res
         }
@@ -192,12 +702,19 @@ pub struct SystemRunner {
     rt: Runtime,
     stop: Receiver<i32>,
     system: System,
+    /// Unregisters the system once the runner is done
+    _registration: Registration,
 }
 
 impl SystemRunner {
     /// This function will start event loop and will finish once the
     /// `System::stop()` function is called.
-    pub fn run(self) -> io::Result<()> {
+    ///
+    /// Returns the exit code passed to `System::stop_with_code()`, 0 for
+    /// `System::stop()`. Arbiters of the system are stopped newest first,
+    /// each one exits before the next one is stopped and all of them exit
+    /// before this function returns.
+    pub fn run(self) -> io::Result<i32> {
         let SystemRunner { mut rt, stop, .. } = self;
 
         // run loop
@@ -205,19 +722,9 @@ impl SystemRunner {
             Arbiter::run_system();
             Ok::<_, ()>(())
         }));
-        let result = match rt.block_on(stop) {
-            Ok(code) => {
-                if code != 0 {
-                    Err(io::Error::new(
-                        io::ErrorKind::Other,
-                        format!("Non-zero exit code: {}", code),
-                    ))
-                } else {
-                    Ok(())
-                }
-            }
-            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
-        };
+        let result = rt
+            .block_on(stop)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e));
         Arbiter::stop_system();
         result
     }
@@ -0,0 +1,131 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_timer::clock::{Clock, Now};
+
+use crate::time;
+
+/// Manually advanced clock for deterministic tests.
+///
+/// Time stands still until `TestClock::advance()` or `Runtime::advance()` is
+/// called. Clones share the same time.
+///
+/// Timer of the runtime computes its park timeout from the test clock, but
+/// it still parks the thread for that long in real time and fires only the
+/// timers that are due on the test clock. Future waiting on a timer is
+/// never woken unless the clock gets advanced, so drive such futures with
+/// `Runtime::advance()` instead of `block_on()`.
+#[derive(Debug, Clone)]
+pub struct TestClock(Arc<Mutex<Instant>>);
+
+/// Create test clock that starts at the current instant.
+pub fn test_clock() -> TestClock {
+    TestClock(Arc::new(Mutex::new(Instant::now())))
+}
+
+impl TestClock {
+    /// Current instant of the clock.
+    pub fn now(&self) -> Instant {
+        *self.0.lock().unwrap()
+    }
+
+    /// Move the clock forward.
+    ///
+    /// Timers are not fired until the runtime's timer turns, use
+    /// `Runtime::advance()` to advance the clock and fire due timers.
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+
+    /// `Clock` instance backed by this test clock.
+    pub fn clock(&self) -> Clock {
+        Clock::new_with_now(self.clone())
+    }
+}
+
+impl Now for TestClock {
+    fn now(&self) -> Instant {
+        TestClock::now(self)
+    }
+}
+
+/// Clock of a runtime that can be paused and advanced manually, see
+/// `Runtime::pause_time()`.
+///
+/// Runs along the builder's clock until paused. After resuming it continues
+/// from the instant it was paused at.
+#[derive(Debug, Clone)]
+pub(crate) struct PausableClock(Arc<Mutex<PauseState>>);
+
+#[derive(Debug)]
+struct PauseState {
+    base: Clock,
+    /// Frozen instant while paused
+    paused: Option<Instant>,
+    /// Instant of this clock at `anchor` of the base clock
+    start: Instant,
+    anchor: Instant,
+}
+
+impl PausableClock {
+    pub(crate) fn new(base: Clock) -> Self {
+        let now = base.now();
+        PausableClock(Arc::new(Mutex::new(PauseState {
+            base,
+            paused: None,
+            start: now,
+            anchor: now,
+        })))
+    }
+
+    /// `Clock` instance backed by this clock.
+    pub(crate) fn clock(&self) -> Clock {
+        Clock::new_with_now(self.clone())
+    }
+
+    pub(crate) fn is_paused(&self) -> bool {
+        self.0.lock().unwrap().paused.is_some()
+    }
+
+    /// Freeze the clock at the current instant rounded with `align`.
+    pub(crate) fn pause<F: FnOnce(Instant) -> Instant>(&self, align: F) {
+        let mut state = self.0.lock().unwrap();
+        if state.paused.is_none() {
+            state.paused = Some(align(state.now()));
+        }
+    }
+
+    pub(crate) fn resume(&self) {
+        let mut state = self.0.lock().unwrap();
+        if let Some(paused) = state.paused.take() {
+            state.start = paused;
+            state.anchor = state.base.now();
+        }
+    }
+
+    /// Move the paused clock forward, returns `false` if it is not paused.
+    pub(crate) fn advance(&self, duration: Duration) -> bool {
+        match self.0.lock().unwrap().paused {
+            Some(ref mut paused) => {
+                *paused += duration;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl PauseState {
+    fn now(&self) -> Instant {
+        match self.paused {
+            Some(paused) => paused,
+            None => self.start + time::elapsed(self.base.now(), self.anchor),
+        }
+    }
+}
+
+impl Now for PausableClock {
+    fn now(&self) -> Instant {
+        self.0.lock().unwrap().now()
+    }
+}
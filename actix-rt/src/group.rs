@@ -0,0 +1,153 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+
+use futures::task::AtomicWaker;
+
+use crate::runtime::SpawnErrorKind;
+
+/// Set of related tasks that are cancelled and joined together.
+///
+/// Tasks are spawned with `actix_rt::spawn()` on the current thread. Clones
+/// refer to the same group.
+#[derive(Clone, Default)]
+pub struct TaskGroup(Arc<Shared>);
+
+#[derive(Default)]
+struct Shared {
+    cancelled: AtomicBool,
+    /// Number of tasks that are not dropped yet
+    alive: AtomicUsize,
+    next_id: AtomicUsize,
+    /// Wakers of the live tasks
+    members: Mutex<HashMap<usize, Arc<AtomicWaker>>>,
+    /// Wakers of `join()` futures
+    joins: Mutex<Vec<Waker>>,
+}
+
+impl TaskGroup {
+    /// Create empty group.
+    pub fn new() -> Self {
+        TaskGroup::default()
+    }
+
+    /// Spawn a future on the current thread as a member of the group.
+    ///
+    /// Returns `SpawnErrorKind::Shutdown` if the group is cancelled, or the
+    /// error of `actix_rt::spawn()`.
+    pub fn spawn<F>(&self, future: F) -> Result<(), SpawnErrorKind>
+    where
+        F: Future<Output = ()> + 'static,
+    {
+        if self.is_cancelled() {
+            return Err(SpawnErrorKind::Shutdown);
+        }
+
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let waker = Arc::new(AtomicWaker::new());
+        self.0.members.lock().unwrap().insert(id, waker.clone());
+        self.0.alive.fetch_add(1, Ordering::AcqRel);
+
+        // member that fails to spawn leaves the group on drop
+        crate::spawn(Member {
+            future,
+            id,
+            waker,
+            group: self.0.clone(),
+        })
+    }
+
+    /// Cancel the tasks of the group.
+    ///
+    /// Each task is dropped at its next poll, spawning more tasks fails.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::Release);
+        let members: Vec<_> = self.0.members.lock().unwrap().values().cloned().collect();
+        for waker in members {
+            waker.wake();
+        }
+    }
+
+    /// Returns `true` if the group is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Wait until every task of the group has completed or got dropped.
+    ///
+    /// Resolves immediately if the group has no live tasks.
+    pub fn join(&self) -> impl Future<Output = ()> {
+        Join(self.0.clone())
+    }
+}
+
+impl fmt::Debug for TaskGroup {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaskGroup")
+            .field("cancelled", &self.is_cancelled())
+            .field("alive", &self.0.alive.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+struct Member<F> {
+    future: F,
+    id: usize,
+    waker: Arc<AtomicWaker>,
+    group: Arc<Shared>,
+}
+
+impl<F: Future<Output = ()>> Future for Member<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // safe, `future` is never moved out of pinned member
+        let this = unsafe { self.get_unchecked_mut() };
+
+        // cancelling wakes the task
+        this.waker.register(cx.waker());
+        if this.group.cancelled.load(Ordering::Acquire) {
+            return Poll::Ready(());
+        }
+        unsafe { Pin::new_unchecked(&mut this.future) }.poll(cx)
+    }
+}
+
+impl<F> Drop for Member<F> {
+    fn drop(&mut self) {
+        self.group.members.lock().unwrap().remove(&self.id);
+        if self.group.alive.fetch_sub(1, Ordering::AcqRel) == 1 {
+            for waker in self.group.joins.lock().unwrap().drain(..) {
+                waker.wake();
+            }
+        }
+    }
+}
+
+struct Join(Arc<Shared>);
+
+impl Future for Join {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0.alive.load(Ordering::Acquire) == 0 {
+            return Poll::Ready(());
+        }
+        {
+            let mut joins = self.0.joins.lock().unwrap();
+            if !joins.iter().any(|waker| waker.will_wake(cx.waker())) {
+                joins.push(cx.waker().clone());
+            }
+        }
+        // last task could be dropped before the waker got registered
+        if self.0.alive.load(Ordering::Acquire) == 0 {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
@@ -1,30 +1,86 @@
 //! A runtime implementation that runs everything on the current thread.
 
+#[cfg(feature = "core_affinity")]
+mod affinity;
 mod arbiter;
+#[cfg(feature = "blocking")]
+pub mod blocking;
 mod builder;
+mod clock;
+mod group;
+mod metrics;
+mod panic;
+mod park;
+mod pool;
 mod runtime;
+pub mod signal;
+mod slab;
+mod stall;
+mod stop;
 mod system;
+pub mod task;
+mod tasks;
+pub mod time;
 
-pub use self::arbiter::Arbiter;
+pub use self::arbiter::{Arbiter, ArbiterBuilder};
 pub use self::builder::{Builder, SystemRunner};
-pub use self::runtime::Runtime;
+pub use self::clock::{test_clock, TestClock};
+pub use self::group::TaskGroup;
+pub use self::metrics::RuntimeMetrics;
+pub use self::panic::PanicPolicy;
+pub use self::park::{ReactorStats, TurnStats};
+pub use self::pool::{ArbiterPool, Balance};
+pub use self::runtime::{
+    try_current, BlockOnError, Handle, IdleResult, LocalSpawner, RunError, Runtime,
+    RemoteError, SpawnErrorKind, TurnResult,
+};
+pub use self::stop::{DropPolicy, StopHandle, StopReport};
 pub use self::system::System;
+pub use self::task::{JoinError, JoinHandle, RemoteJoinHandle, TaskInfo, TaskMeta};
+pub use self::tasks::TickBudget;
 
-#[doc(hidden)]
-pub use actix_threadpool as blocking;
+/// Spawns a future on the current arbiter, or on the current runtime if the
+/// thread has no actix system.
+///
+/// Returns `SpawnErrorKind::Shutdown` if called outside of a system and a
+/// runtime context. Spawn on a runtime without a system also fails during a
+/// graceful stop or at capacity.
+pub fn spawn<F>(f: F) -> Result<(), SpawnErrorKind>
+where
+    F: futures::Future<Output = ()> + 'static,
+{
+    if System::is_set() {
+        Arbiter::spawn(f);
+        Ok(())
+    } else {
+        runtime::try_spawn_local(None, f)
+    }
+}
 
-/// Spawns a future on the current arbiter.
+/// Spawns a named future on the current runtime, see `Runtime::dump_tasks()`.
+///
+/// Unlike `spawn()`, requires a runtime context on the current thread and
+/// returns `SpawnErrorKind::Shutdown` otherwise.
+pub fn spawn_named<N, F>(name: N, f: F) -> Result<(), SpawnErrorKind>
+where
+    N: Into<std::sync::Arc<str>>,
+    F: futures::Future<Output = ()> + 'static,
+{
+    runtime::try_spawn_local(Some(name.into()), f)
+}
+
+/// Spawns a future on the current arbiter and returns handle to its output.
 ///
 /// # Panics
 ///
 /// This function panics if actix system is not running.
-pub fn spawn<F>(f: F)
+pub fn spawn_with_handle<F>(f: F) -> JoinHandle<F::Output>
 where
-    F: futures::Future<Output = ()> + 'static,
+    F: futures::Future + 'static,
 {
     if !System::is_set() {
         panic!("System is not running");
     }
 
-    Arbiter::spawn(f);
+    Arbiter::spawn_with_handle(f)
 }
@@ -0,0 +1,97 @@
+use std::fmt;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::tasks::Tasks;
+
+/// Task counters and thread time of a runtime.
+///
+/// Counters are updated by the runtime as tasks run, metrics can be read
+/// from any thread. Only tasks spawned through `Runtime`, `Handle`,
+/// `Arbiter` or `actix_rt::spawn()` are counted.
+#[derive(Clone)]
+pub struct RuntimeMetrics(Arc<Tasks>);
+
+impl RuntimeMetrics {
+    pub(crate) fn new(tasks: Arc<Tasks>) -> Self {
+        RuntimeMetrics(tasks)
+    }
+
+    /// Number of spawned tasks that are not complete yet.
+    pub fn alive_tasks(&self) -> usize {
+        self.0.alive.load(Ordering::Acquire)
+    }
+
+    /// Highest number of spawned tasks that were alive at the same time.
+    pub fn peak_alive_tasks(&self) -> usize {
+        self.0.peak.load(Ordering::Relaxed)
+    }
+
+    /// Total number of spawned tasks.
+    pub fn spawned_tasks(&self) -> usize {
+        self.0.spawned.load(Ordering::Relaxed)
+    }
+
+    /// Total number of completed tasks.
+    ///
+    /// Tasks that panicked are not complete, see `panicked_tasks()`.
+    pub fn completed_tasks(&self) -> usize {
+        self.0.completed.load(Ordering::Relaxed)
+    }
+
+    /// Total number of tasks that panicked, whatever the `PanicPolicy`.
+    pub fn panicked_tasks(&self) -> usize {
+        self.0.panics.load(Ordering::Relaxed)
+    }
+
+    /// Number of timers created with `actix_rt::time` that are not complete
+    /// or dropped yet.
+    ///
+    /// Timers created with tokio-timer directly, e.g. through
+    /// `Runtime::timer_handle()`, are not counted, the timer does not report
+    /// its registrations.
+    pub fn pending_timers(&self) -> usize {
+        self.0.timers.load(Ordering::Relaxed)
+    }
+
+    /// Number of live listeners of `actix_rt::signal`.
+    ///
+    /// Each listener holds its own registration with the reactor until it is
+    /// dropped. IO objects and signal listeners created with tokio-net
+    /// directly are not counted, the reactor does not report its
+    /// registrations.
+    pub fn signal_listeners(&self) -> usize {
+        self.0.signal_listeners.load(Ordering::Relaxed)
+    }
+
+    /// Total time the runtime thread spent running tasks and drivers.
+    ///
+    /// Time between parks counts as busy while the runtime runs, that is
+    /// within `block_on()`, `run()` and similar calls.
+    pub fn busy_time(&self) -> Duration {
+        self.0.busy_time()
+    }
+
+    /// Total time the runtime thread spent parked, waiting for IO events and
+    /// timers.
+    pub fn park_time(&self) -> Duration {
+        self.0.park_time()
+    }
+}
+
+impl fmt::Debug for RuntimeMetrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RuntimeMetrics")
+            .field("alive_tasks", &self.alive_tasks())
+            .field("peak_alive_tasks", &self.peak_alive_tasks())
+            .field("spawned_tasks", &self.spawned_tasks())
+            .field("completed_tasks", &self.completed_tasks())
+            .field("panicked_tasks", &self.panicked_tasks())
+            .field("pending_timers", &self.pending_timers())
+            .field("signal_listeners", &self.signal_listeners())
+            .field("busy_time", &self.busy_time())
+            .field("park_time", &self.park_time())
+            .finish()
+    }
+}
@@ -0,0 +1,68 @@
+use std::any::Any;
+use std::fmt;
+use std::sync::Arc;
+
+/// Handling of panics in tasks spawned on a runtime.
+#[derive(Clone)]
+pub enum PanicPolicy {
+    /// Panic unwinds through the executor and stops the runtime thread,
+    /// `Runtime::run()` returns `RunError::Panic`
+    ///
+    /// Polls are still wrapped in `catch_unwind` with this policy. The
+    /// executor leaks a task that unwinds through it and never gets idle
+    /// again, so the panicked task is completed first and its panic is
+    /// resumed after the current tick. The cost is a landing pad per poll,
+    /// no allocation or synchronization.
+    Propagate,
+    /// Panicked task is dropped and the panic is logged, other tasks keep
+    /// running
+    CatchAndLog,
+    /// Panicked task is dropped and the callback is invoked with the panic
+    /// payload, other tasks keep running
+    CatchAndNotify(Arc<dyn Fn(Box<dyn Any + Send>) + Send + Sync>),
+}
+
+impl Default for PanicPolicy {
+    fn default() -> Self {
+        PanicPolicy::Propagate
+    }
+}
+
+impl PanicPolicy {
+    /// Create `CatchAndNotify` policy.
+    pub fn notify<F>(f: F) -> Self
+    where
+        F: Fn(Box<dyn Any + Send>) + Send + Sync + 'static,
+    {
+        PanicPolicy::CatchAndNotify(Arc::new(f))
+    }
+
+    pub(crate) fn handle(&self, err: Box<dyn Any + Send>) {
+        match self {
+            PanicPolicy::Propagate => std::panic::resume_unwind(err),
+            PanicPolicy::CatchAndLog => log::error!("Task panicked: {}", message(&err)),
+            PanicPolicy::CatchAndNotify(f) => f(err),
+        }
+    }
+}
+
+/// Message of the panic payload.
+pub(crate) fn message(err: &Box<dyn Any + Send>) -> &str {
+    if let Some(msg) = err.downcast_ref::<&str>() {
+        msg
+    } else if let Some(msg) = err.downcast_ref::<String>() {
+        msg.as_str()
+    } else {
+        "Box<Any>"
+    }
+}
+
+impl fmt::Debug for PanicPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PanicPolicy::Propagate => write!(f, "PanicPolicy::Propagate"),
+            PanicPolicy::CatchAndLog => write!(f, "PanicPolicy::CatchAndLog"),
+            PanicPolicy::CatchAndNotify(_) => write!(f, "PanicPolicy::CatchAndNotify"),
+        }
+    }
+}
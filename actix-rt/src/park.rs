@@ -0,0 +1,382 @@
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_executor::park::{Park, Unpark};
+use tokio_net::driver::Reactor;
+use tokio_timer::timer::Timer;
+
+use crate::clock::PausableClock;
+use crate::tasks::Tasks;
+use crate::time;
+
+pub(crate) type TurnHook = Arc<dyn Fn(TurnStats) + Send + Sync>;
+
+/// Callback of `Builder::utilization_callback()`
+pub(crate) type UtilizationHook = Arc<dyn Fn(f64) + Send + Sync>;
+
+/// Statistics of a single reactor turn.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TurnStats {
+    /// Longest time the turn was allowed to block, `None` if unbounded
+    pub max_wait: Option<Duration>,
+    /// Time spent blocked in the reactor's poll
+    pub elapsed: Duration,
+    /// Whether the poll failed
+    pub failed: bool,
+}
+
+/// Snapshot of reactor turn statistics, see `Runtime::reactor_stats()`.
+///
+/// The reactor of tokio-net does not report how many events a turn
+/// dispatched, so turns are described by their duration only.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ReactorStats {
+    /// Number of turns
+    pub turns: u64,
+    /// Total time spent in the reactor's poll
+    pub poll_time: Duration,
+    /// Number of turns that failed
+    pub errors: u64,
+    /// Last turn
+    pub last_turn: Option<TurnStats>,
+}
+
+/// Park of the runtime below the timer, either the reactor or a custom
+/// park installed with `Builder::with_park()`
+pub(crate) struct BoxedPark {
+    park: Box<dyn Park<Unpark = Box<dyn Unpark>, Error = io::Error> + Send>,
+    /// Last park failure, executor discards the error itself
+    error: Option<io::Error>,
+    stats: ReactorStats,
+    on_turn: Option<TurnHook>,
+    /// Timer deadlines are not slept for while the clock is paused
+    clock: Option<PausableClock>,
+    /// Each park raises panics of tasks and starts a new tick
+    tasks: Option<Arc<Tasks>>,
+    /// End of the last park while the runtime is running
+    unparked: Option<Instant>,
+    utilization: Option<Utilization>,
+}
+
+/// Busy and parked time of the current report interval
+struct Utilization {
+    interval: Duration,
+    hook: UtilizationHook,
+    since: Instant,
+    busy: Duration,
+    parked: Duration,
+}
+
+impl BoxedPark {
+    pub(crate) fn new<P>(park: P) -> Self
+    where
+        P: Park + Send + 'static,
+        P::Error: Into<io::Error>,
+    {
+        BoxedPark {
+            park: Box::new(Erased(park)),
+            error: None,
+            stats: ReactorStats::default(),
+            on_turn: None,
+            clock: None,
+            tasks: None,
+            unparked: None,
+            utilization: None,
+        }
+    }
+
+    pub(crate) fn set_on_turn(&mut self, hook: Option<TurnHook>) {
+        self.on_turn = hook;
+    }
+
+    pub(crate) fn set_clock(&mut self, clock: PausableClock) {
+        self.clock = Some(clock);
+    }
+
+    pub(crate) fn set_tasks(&mut self, tasks: Arc<Tasks>) {
+        self.tasks = Some(tasks);
+    }
+
+    pub(crate) fn set_utilization(&mut self, hook: Option<(Duration, UtilizationHook)>) {
+        self.utilization = hook.map(|(interval, hook)| Utilization {
+            interval,
+            hook,
+            since: Instant::now(),
+            busy: Duration::from_millis(0),
+            parked: Duration::from_millis(0),
+        });
+    }
+
+    /// The runtime starts running, time until the next park is busy.
+    pub(crate) fn enter(&mut self) {
+        self.unparked = Some(Instant::now());
+    }
+
+    /// The runtime stops running.
+    pub(crate) fn exit(&mut self) {
+        if self.unparked.is_some() {
+            self.busy_until(Instant::now());
+        }
+    }
+
+    /// Count time since the last park as busy, `now` starts the next park.
+    fn busy_until(&mut self, now: Instant) {
+        if let Some(unparked) = self.unparked.take() {
+            let busy = now - unparked;
+            if let Some(ref tasks) = self.tasks {
+                tasks.add_busy_time(busy);
+            }
+            if let Some(ref mut utilization) = self.utilization {
+                utilization.busy += busy;
+            }
+        }
+    }
+
+    /// Time left until the next utilization report
+    fn report_in(&self, now: Instant) -> Option<Duration> {
+        self.utilization
+            .as_ref()
+            .map(|utilization| time::elapsed(utilization.since + utilization.interval, now))
+    }
+
+    pub(crate) fn stats(&self) -> ReactorStats {
+        self.stats
+    }
+
+    /// Take error of the last failed park.
+    pub(crate) fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    fn stash(&mut self, err: io::Error) -> io::Error {
+        let kind = err.kind();
+        self.error = Some(err);
+        kind.into()
+    }
+
+    /// Update statistics with a finished turn, report utilization once its
+    /// interval elapses
+    fn record(&mut self, max_wait: Option<Duration>, start: Instant, failed: bool) {
+        let end = Instant::now();
+        let turn = TurnStats {
+            max_wait,
+            elapsed: end - start,
+            failed,
+        };
+        self.unparked = Some(end);
+        if let Some(ref tasks) = self.tasks {
+            tasks.add_park_time(turn.elapsed);
+        }
+        if let Some(ref mut utilization) = self.utilization {
+            utilization.parked += turn.elapsed;
+            if end - utilization.since >= utilization.interval {
+                let total = utilization.busy + utilization.parked;
+                let ratio = if total == Duration::from_millis(0) {
+                    0.0
+                } else {
+                    utilization.busy.as_nanos() as f64 / total.as_nanos() as f64
+                };
+                utilization.since = end;
+                utilization.busy = Duration::from_millis(0);
+                utilization.parked = Duration::from_millis(0);
+                (utilization.hook)(ratio);
+            }
+        }
+        self.stats.turns += 1;
+        self.stats.poll_time += turn.elapsed;
+        if failed {
+            self.stats.errors += 1;
+        }
+        self.stats.last_turn = Some(turn);
+        if let Some(ref on_turn) = self.on_turn {
+            on_turn(turn);
+        }
+    }
+}
+
+impl From<Reactor> for BoxedPark {
+    fn from(reactor: Reactor) -> Self {
+        BoxedPark::new(reactor)
+    }
+}
+
+impl Park for BoxedPark {
+    type Unpark = Box<dyn Unpark>;
+    type Error = io::Error;
+
+    fn unpark(&self) -> Self::Unpark {
+        self.park.unpark()
+    }
+
+    fn park(&mut self) -> Result<(), Self::Error> {
+        if let Some(ref tasks) = self.tasks {
+            tasks.resume_panic();
+            tasks.next_tick();
+        }
+        let start = Instant::now();
+        self.busy_until(start);
+        // wake up to report utilization while idle
+        let res = match self.report_in(start) {
+            Some(report_in) => self.park.park_timeout(report_in),
+            None => self.park.park(),
+        };
+        self.record(None, start, res.is_err());
+        res.map_err(|e| self.stash(e))
+    }
+
+    fn park_timeout(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        // timeout of the timer is virtual, wait for IO events and unparks only
+        if duration != Duration::from_millis(0) {
+            if let Some(ref clock) = self.clock {
+                if clock.is_paused() {
+                    return self.park();
+                }
+            }
+        }
+
+        if let Some(ref tasks) = self.tasks {
+            tasks.resume_panic();
+            tasks.next_tick();
+        }
+        let start = Instant::now();
+        self.busy_until(start);
+        let report_in = self.report_in(start).unwrap_or(duration);
+        let res = self.park.park_timeout(duration.min(report_in));
+        self.record(Some(duration), start, res.is_err());
+        res.map_err(|e| self.stash(e))
+    }
+}
+
+impl fmt::Debug for BoxedPark {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BoxedPark")
+            .field("error", &self.error)
+            .field("stats", &self.stats)
+            .finish()
+    }
+}
+
+/// Park of the executor, the timer wheel on top of the base park unless
+/// the runtime is built without timer
+#[derive(Debug)]
+pub(crate) enum Parker {
+    Timer(Timer<BoxedPark>),
+    Base(BoxedPark),
+}
+
+impl Parker {
+    pub(crate) fn get_park(&self) -> &BoxedPark {
+        match self {
+            Parker::Timer(timer) => timer.get_park(),
+            Parker::Base(park) => park,
+        }
+    }
+
+    pub(crate) fn get_park_mut(&mut self) -> &mut BoxedPark {
+        match self {
+            Parker::Timer(timer) => timer.get_park_mut(),
+            Parker::Base(park) => park,
+        }
+    }
+}
+
+impl Park for Parker {
+    type Unpark = Box<dyn Unpark>;
+    type Error = io::Error;
+
+    fn unpark(&self) -> Self::Unpark {
+        match self {
+            Parker::Timer(timer) => timer.unpark(),
+            Parker::Base(park) => park.unpark(),
+        }
+    }
+
+    fn park(&mut self) -> Result<(), Self::Error> {
+        match self {
+            Parker::Timer(timer) => timer.park(),
+            Parker::Base(park) => park.park(),
+        }
+    }
+
+    fn park_timeout(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        match self {
+            Parker::Timer(timer) => timer.park_timeout(duration),
+            Parker::Base(park) => park.park_timeout(duration),
+        }
+    }
+}
+
+/// Park of a runtime without reactor, blocks the thread on a condvar
+#[derive(Debug, Default)]
+pub(crate) struct ThreadPark(ThreadUnpark);
+
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ThreadUnpark(Arc<(Mutex<bool>, Condvar)>);
+
+impl Park for ThreadPark {
+    type Unpark = ThreadUnpark;
+    type Error = io::Error;
+
+    fn unpark(&self) -> Self::Unpark {
+        self.0.clone()
+    }
+
+    fn park(&mut self) -> Result<(), Self::Error> {
+        let (ref notified, ref condvar) = *(self.0).0;
+        let mut notified = notified.lock().unwrap();
+        while !*notified {
+            notified = condvar.wait(notified).unwrap();
+        }
+        *notified = false;
+        Ok(())
+    }
+
+    fn park_timeout(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        let (ref notified, ref condvar) = *(self.0).0;
+        let deadline = Instant::now() + duration;
+        let mut notified = notified.lock().unwrap();
+        while !*notified {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            notified = condvar.wait_timeout(notified, deadline - now).unwrap().0;
+        }
+        *notified = false;
+        Ok(())
+    }
+}
+
+impl Unpark for ThreadUnpark {
+    fn unpark(&self) {
+        let (ref notified, ref condvar) = *self.0;
+        *notified.lock().unwrap() = true;
+        condvar.notify_one();
+    }
+}
+
+/// Adapter that erases unpark and error types of the park
+struct Erased<P>(P);
+
+impl<P> Park for Erased<P>
+where
+    P: Park,
+    P::Error: Into<io::Error>,
+{
+    type Unpark = Box<dyn Unpark>;
+    type Error = io::Error;
+
+    fn unpark(&self) -> Self::Unpark {
+        Box::new(self.0.unpark())
+    }
+
+    fn park(&mut self) -> Result<(), Self::Error> {
+        self.0.park().map_err(Into::into)
+    }
+
+    fn park_timeout(&mut self, duration: Duration) -> Result<(), Self::Error> {
+        self.0.park_timeout(duration).map_err(Into::into)
+    }
+}
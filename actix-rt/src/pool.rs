@@ -0,0 +1,183 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use crate::arbiter::Arbiter;
+
+/// How `ArbiterPool::spawn()` picks an arbiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    /// Arbiters take turns
+    RoundRobin,
+    /// Arbiter with the fewest live pool tasks
+    LeastLoaded,
+}
+
+/// Fixed set of arbiter threads that share spawned futures.
+///
+/// Arbiters belong to the current `System`. Dropping the pool stops them
+/// after the already sent futures are spawned, use `shutdown()` to wait for
+/// the tasks first.
+pub struct ArbiterPool {
+    arbiters: Vec<Arbiter>,
+    load: Arc<Load>,
+    next: AtomicUsize,
+    balance: Balance,
+}
+
+/// Live tasks per arbiter
+struct Load {
+    tasks: Mutex<Vec<usize>>,
+    idle: Condvar,
+}
+
+impl ArbiterPool {
+    /// Start `size` arbiter threads that take turns in running futures.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `size` is zero.
+    pub fn new(size: usize) -> ArbiterPool {
+        assert!(size > 0, "ArbiterPool needs at least one arbiter");
+
+        ArbiterPool {
+            arbiters: (0..size).map(|_| Arbiter::new()).collect(),
+            load: Arc::new(Load {
+                tasks: Mutex::new(vec![0; size]),
+                idle: Condvar::new(),
+            }),
+            next: AtomicUsize::new(0),
+            balance: Balance::RoundRobin,
+        }
+    }
+
+    /// Set how `spawn()` picks an arbiter, defaults to `Balance::RoundRobin`.
+    pub fn balance(mut self, balance: Balance) -> Self {
+        self.balance = balance;
+        self
+    }
+
+    /// Arbiters of the pool, for spawning on a particular one.
+    ///
+    /// Futures sent directly are not counted by `Balance::LeastLoaded` and
+    /// not awaited by `shutdown()`.
+    pub fn handles(&self) -> &[Arbiter] {
+        &self.arbiters
+    }
+
+    /// Number of live tasks spawned with `spawn()`.
+    pub fn pending(&self) -> usize {
+        self.load.tasks.lock().unwrap().iter().sum()
+    }
+
+    /// Send a future to one of the arbiters and spawn it there.
+    pub fn spawn<F>(&self, future: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let idx = {
+            let mut tasks = self.load.tasks.lock().unwrap();
+            let idx = match self.balance {
+                Balance::RoundRobin => {
+                    self.next.fetch_add(1, Ordering::Relaxed) % self.arbiters.len()
+                }
+                Balance::LeastLoaded => (0..tasks.len()).min_by_key(|idx| tasks[*idx]).unwrap(),
+            };
+            tasks[idx] += 1;
+            idx
+        };
+
+        // arbiter drops the task if it is stopped
+        self.arbiters[idx].send(Counted {
+            future,
+            idx,
+            load: self.load.clone(),
+        });
+    }
+
+    /// Run a function on every arbiter of the pool.
+    pub fn broadcast_fn<F>(&self, f: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        let f = Arc::new(f);
+        for arb in &self.arbiters {
+            let f = f.clone();
+            arb.exec_fn(move || f());
+        }
+    }
+
+    /// Wait until the spawned tasks complete or `timeout` elapses, then stop
+    /// the arbiters and join their threads.
+    ///
+    /// Returns number of abandoned tasks.
+    pub fn shutdown(self, timeout: Duration) -> usize {
+        let deadline = Instant::now() + timeout;
+        let abandoned = {
+            let mut tasks = self.load.tasks.lock().unwrap();
+            loop {
+                let pending = tasks.iter().sum();
+                let now = Instant::now();
+                if pending == 0 || now >= deadline {
+                    break pending;
+                }
+                tasks = self
+                    .load
+                    .idle
+                    .wait_timeout(tasks, deadline - now)
+                    .unwrap()
+                    .0;
+            }
+        };
+
+        for arb in &self.arbiters {
+            arb.stop();
+        }
+        for arb in self.arbiters {
+            if arb.join().is_err() {
+                log::error!("Arbiter of the pool panicked");
+            }
+        }
+        abandoned
+    }
+}
+
+impl fmt::Debug for ArbiterPool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArbiterPool")
+            .field("size", &self.arbiters.len())
+            .field("balance", &self.balance)
+            .field("pending", &self.pending())
+            .finish()
+    }
+}
+
+/// Pool task, leaves the load of its arbiter on drop
+struct Counted<F> {
+    future: F,
+    idx: usize,
+    load: Arc<Load>,
+}
+
+impl<F: Future<Output = ()>> Future for Counted<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // safe, `future` is never moved out of pinned task
+        unsafe { self.map_unchecked_mut(|this| &mut this.future) }.poll(cx)
+    }
+}
+
+impl<F> Drop for Counted<F> {
+    fn drop(&mut self) {
+        let mut tasks = self.load.tasks.lock().unwrap();
+        tasks[self.idx] -= 1;
+        if tasks.iter().all(|n| *n == 0) {
+            self.load.idle.notify_all();
+        }
+    }
+}
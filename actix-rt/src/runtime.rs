@@ -1,17 +1,175 @@
+#[cfg(feature = "blocking")]
+use crate::blocking::{self, BlockingError};
+use crate::clock::{PausableClock, TestClock};
+use crate::metrics::RuntimeMetrics;
+use crate::panic;
+use crate::park::{Parker, ReactorStats};
+use crate::slab::{Task, TaskSlab};
+use crate::stall;
+use crate::stop::{DropPolicy, Mode, StopHandle, StopReport};
+use crate::tasks::{self, Hooks, Tasks};
+use crate::time::TimerConfig;
+use crate::task::{self, JoinError, JoinHandle, RemoteJoinHandle, TaskInfo};
 use crate::Builder;
 
+use futures::future;
+use futures::future::{FutureObj, LocalFutureObj};
+#[cfg(feature = "compat")]
+use futures::compat::Future01CompatExt;
+#[cfg(feature = "compat")]
+use futures::FutureExt;
+use futures::task::{waker, ArcWake, LocalSpawn, Spawn, SpawnError};
 use tokio_executor::current_thread::Handle as ExecutorHandle;
 use tokio_executor::current_thread::{self, CurrentThread};
-use tokio_net::driver::{self, Reactor};
+use tokio_executor::park::Park;
+use tokio_net::driver;
 use tokio_timer::clock::{self, Clock};
-use tokio_timer::timer::{self, Timer};
+use tokio_timer::timer;
 
+use std::any::Any;
+use std::borrow::Cow;
+use std::cell::RefCell;
+#[cfg(feature = "blocking")]
+use std::convert::Infallible;
 use std::error::Error;
 use std::fmt;
 use std::future::Future;
 use std::io;
+use std::mem;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
 use tokio_executor as executor;
 
+thread_local!(
+    static CURRENT: RefCell<Option<Current>> = RefCell::new(None);
+);
+
+/// Tasks spawned while the executor of the runtime is not running, spawned
+/// on the executor once the runtime is entered
+#[derive(Default)]
+struct LocalQueue(RefCell<Vec<Pin<Box<dyn Future<Output = ()>>>>>);
+
+impl LocalQueue {
+    fn push(&self, task: Pin<Box<dyn Future<Output = ()>>>) {
+        self.0.borrow_mut().push(task)
+    }
+
+    fn take(&self) -> Vec<Pin<Box<dyn Future<Output = ()>>>> {
+        mem::replace(&mut *self.0.borrow_mut(), Vec::new())
+    }
+}
+
+impl fmt::Debug for LocalQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("LocalQueue")
+            .field("len", &self.0.borrow().len())
+            .finish()
+    }
+}
+
+/// Runtime that is running on the current thread
+#[derive(Clone)]
+pub(crate) struct Current {
+    pub(crate) tasks: Arc<Tasks>,
+    pub(crate) handle: Handle,
+    /// Missing if the runtime is built without timer
+    pub(crate) timer: Option<timer::Handle>,
+    pub(crate) timer_config: TimerConfig,
+    /// Whether I/O objects can register with the runtime
+    pub(crate) reactor: bool,
+    /// Set if the executor is not running, see `Runtime::enter()`
+    deferred: Option<Rc<LocalQueue>>,
+    slab: Rc<TaskSlab>,
+}
+
+impl Current {
+    pub(crate) fn get() -> Option<Current> {
+        CURRENT.with(|cell| cell.borrow().clone())
+    }
+
+    /// Parts of the current runtime that spawns need, cheaper to clone on
+    /// every spawn than the whole context.
+    fn spawner() -> Option<Spawner> {
+        CURRENT.with(|cell| {
+            cell.borrow().as_ref().map(|current| Spawner {
+                tasks: current.tasks.clone(),
+                deferred: current.deferred.clone(),
+                slab: current.slab.clone(),
+            })
+        })
+    }
+}
+
+/// Spawns tasks on the runtime of the current thread.
+struct Spawner {
+    tasks: Arc<Tasks>,
+    deferred: Option<Rc<LocalQueue>>,
+    slab: Rc<TaskSlab>,
+}
+
+impl Spawner {
+    fn spawn(&self, task: Task) {
+        match self.deferred {
+            Some(ref deferred) => deferred.push(task),
+            None => spawn_task(&self.slab, task),
+        }
+    }
+}
+
+/// Spawn a future on the current thread's executor.
+///
+/// Future is tracked by the runtime that is running on the current thread.
+pub(crate) fn spawn_local<F>(future: F)
+where
+    F: Future<Output = ()> + 'static,
+{
+    match Current::spawner() {
+        Some(spawner) => {
+            let task = spawner.tasks.track(future);
+            spawner.spawn(Box::pin(task))
+        }
+        None => current_thread::spawn(tasks::in_current_span(future)),
+    }
+}
+
+/// Spawn a future on the runtime that is running on the current thread.
+///
+/// Fails outside of a runtime context, during a graceful stop or at capacity.
+pub(crate) fn try_spawn_local<F>(
+    name: Option<Arc<str>>,
+    future: F,
+) -> Result<(), SpawnErrorKind>
+where
+    F: Future<Output = ()> + 'static,
+{
+    let spawner = Current::spawner().ok_or(SpawnErrorKind::Shutdown)?;
+    let task = spawner.tasks.try_track_named(name, future)?;
+    spawner.spawn(Box::pin(task));
+    Ok(())
+}
+
+/// Store the task in the slab of the runtime, the executor of the runtime
+/// has to run on the current thread.
+fn spawn_task(slab: &Rc<TaskSlab>, task: Task) {
+    if let Some(driver) = slab.insert(task) {
+        current_thread::spawn(driver);
+    }
+}
+
+/// Get handle of the runtime that is running on the current thread.
+///
+/// Returns `None` outside of a runtime context, e.g. outside of
+/// `Runtime::block_on()`, `Runtime::enter()` or a spawned task.
+pub fn try_current() -> Option<Handle> {
+    Current::get().map(|current| current.handle)
+}
+
 /// Single-threaded runtime provides a way to start reactor
 /// and executor on the current thread.
 ///
@@ -20,30 +178,233 @@ use tokio_executor as executor;
 /// [mod]: index.html
 #[derive(Debug)]
 pub struct Runtime {
-    reactor_handle: driver::Handle,
-    timer_handle: timer::Handle,
+    /// Missing if the runtime parks on a custom park
+    reactor_handle: Option<driver::Handle>,
+    /// Missing if the runtime is built without timer
+    timer_handle: Option<timer::Handle>,
+    timer_config: TimerConfig,
     clock: Clock,
+    pausable: PausableClock,
+    test_clock: Option<TestClock>,
+    tasks: Arc<Tasks>,
+    local: Rc<LocalQueue>,
+    /// Tasks spawned by the runtime, polled by a driver task on the executor
+    slab: Rc<TaskSlab>,
     executor: CurrentThread<Parker>,
+    #[cfg(feature = "core_affinity")]
+    pub(super) cpu_affinity: Option<Vec<usize>>,
+    pub(super) on_stop: Option<OnStop>,
+    /// Name of the builder, used in error messages
+    pub(super) name: Cow<'static, str>,
 }
 
-pub(super) type Parker = Timer<Reactor>;
-
 /// Handle to spawn a future on the corresponding `CurrentThread` runtime instance
 #[derive(Debug, Clone)]
-pub struct Handle(ExecutorHandle);
+pub struct Handle(ExecutorHandle, Arc<Tasks>, Drivers);
+
+/// Reactor and timer of a runtime, missing if the runtime is built without
+/// them
+#[derive(Debug, Clone)]
+struct Drivers {
+    reactor: Option<driver::Handle>,
+    timer: Option<timer::Handle>,
+}
 
 impl Handle {
     /// Spawn a future onto the `CurrentThread` runtime instance corresponding to this handle
     ///
-    /// # Panics
+    /// # Errors
     ///
-    /// This function panics if the spawn fails. Failure occurs if the `CurrentThread`
-    /// instance of the `Handle` does not exist anymore.
-    pub fn spawn<F>(&self, future: F) -> Result<(), tokio_executor::SpawnError>
+    /// Returns `SpawnErrorKind::Shutdown` if the `CurrentThread` instance of the
+    /// `Handle` does not exist anymore, `SpawnErrorKind::AtCapacity` if the runtime
+    /// reached `Builder::max_spawned_tasks()` and `SpawnErrorKind::Stopping` during
+    /// a graceful stop.
+    pub fn spawn<F>(&self, future: F) -> Result<(), SpawnErrorKind>
         where
             F: Future<Output = ()> + Send + 'static,
     {
-        self.0.spawn(future)
+        Ok(self.0.spawn(self.1.try_track(future)?)?)
+    }
+
+    /// Spawn a named future, see `Runtime::dump_tasks()`.
+    pub fn spawn_named<N, F>(&self, name: N, future: F) -> Result<(), SpawnErrorKind>
+        where
+            N: Into<Arc<str>>,
+            F: Future<Output = ()> + Send + 'static,
+    {
+        Ok(self.0.spawn(self.1.try_track_named(Some(name.into()), future)?)?)
+    }
+
+    /// Spawn a future onto the `CurrentThread` runtime instance corresponding
+    /// to this handle and return handle to its output.
+    ///
+    /// Returned handle can be awaited on any thread. It resolves with
+    /// `JoinError::Shutdown` if the runtime shuts down before the future
+    /// completes.
+    pub fn spawn_with_handle<F>(
+        &self,
+        future: F,
+    ) -> Result<RemoteJoinHandle<F::Output>, SpawnErrorKind>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send,
+    {
+        let (task, handle) = task::with_handle(future);
+        self.0.spawn(self.1.try_track(task)?)?;
+        Ok(handle.into())
+    }
+
+    /// Spawn a future that starts once `deadline` elapses and return handle
+    /// to its output.
+    ///
+    /// The future is not polled before the deadline. Aborting the handle
+    /// before then drops the future right away, later it behaves like
+    /// `spawn_with_handle()`.
+    pub fn spawn_at<F>(
+        &self,
+        deadline: Instant,
+        future: F,
+    ) -> Result<RemoteJoinHandle<F::Output>, SpawnErrorKind>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send,
+    {
+        let (task, handle) = task::with_handle_at(deadline, future);
+        self.0.spawn(self.1.try_track(task)?)?;
+        Ok(handle.into())
+    }
+
+    /// Run the future on the runtime and block the current thread until it
+    /// completes.
+    ///
+    /// Allows to call into the runtime from threads that do not run an
+    /// executor, for example from callbacks of a C library. Returns
+    /// `RemoteError::Deadlock` if called on the thread of the runtime,
+    /// which can not make progress while it is blocked.
+    pub fn block_on<F>(&self, future: F) -> Result<F::Output, RemoteError>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send,
+    {
+        self.block_on_until(future, None)
+    }
+
+    /// Same as `block_on()`, but gives up once `timeout` elapses.
+    ///
+    /// Timeout is measured in real time, the future gets aborted on timeout
+    /// and `RemoteError::Elapsed` is returned.
+    pub fn block_on_timeout<F>(
+        &self,
+        future: F,
+        timeout: Duration,
+    ) -> Result<F::Output, RemoteError>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send,
+    {
+        self.block_on_until(future, Some(Instant::now() + timeout))
+    }
+
+    fn block_on_until<F>(
+        &self,
+        future: F,
+        deadline: Option<Instant>,
+    ) -> Result<F::Output, RemoteError>
+        where
+            F: Future + Send + 'static,
+            F::Output: Send,
+    {
+        if self.1.is_runtime_thread() {
+            return Err(RemoteError::Deadlock);
+        }
+        let mut handle = self.spawn_with_handle(future).map_err(RemoteError::Spawn)?;
+
+        let waker = waker(Arc::new(Unparker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(res) = Pin::new(&mut handle).poll(&mut cx) {
+                return res.map_err(RemoteError::Join);
+            }
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        handle.abort();
+                        return Err(RemoteError::Elapsed);
+                    }
+                    thread::park_timeout(deadline - now);
+                }
+                None => thread::park(),
+            }
+        }
+    }
+
+    /// Run the closure on the runtime thread and spawn the future it returns
+    /// there.
+    ///
+    /// Unlike `spawn`, the future does not have to be `Send`, so it can use
+    /// `Rc` based state of the runtime thread. Returns `SpawnErrorKind` if
+    /// the runtime is shut down or at capacity.
+    pub fn run_fn<F, Fut>(&self, f: F) -> Result<(), SpawnErrorKind>
+        where
+            F: FnOnce() -> Fut + Send + 'static,
+            Fut: Future<Output = ()> + 'static,
+    {
+        self.1.check_spawn()?;
+        // only the spawned future is tracked by the runtime
+        Ok(self.0.spawn(future::lazy(move |_| spawn_local(f())))?)
+    }
+
+    /// Execute blocking function on the shared thread pool.
+    ///
+    /// See `blocking::spawn_blocking()` for details.
+    #[cfg(feature = "blocking")]
+    pub fn spawn_blocking<F, R>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<R, BlockingError<Infallible>>>
+        where
+            F: FnOnce() -> R + Send + 'static,
+            R: Send + 'static,
+    {
+        blocking::spawn_blocking(f)
+    }
+
+    /// Get task metrics of the runtime instance corresponding to this handle.
+    pub fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics::new(self.1.clone())
+    }
+
+    /// Number of pending `actix_rt::time` timers of the runtime, see
+    /// `Runtime::pending_timers()`.
+    pub fn pending_timers(&self) -> usize {
+        self.1.timers.load(Ordering::Relaxed)
+    }
+
+    /// Number of `actix_rt::signal` listeners of the runtime, see
+    /// `Runtime::signal_listeners()`.
+    pub fn signal_listeners(&self) -> usize {
+        self.1.signal_listeners.load(Ordering::Relaxed)
+    }
+
+    /// Get handle of the runtime's reactor, see `Runtime::reactor_handle()`.
+    pub fn reactor_handle(&self) -> Option<driver::Handle> {
+        self.2.reactor.clone()
+    }
+
+    /// Get handle of the runtime's timer, see `Runtime::timer_handle()`.
+    pub fn timer_handle(&self) -> Option<timer::Handle> {
+        self.2.timer.clone()
+    }
+
+    /// Get live named tasks of the runtime, see `Runtime::dump_tasks()`.
+    pub fn dump_tasks(&self) -> Vec<TaskInfo> {
+        self.1.dump()
+    }
+
+    /// Log live tasks of the runtime, see `Runtime::log_pending_tasks()`.
+    pub fn log_pending_tasks(&self) {
+        self.1.log_pending()
     }
 
     /// Provides a best effort **hint** to whether or not `spawn` will succeed.
@@ -55,8 +416,15 @@ impl Handle {
     ///
     /// This allows a caller to avoid creating the task if the call to `spawn`
     /// has a high likelihood of failing.
-    pub fn status(&self) -> Result<(), tokio_executor::SpawnError> {
-        self.0.status()
+    ///
+    /// Returns `SpawnErrorKind::Stopping` while the runtime is being stopped
+    /// with a `StopHandle`.
+    pub fn status(&self) -> Result<(), SpawnErrorKind> {
+        self.0.status()?;
+        if self.1.mode() != Mode::Running {
+            return Err(SpawnErrorKind::Stopping);
+        }
+        self.1.check_spawn()
     }
 }
 
@@ -65,28 +433,274 @@ impl<T> executor::TypedExecutor<T> for Handle
         T: Future<Output = ()> + Send + 'static,
 {
     fn spawn(&mut self, future: T) -> Result<(), executor::SpawnError> {
-        Handle::spawn(self, future)
+        Ok(Handle::spawn(self, future)?)
+    }
+}
+
+#[cfg(feature = "compat")]
+impl<F> futures01::future::Executor<F> for Handle
+    where
+        F: futures01::Future<Item = (), Error = ()> + Send + 'static,
+{
+    fn execute(&self, future: F) -> Result<(), futures01::future::ExecuteError<F>> {
+        use futures01::future::{lazy, ExecuteError, ExecuteErrorKind};
+        use std::sync::Mutex;
+
+        // a refused task is dropped unpolled, so the future is still in the slot
+        let slot = Arc::new(Mutex::new(Some(future)));
+        let task = {
+            let slot = slot.clone();
+            lazy(move || slot.lock().unwrap().take().unwrap())
+        };
+        Handle::spawn(self, task.compat().map(|_| ())).map_err(|kind| {
+            let kind = match kind {
+                SpawnErrorKind::AtCapacity { .. } => ExecuteErrorKind::NoCapacity,
+                _ => ExecuteErrorKind::Shutdown,
+            };
+            ExecuteError::new(kind, slot.lock().unwrap().take().unwrap())
+        })
+    }
+}
+
+/// `SpawnError` of futures can only tell that the executor is shut down, so
+/// every `SpawnErrorKind` is reported as `SpawnError::shutdown()` and the
+/// actual kind is lost. Failed spawns log it, use `Handle::spawn()` to get it.
+impl Spawn for Handle {
+    fn spawn_obj(&mut self, future: FutureObj<'static, ()>) -> Result<(), SpawnError> {
+        Handle::spawn(self, future).map_err(spawn_error)
+    }
+
+    fn status(&self) -> Result<(), SpawnError> {
+        Handle::status(self).map_err(|_| SpawnError::shutdown())
+    }
+}
+
+/// Spawner of `!Send` futures on the runtime, created with
+/// `Runtime::local_spawner()`.
+///
+/// Futures spawned while the runtime is not running are queued and first
+/// polled by the next `block_on` or `run`. As for `Handle`, every
+/// `SpawnErrorKind` is reported as `SpawnError::shutdown()`.
+#[derive(Debug, Clone)]
+pub struct LocalSpawner {
+    local: Weak<LocalQueue>,
+    tasks: Arc<Tasks>,
+}
+
+impl LocalSpawn for LocalSpawner {
+    fn spawn_local_obj(&mut self, future: LocalFutureObj<'static, ()>) -> Result<(), SpawnError> {
+        let local = self.local.upgrade().ok_or_else(SpawnError::shutdown)?;
+        let task = self.tasks.try_track(future).map_err(spawn_error)?;
+
+        match Current::get() {
+            // executor of the runtime is running on this thread
+            Some(ref current) if current.deferred.is_none() && Arc::ptr_eq(&current.tasks, &self.tasks) => {
+                spawn_task(&current.slab, Box::pin(task))
+            }
+            _ => local.push(Box::pin(task)),
+        }
+        Ok(())
+    }
+
+    fn status_local(&self) -> Result<(), SpawnError> {
+        if self.local.upgrade().is_none() {
+            return Err(SpawnError::shutdown());
+        }
+        self.tasks
+            .check_spawn()
+            .map_err(|_| SpawnError::shutdown())
     }
 }
 
-/// Error returned by the `run` function.
+/// `SpawnError` has no kinds besides shutdown, log the actual one.
+fn spawn_error(kind: SpawnErrorKind) -> SpawnError {
+    log::debug!("Spawn failed: {}", kind);
+    SpawnError::shutdown()
+}
+
+/// Error returned by the `run` and `try_block_on` functions.
 #[derive(Debug)]
-pub struct RunError {
-    inner: current_thread::RunError,
+pub enum RunError {
+    /// Task panicked, contains the panic payload
+    Panic(Box<dyn Any + Send + 'static>),
+    /// Executor shut down and can not drive tasks anymore
+    Shutdown,
+    /// Reactor or custom park failed
+    Io(io::Error),
+    /// Called from within a running executor on the current thread
+    Nested,
+}
+
+impl RunError {
+    /// Message of the panic, if the error is `RunError::Panic`.
+    pub fn panic_message(&self) -> Option<&str> {
+        match self {
+            RunError::Panic(err) => Some(panic::message(err)),
+            _ => None,
+        }
+    }
 }
 
 impl fmt::Display for RunError {
     fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(fmt, "{}", self.inner)
+        match self {
+            RunError::Panic(err) => write!(fmt, "task panicked: {}", panic::message(err)),
+            RunError::Shutdown => write!(fmt, "executor shut down"),
+            RunError::Io(err) => write!(fmt, "park failed: {}", err),
+            RunError::Nested => write!(fmt, "executor is already running on this thread"),
+        }
     }
 }
 
 impl Error for RunError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
-        self.inner.source()
+        match self {
+            RunError::Io(err) => Some(err),
+            RunError::Panic(_) | RunError::Shutdown | RunError::Nested => None,
+        }
+    }
+}
+
+/// Wakes the thread that is blocked in `Handle::block_on()`
+struct Unparker(thread::Thread);
+
+impl ArcWake for Unparker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.0.unpark();
+    }
+}
+
+/// Error returned by `Handle::block_on()`.
+#[derive(Debug)]
+pub enum RemoteError {
+    /// Called on the thread of the runtime
+    Deadlock,
+    /// Future could not be spawned
+    Spawn(SpawnErrorKind),
+    /// Task panicked, got aborted or the runtime shut down
+    Join(JoinError),
+    /// Timeout of `block_on_timeout()` elapsed, the task got aborted
+    Elapsed,
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::Deadlock => write!(fmt, "can not block the thread of the runtime"),
+            RemoteError::Spawn(kind) => write!(fmt, "can not spawn the future: {}", kind),
+            RemoteError::Join(err) => err.fmt(fmt),
+            RemoteError::Elapsed => write!(fmt, "deadline has elapsed"),
+        }
+    }
+}
+
+impl Error for RemoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RemoteError::Spawn(kind) => Some(kind),
+            RemoteError::Join(err) => Some(err),
+            RemoteError::Deadlock | RemoteError::Elapsed => None,
+        }
+    }
+}
+
+/// Error returned by the `block_on_timeout` function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockOnError {
+    /// Future did not complete in time and got dropped
+    Elapsed,
+    /// Called from within a running executor on the current thread
+    Nested,
+    /// Runtime is built without timer, see `Builder::enable_time()`
+    NoTimer,
+}
+
+impl fmt::Display for BlockOnError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockOnError::Elapsed => write!(fmt, "deadline has elapsed"),
+            BlockOnError::Nested => write!(fmt, "executor is already running on this thread"),
+            BlockOnError::NoTimer => write!(fmt, "timer is disabled on this runtime"),
+        }
+    }
+}
+
+impl Error for BlockOnError {}
+
+/// Reason of a failed spawn or a failed `Handle::status()` check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnErrorKind {
+    /// Runtime does not exist anymore
+    Shutdown,
+    /// Runtime does not accept more tasks, contains the task limit if it
+    /// is set with `Builder::max_spawned_tasks()`
+    AtCapacity {
+        /// Limit of live tasks
+        limit: Option<usize>,
+    },
+    /// Runtime is being stopped. Fallible spawns fail with it during a
+    /// graceful stop, `Handle::status()` returns it during any stop
+    Stopping,
+}
+
+impl fmt::Display for SpawnErrorKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnErrorKind::Shutdown => write!(fmt, "runtime is shut down"),
+            SpawnErrorKind::AtCapacity { limit: Some(limit) } => {
+                write!(fmt, "runtime is at capacity of {} tasks", limit)
+            }
+            SpawnErrorKind::AtCapacity { limit: None } => write!(fmt, "runtime is at capacity"),
+            SpawnErrorKind::Stopping => write!(fmt, "runtime is stopping"),
+        }
     }
 }
 
+impl Error for SpawnErrorKind {}
+
+impl From<executor::SpawnError> for SpawnErrorKind {
+    fn from(err: executor::SpawnError) -> Self {
+        if err.is_at_capacity() {
+            SpawnErrorKind::AtCapacity { limit: None }
+        } else {
+            SpawnErrorKind::Shutdown
+        }
+    }
+}
+
+impl From<SpawnErrorKind> for executor::SpawnError {
+    fn from(kind: SpawnErrorKind) -> Self {
+        match kind {
+            SpawnErrorKind::AtCapacity { .. } => executor::SpawnError::at_capacity(),
+            SpawnErrorKind::Shutdown | SpawnErrorKind::Stopping => {
+                executor::SpawnError::shutdown()
+            }
+        }
+    }
+}
+
+impl From<SpawnErrorKind> for io::Error {
+    fn from(kind: SpawnErrorKind) -> Self {
+        io::Error::new(io::ErrorKind::Other, kind)
+    }
+}
+
+/// Result of the `run_until_idle` function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IdleResult {
+    /// Number of spawned tasks that are not complete yet
+    pub pending: usize,
+}
+
+/// Result of the `turn` function.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TurnResult {
+    /// Whether any task got polled
+    pub polled: bool,
+    /// Number of spawned tasks that are not complete yet
+    pub pending: usize,
+}
+
 impl Runtime {
     /// Returns a new runtime initialized with default configuration values.
     pub fn new() -> io::Result<Runtime> {
@@ -94,16 +708,34 @@ impl Runtime {
     }
 
     pub(super) fn new2(
-        reactor_handle: driver::Handle,
-        timer_handle: timer::Handle,
-        clock: Clock,
-        executor: CurrentThread<Parker>,
+        reactor_handle: Option<driver::Handle>,
+        timer_handle: Option<timer::Handle>,
+        timer_config: TimerConfig,
+        pausable: PausableClock,
+        test_clock: Option<TestClock>,
+        hooks: Hooks,
+        mut executor: CurrentThread<Parker>,
     ) -> Runtime {
+        let unpark = Box::new(executor.get_park().unpark());
+        let capacity = hooks.task_slab_capacity;
+        let tasks = Arc::new(Tasks::new(unpark, hooks));
+        let slab = Rc::new(TaskSlab::new(capacity, tasks.clone()));
+        executor.get_park_mut().get_park_mut().set_tasks(tasks.clone());
         Runtime {
             reactor_handle,
             timer_handle,
-            clock,
+            timer_config,
+            clock: pausable.clock(),
+            pausable,
+            test_clock,
+            tasks,
+            local: Rc::default(),
+            slab,
             executor,
+            #[cfg(feature = "core_affinity")]
+            cpu_affinity: None,
+            on_stop: None,
+            name: Cow::Borrowed("actix"),
         }
     }
 
@@ -112,7 +744,128 @@ impl Runtime {
     /// Different to the runtime itself, the handle can be sent to different
     /// threads.
     pub fn handle(&self) -> Handle {
-        Handle(self.executor.handle().clone())
+        Handle(
+            self.executor.handle().clone(),
+            self.tasks.clone(),
+            self.drivers(),
+        )
+    }
+
+    fn drivers(&self) -> Drivers {
+        Drivers {
+            reactor: self.reactor_handle.clone(),
+            timer: self.timer_handle.clone(),
+        }
+    }
+
+    /// Get a handle that stops `run()` even if spawned futures remain.
+    ///
+    /// Handle is cheap to clone and can be sent to different threads.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(self.tasks.clone())
+    }
+
+    /// Get task metrics of the runtime.
+    pub fn metrics(&self) -> RuntimeMetrics {
+        RuntimeMetrics::new(self.tasks.clone())
+    }
+
+    /// Number of timers created with `actix_rt::time` that are not complete
+    /// or dropped yet, see `RuntimeMetrics::pending_timers()`.
+    pub fn pending_timers(&self) -> usize {
+        self.tasks.timers.load(Ordering::Relaxed)
+    }
+
+    /// Number of live listeners of `actix_rt::signal`, see
+    /// `RuntimeMetrics::signal_listeners()`.
+    pub fn signal_listeners(&self) -> usize {
+        self.tasks.signal_listeners.load(Ordering::Relaxed)
+    }
+
+    /// Get live named tasks, ordered by id.
+    ///
+    /// Tasks spawned with `spawn_named()` are listed, unnamed tasks are only
+    /// counted by `metrics()` to keep their overhead low.
+    pub fn dump_tasks(&self) -> Vec<TaskInfo> {
+        self.tasks.dump()
+    }
+
+    /// Log live named tasks and the number of unnamed ones with `log::warn!`.
+    ///
+    /// Use `Handle::log_pending_tasks()` from other threads, e.g. from a
+    /// signal handler.
+    pub fn log_pending_tasks(&self) {
+        self.tasks.log_pending()
+    }
+
+    /// Get spawner of `!Send` futures that implements `LocalSpawn`.
+    ///
+    /// Spawner can be used on the runtime thread only, it fails with
+    /// `SpawnError` once the runtime is dropped.
+    pub fn local_spawner(&self) -> LocalSpawner {
+        LocalSpawner {
+            local: Rc::downgrade(&self.local),
+            tasks: self.tasks.clone(),
+        }
+    }
+
+    /// Start monitor of stalled polls, if configured.
+    pub(super) fn watch_stalls(&self) -> io::Result<()> {
+        match self.tasks.heartbeat {
+            Some(ref heartbeat) => stall::watch(heartbeat),
+            None => Ok(()),
+        }
+    }
+
+    /// Get statistics of the reactor turns, including turns of a custom park.
+    pub fn reactor_stats(&self) -> ReactorStats {
+        self.executor.get_park().get_park().stats()
+    }
+
+    /// Number of task nodes allocated by the runtime, see
+    /// `Builder::task_slab_capacity()`.
+    ///
+    /// Nodes of completed tasks are reused, so the number only grows with
+    /// the highest number of tasks that were alive at the same time.
+    pub fn task_slab_size(&self) -> usize {
+        self.slab.len()
+    }
+
+    /// Get handle of the runtime's reactor, e.g. to register raw `mio`
+    /// sources.
+    ///
+    /// I/O resources registered through the handle are driven and woken by
+    /// this runtime, registrations fail once the runtime is dropped. The
+    /// handle can be sent to other threads. Returns `None` if the runtime has
+    /// no reactor, see `Builder::enable_io()` and `Builder::with_park()`.
+    pub fn reactor_handle(&self) -> Option<driver::Handle> {
+        self.reactor_handle.clone()
+    }
+
+    /// Get handle of the runtime's timer, e.g. for timers of other crates.
+    ///
+    /// Timers created through the handle fire on this runtime and fail once
+    /// the runtime is dropped. Unlike timers of `actix_rt::time` they are not
+    /// rounded to the timer resolution and not counted by `pending_timers()`.
+    /// Returns `None` if the runtime is built without timer, see
+    /// `Builder::enable_time()`.
+    pub fn timer_handle(&self) -> Option<timer::Handle> {
+        self.timer_handle.clone()
+    }
+
+    /// Resolution of the runtime's timer, see `Builder::timer_resolution()`.
+    ///
+    /// Deadlines of `actix_rt::time` timers are rounded up to it.
+    pub fn timer_resolution(&self) -> Duration {
+        self.timer_config.resolution
+    }
+
+    /// Cores the runtime thread got pinned to with `Builder::cpu_affinity()`.
+    ///
+    /// Returns `None` if affinity is not configured or could not be applied.
+    #[cfg(feature = "core_affinity")]
+    pub fn cpu_affinity(&self) -> Option<&[usize]> {
+        self.cpu_affinity.as_ref().map(|cores| &**cores)
     }
 
     /// Spawn a future onto the single-threaded Tokio runtime.
@@ -145,10 +898,112 @@ impl Runtime {
         where
             F: Future<Output = ()> + 'static,
     {
-        self.executor.spawn(future);
+        let task = self.tasks.track(future);
+        self.spawn_task(Box::pin(task));
+        self
+    }
+
+    /// Spawn a named future, the task is listed by `dump_tasks()`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the runtime is at capacity.
+    pub fn spawn_named<N, F>(&mut self, name: N, future: F) -> &mut Self
+        where
+            N: Into<Arc<str>>,
+            F: Future<Output = ()> + 'static,
+    {
+        let task = self.tasks.track_named(Some(name.into()), future);
+        self.spawn_task(Box::pin(task));
         self
     }
 
+    /// Spawn a future onto the single-threaded runtime.
+    ///
+    /// Returns `SpawnErrorKind::AtCapacity` if the runtime reached
+    /// `Builder::max_spawned_tasks()` and `SpawnErrorKind::Stopping` during a
+    /// graceful stop.
+    pub fn try_spawn<F>(&mut self, future: F) -> Result<(), SpawnErrorKind>
+        where
+            F: Future<Output = ()> + 'static,
+    {
+        let task = self.tasks.try_track(future)?;
+        self.spawn_task(Box::pin(task));
+        Ok(())
+    }
+
+    /// Spawn a futures 0.1 future onto the single-threaded runtime.
+    ///
+    /// The future is driven through the futures compat layer, its task
+    /// notifications wake the runtime like any other task.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the runtime is at capacity.
+    #[cfg(feature = "compat")]
+    pub fn spawn_01<F>(&mut self, future: F) -> &mut Self
+        where
+            F: futures01::Future<Item = (), Error = ()> + 'static,
+    {
+        self.spawn(future.compat().map(|_| ()))
+    }
+
+    /// Spawn a future onto the single-threaded runtime and return handle to
+    /// its output.
+    ///
+    /// Handle resolves with `JoinError::Panic` if the future panics, with
+    /// `JoinError::Cancelled` if it gets aborted and with `JoinError::Shutdown`
+    /// if the runtime is dropped before the future completes.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the spawn fails. Failure occurs if the executor
+    /// is currently at capacity and is unable to spawn a new future.
+    pub fn spawn_with_handle<F>(&mut self, future: F) -> JoinHandle<F::Output>
+        where
+            F: Future + 'static,
+    {
+        let (task, handle) = task::with_handle(future);
+        let task = self.tasks.track(task);
+        self.spawn_task(Box::pin(task));
+        handle
+    }
+
+    /// Spawn a future that starts after `delay` and return handle to its
+    /// output.
+    ///
+    /// Delay is measured by the runtime's clock from now on. The future is not
+    /// polled before it elapses, aborting the handle meanwhile drops the future
+    /// right away. Started future gets aborted like with `spawn_with_handle()`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the runtime is at capacity.
+    pub fn spawn_after<F>(&mut self, delay: Duration, future: F) -> JoinHandle<F::Output>
+        where
+            F: Future + 'static,
+    {
+        let (task, handle) = task::with_handle_at(self.clock.now() + delay, future);
+        let task = self.tasks.track(task);
+        self.spawn_task(Box::pin(task));
+        handle
+    }
+
+    /// Execute blocking function on the shared thread pool.
+    ///
+    /// See `blocking::spawn_blocking()` for details.
+    #[cfg(feature = "blocking")]
+    pub fn spawn_blocking<F, R>(
+        &self,
+        f: F,
+    ) -> impl Future<Output = Result<R, BlockingError<Infallible>>>
+        where
+            F: FnOnce() -> R + Send + 'static,
+            R: Send + 'static,
+    {
+        blocking::spawn_blocking(f)
+    }
+
     /// Runs the provided future, blocking the current thread until the future
     /// completes.
     ///
@@ -165,40 +1020,427 @@ impl Runtime {
     ///
     /// The caller is responsible for ensuring that other spawned futures
     /// complete execution by calling `block_on` or `run`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if called while an executor is already running
+    /// on the current thread, for example from a task. Spawn the future and
+    /// await its `JoinHandle` instead.
     pub fn block_on<F>(&mut self, f: F) -> F::Output
         where
             F: Future,
     {
-        self.enter(|executor| {
+        let f = self.tasks.root(f);
+        self.enter_context(true, |executor| {
             // Run the provided future
             executor.block_on(f)
         })
     }
 
+    /// Runs the provided future like `block_on`, but returns `RunError`
+    /// instead of panicking if the future or a spawned task panics, if the
+    /// runtime fails to park or if the call is nested.
+    pub fn try_block_on<F>(&mut self, f: F) -> Result<F::Output, RunError>
+        where
+            F: Future,
+    {
+        if is_nested() {
+            return Err(RunError::Nested);
+        }
+        catch_unwind(AssertUnwindSafe(|| self.block_on(f))).map_err(|err| {
+            match self.take_park_error() {
+                Some(e) => RunError::Io(e),
+                None => RunError::Panic(err),
+            }
+        })
+    }
+
+    /// Runs the provided future like `block_on`, but returns the panic
+    /// payload instead of unwinding if the future or a spawned task panics.
+    ///
+    /// The future is polled within `AssertUnwindSafe`, after a panic state
+    /// it shares with the caller may be left inconsistent. The runtime stays
+    /// usable, other spawned tasks remain and run on the next `block_on` or
+    /// `run`. Nested calls fail with the panic of `block_on`.
+    pub fn block_on_catch<F>(&mut self, f: F) -> Result<F::Output, Box<dyn Any + Send>>
+        where
+            F: Future,
+    {
+        catch_unwind(AssertUnwindSafe(|| self.block_on(f)))
+    }
+
+    /// Runs the provided future like `block_on`, but gives up once `timeout`
+    /// elapses.
+    ///
+    /// Timeout is tracked by the runtime's own timer and clock. On timeout the
+    /// future is dropped before this function returns.
+    ///
+    /// Returns `BlockOnError::Nested` if called while an executor is already
+    /// running on the current thread, for example from inside of `block_on`,
+    /// and `BlockOnError::NoTimer` if the runtime is built without timer.
+    pub fn block_on_timeout<F>(
+        &mut self,
+        f: F,
+        timeout: Duration,
+    ) -> Result<F::Output, BlockOnError>
+        where
+            F: Future,
+    {
+        if is_nested() {
+            return Err(BlockOnError::Nested);
+        }
+
+        let timer = match self.timer_handle {
+            Some(ref timer) => timer.clone(),
+            None => return Err(BlockOnError::NoTimer),
+        };
+        let f = self.tasks.root(f);
+        self.enter_context(true, move |executor| {
+            // deadline uses the runtime's clock
+            executor.block_on(timer.timeout(f, timeout))
+        })
+        .map_err(|_| BlockOnError::Elapsed)
+    }
+
     /// Run the executor to completion, blocking the thread until **all**
     /// spawned futures have completed.
+    ///
+    /// Returns early if the runtime gets stopped with a `StopHandle`. Panics
+    /// of spawned tasks are returned as `RunError::Panic`, unless caught by
+    /// the task panic policy.
     pub fn run(&mut self) -> Result<(), RunError> {
-        self.enter(|executor| executor.run())
-            .map_err(|e| RunError { inner: e })
+        if is_nested() {
+            return Err(RunError::Nested);
+        }
+        self.run_loop(None).0
+    }
+
+    /// Stop the runtime gracefully and run it until the remaining tasks
+    /// complete or `timeout` elapses.
+    ///
+    /// Fallible spawns fail with `SpawnErrorKind::Stopping` meanwhile, so
+    /// accept loops can wind down. Report tells whether the stop was forced
+    /// by the timeout and how many tasks are abandoned.
+    pub fn run_graceful(&mut self, timeout: Duration) -> Result<StopReport, RunError> {
+        if is_nested() {
+            return Err(RunError::Nested);
+        }
+        self.tasks.request(Mode::Graceful(timeout));
+        let (res, report) = self.run_loop(None);
+        res.map(|_| report)
+    }
+
+    /// Runs the provided future like `try_block_on`, then runs the executor
+    /// until all spawned futures complete or `timeout` elapses.
+    ///
+    /// Returns output of the future and the number of spawned futures that
+    /// completed after it. Draining stops early if the runtime gets stopped
+    /// with a `StopHandle`, unfinished futures remain pending in the runtime.
+    pub fn block_on_all<F>(
+        &mut self,
+        f: F,
+        timeout: Option<Duration>,
+    ) -> Result<(F::Output, usize), RunError>
+        where
+            F: Future,
+    {
+        let output = self.try_block_on(f)?;
+        let completed = self.tasks.completed.load(Ordering::Acquire);
+        self.run_loop(timeout).0?;
+        let drained = self.tasks.completed.load(Ordering::Acquire) - completed;
+        Ok((output, drained))
+    }
+
+    /// Run the executor until it is idle or stopped, or until `limit`
+    /// elapses unless a graceful stop sets its own deadline.
+    fn run_loop(&mut self, limit: Option<Duration>) -> (Result<(), RunError>, StopReport) {
+        let tasks = self.tasks.clone();
+        let clock = self.clock.clone();
+        let limit = limit.map(|limit| clock.now() + limit);
+        let mut deadline = None;
+
+        // panics of tasks unwind through the executor
+        let res: thread::Result<Result<(), current_thread::TurnError>> =
+            catch_unwind(AssertUnwindSafe(|| {
+                self.enter_context(true, |executor| loop {
+                    let timeout = match tasks.mode() {
+                        Mode::Running => match limit {
+                            Some(limit) => {
+                                let now = clock.now();
+                                if now >= limit {
+                                    return Ok(());
+                                }
+                                Some(limit - now)
+                            }
+                            None => None,
+                        },
+                        Mode::Stop => return Ok(()),
+                        Mode::Graceful(timeout) => {
+                            let deadline = *deadline.get_or_insert_with(|| clock.now() + timeout);
+                            let now = clock.now();
+                            if tasks.pending() == 0 || now >= deadline {
+                                return Ok(());
+                            }
+                            Some(deadline - now)
+                        }
+                    };
+                    if executor.is_idle() {
+                        return Ok(());
+                    }
+                    executor.turn(timeout)?;
+                    tasks.resume_panic();
+                })
+            }));
+        let report = tasks.finish();
+        let res = match res {
+            Ok(Ok(())) => Ok(()),
+            Ok(Err(_)) => match self.take_park_error() {
+                Some(e) => Err(RunError::Io(e)),
+                None => Err(RunError::Shutdown),
+            },
+            Err(err) => Err(RunError::Panic(err)),
+        };
+        (res, report)
+    }
+
+    /// Run a single iteration of the event loop.
+    ///
+    /// The runtime waits up to `max_wait` for IO events and timers, forever
+    /// if it is `None`, but does not wait at all if tasks are runnable
+    /// already. Then due timers fire and every runnable task is polled once.
+    /// This allows to drive the runtime from an external loop, for example
+    /// once per frame with `Some(Duration::from_millis(0))`, while other
+    /// threads spawn with `handle()`.
+    ///
+    /// Returns `RunError::Nested` if called while an executor is already
+    /// running on the current thread, for example from a task or within
+    /// `block_on` of another runtime. Panics of spawned tasks are returned as
+    /// `RunError::Panic`, unless caught by the task panic policy.
+    pub fn turn(&mut self, max_wait: Option<Duration>) -> Result<TurnResult, RunError> {
+        if is_nested() {
+            return Err(RunError::Nested);
+        }
+
+        let tasks = self.tasks.clone();
+        let res = catch_unwind(AssertUnwindSafe(|| {
+            self.enter_context(true, |executor| {
+                let turn = executor.turn(max_wait);
+                tasks.resume_panic();
+                turn
+            })
+        }));
+        match res {
+            Ok(Ok(turn)) => Ok(TurnResult {
+                polled: turn.has_polled(),
+                pending: tasks.alive.load(Ordering::Acquire),
+            }),
+            Ok(Err(_)) => match self.take_park_error() {
+                Some(e) => Err(RunError::Io(e)),
+                None => Err(RunError::Shutdown),
+            },
+            Err(err) => Err(RunError::Panic(err)),
+        }
+    }
+
+    /// Run spawned futures until none of them can make progress.
+    ///
+    /// Futures that are ready get polled until no future is immediately
+    /// runnable. With a `deadline` the runtime additionally waits up to
+    /// `deadline` for timers and IO events to wake more futures, otherwise it
+    /// returns right away.
+    pub fn run_until_idle(&mut self, deadline: Option<Duration>) -> IdleResult {
+        let tasks = self.tasks.clone();
+        let clock = self.clock.clone();
+        let until = deadline.map(|deadline| clock.now() + deadline);
+
+        self.enter_context(true, |executor| loop {
+            // poll everything that is ready right now
+            let polled = executor
+                .turn(Some(Duration::from_millis(0)))
+                .map(|turn| turn.has_polled())
+                .unwrap_or(false);
+            tasks.resume_panic();
+            if polled {
+                continue;
+            }
+
+            // wait for timers or IO events that are due before the deadline
+            let now = clock.now();
+            match until {
+                Some(until) if until > now => {
+                    if executor.turn(Some(until - now)).is_err() {
+                        break;
+                    }
+                }
+                _ => break,
+            }
+        });
+
+        IdleResult {
+            pending: tasks.alive.load(Ordering::Acquire),
+        }
+    }
+
+    /// Pause the runtime's clock, e.g. to test timeouts without waiting.
+    ///
+    /// Time stands still until `advance()` or `resume_time()` is called.
+    /// Meanwhile the runtime does not sleep for timer deadlines, it waits for
+    /// IO events and spawns only. Future waiting on a timer is never woken
+    /// unless time gets advanced.
+    pub fn pause_time(&self) {
+        // timers fire on ticks of the wheel, due ones have to fire on advance
+        let config = self.timer_config;
+        self.pausable.pause(|now| config.align(now))
+    }
+
+    /// Resume the clock paused with `pause_time()`.
+    ///
+    /// Clock continues from the paused instant, time skipped with `advance()`
+    /// is kept.
+    pub fn resume_time(&self) {
+        self.pausable.resume()
+    }
+
+    /// Advance the paused or the test clock and run spawned futures woken by
+    /// due timers.
+    ///
+    /// Timer parks with zero timeout, so advancing does not sleep. All timers
+    /// due in the window fire and futures get polled until none of them makes
+    /// progress, timers that become due in the process fire as well.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if time is not paused with `pause_time()` and the
+    /// runtime is not built with `Builder::test_clock()`.
+    pub fn advance(&mut self, duration: Duration) {
+        if !self.pausable.advance(duration) {
+            self.test_clock
+                .as_ref()
+                .expect("Runtime time is not paused and does not use test clock")
+                .advance(duration);
+        }
+
+        self.enter_context(true, |executor| {
+            // park fires due timers, then woken futures get polled
+            while executor
+                .turn(Some(Duration::from_millis(0)))
+                .map(|turn| turn.has_polled())
+                .unwrap_or(false)
+            {}
+        })
+    }
+
+    /// Run sync code with the runtime context installed.
+    ///
+    /// Reactor, timer, clock and executor of the runtime are set as defaults
+    /// for the duration of `f`, so `f` can create timers and I/O objects
+    /// bound to this runtime. Unlike `block_on`, no tasks are polled.
+    ///
+    /// Futures spawned with `Arbiter::spawn()` or `actix_rt::spawn()` within
+    /// `f` are queued and first polled by the next `block_on` or `run`.
+    pub fn enter<F, R>(&mut self, f: F) -> R
+        where
+            F: FnOnce() -> R,
+    {
+        let res = self.enter_context(false, |_| f());
+        for task in self.local.take() {
+            self.spawn_task(task);
+        }
+        res
     }
 
-    fn enter<F, R>(&mut self, f: F) -> R
+    /// Run remaining tasks until they complete or `timeout` elapses.
+    ///
+    /// Returns number of unfinished tasks.
+    fn drain(&mut self, timeout: Duration) -> usize {
+        let clock = self.clock.clone();
+        let deadline = clock.now() + timeout;
+
+        self.enter_context(true, |executor| loop {
+            let now = clock.now();
+            if executor.is_idle() || now >= deadline {
+                break;
+            }
+            if executor.turn(Some(deadline - now)).is_err() {
+                break;
+            }
+        });
+        self.tasks.alive.load(Ordering::Acquire)
+    }
+
+    /// Store the task in the slab, spawn the driver of the slab if it is
+    /// not spawned yet.
+    fn spawn_task(&mut self, task: Task) {
+        if let Some(driver) = self.slab.insert(task) {
+            self.executor.spawn(driver);
+        }
+    }
+
+    fn take_park_error(&mut self) -> Option<io::Error> {
+        self.executor.get_park_mut().get_park_mut().take_error()
+    }
+
+    /// Install the runtime context, `running` is `false` if `f` does not
+    /// run the executor.
+    fn enter_context<F, R>(&mut self, running: bool, f: F) -> R
         where
             F: FnOnce(&mut current_thread::CurrentThread<Parker>) -> R,
     {
+        // executor would panic with a less helpful message
+        if running && is_nested() {
+            panic!(
+                "Runtime `{}` can not run within a running executor on this thread, \
+                 spawn the future and await its JoinHandle instead",
+                self.name
+            );
+        }
+
+        let drivers = self.drivers();
         let Runtime {
             ref reactor_handle,
             ref timer_handle,
+            timer_config,
             ref clock,
+            ref tasks,
+            ref local,
+            ref slab,
             ref mut executor,
             ..
         } = *self;
 
+        for task in local.take() {
+            if let Some(driver) = slab.insert(task) {
+                executor.spawn(driver);
+            }
+        }
+
+        // restores previous runtime of the thread
+        struct Reset(Option<Current>);
+        impl Drop for Reset {
+            fn drop(&mut self) {
+                let prev = self.0.take();
+                CURRENT.with(|cell| *cell.borrow_mut() = prev);
+            }
+        }
+        let current = Current {
+            tasks: tasks.clone(),
+            handle: Handle(executor.handle(), tasks.clone(), drivers),
+            timer: timer_handle.clone(),
+            timer_config,
+            reactor: reactor_handle.is_some(),
+            deferred: if running { None } else { Some(local.clone()) },
+            slab: slab.clone(),
+        };
+        let _current = Reset(CURRENT.with(|cell| cell.borrow_mut().replace(current)));
+
         // This will set the default handle and timer to use inside the closure
         // and run the future.
-        let _reactor = driver::set_default(&reactor_handle);
-        clock::with_default(clock, || {
-            let _timer = timer::set_default(&timer_handle);
+        let _reactor = reactor_handle.as_ref().map(driver::set_default);
+        if running {
+            executor.get_park_mut().get_park_mut().enter();
+        }
+        let res = clock::with_default(clock, || {
+            let _timer = timer_handle.as_ref().map(timer::set_default);
             // The TaskExecutor is a fake executor that looks into the
             // current single-threaded executor when used. This is a trick,
             // because we need two mutable references to the executor (one
@@ -206,6 +1448,69 @@ impl Runtime {
             // one). We use the fake one here as the default one.
             let mut default_executor = current_thread::TaskExecutor::current();
             tokio_executor::with_default(&mut default_executor, || f(executor))
-        })
+        });
+        if running {
+            executor.get_park_mut().get_park_mut().exit();
+            tasks.log_polls();
+        }
+        res
+    }
+}
+
+/// Whether an executor is running on the current thread, executor panics if
+/// it is entered twice.
+fn is_nested() -> bool {
+    executor::enter().is_err()
+}
+
+/// Callback of `Builder::on_stop()`
+pub(crate) struct OnStop(pub(crate) Box<dyn FnOnce() + Send>);
+
+impl fmt::Debug for OnStop {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnStop")
+    }
+}
+
+impl Runtime {
+    /// Apply the drop policy, returns number of unfinished tasks the runtime
+    /// has to panic with.
+    fn drop_tasks(&mut self) -> usize {
+        match self.tasks.drop_policy() {
+            DropPolicy::Discard => 0,
+            DropPolicy::RunToCompletion { timeout } => {
+                if is_nested() {
+                    log::warn!("Runtime dropped within a running executor, tasks are not drained");
+                    return 0;
+                }
+                let abandoned = self.drain(timeout);
+                if abandoned != 0 {
+                    log::warn!("Runtime dropped, {} tasks abandoned", abandoned);
+                }
+                0
+            }
+            DropPolicy::Panic => self.tasks.alive.load(Ordering::Acquire),
+        }
+    }
+}
+
+impl Drop for Runtime {
+    fn drop(&mut self) {
+        // runtime is dropped while unwinding
+        let unfinished = if thread::panicking() {
+            0
+        } else {
+            self.drop_tasks()
+        };
+
+        if let Some(OnStop(on_stop)) = self.on_stop.take() {
+            if let Err(err) = catch_unwind(AssertUnwindSafe(on_stop)) {
+                log::error!("Runtime on_stop callback panicked: {}", panic::message(&err));
+            }
+        }
+
+        if unfinished != 0 {
+            panic!("Runtime dropped with {} unfinished tasks", unfinished);
+        }
     }
 }
@@ -0,0 +1,134 @@
+//! Asynchronous signal handling driven by the reactor of the current runtime.
+//!
+//! Signal futures and streams must be polled within an actix-rt `Runtime`
+//! context, they register with its reactor on the first poll so no extra
+//! thread is needed. Every listener of a signal is notified, notifications
+//! that arrive before a listener is polled again are coalesced.
+//!
+//! Each listener holds a registration with the reactor and is counted by
+//! `RuntimeMetrics::signal_listeners()` of the runtime it is created on.
+//!
+//! Runtimes without reactor, built with `Builder::enable_io(false)` or
+//! `Builder::with_park()`, can not deliver signals, listeners fail to be
+//! created on them.
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::Stream;
+
+use crate::runtime::Current;
+use crate::tasks::Tasks;
+
+/// Future that resolves on the next "ctrl-c" notification, see `ctrl_c()`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct CtrlC {
+    inner: tokio_net::signal::CtrlC,
+    _registered: Registered,
+}
+
+/// Counts a listener of the current runtime until it is dropped
+#[derive(Debug)]
+struct Registered(Option<Arc<Tasks>>);
+
+impl Registered {
+    fn new() -> Self {
+        let tasks = Current::get().map(|current| current.tasks);
+        if let Some(ref tasks) = tasks {
+            tasks.signal_listeners.fetch_add(1, Ordering::Relaxed);
+        }
+        Registered(tasks)
+    }
+}
+
+impl Drop for Registered {
+    fn drop(&mut self) {
+        if let Some(ref tasks) = self.0 {
+            tasks.signal_listeners.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Create future that resolves on the next "ctrl-c" notification sent to the
+/// process, `SIGINT` on Unix.
+///
+/// Dropping the future deregisters the listener, the process handler of the
+/// signal stays installed.
+pub fn ctrl_c() -> io::Result<CtrlC> {
+    check_reactor()?;
+    let ctrl_c = tokio_net::signal::ctrl_c()?;
+    Ok(CtrlC {
+        inner: ctrl_c,
+        _registered: Registered::new(),
+    })
+}
+
+/// Fails if the current runtime has no reactor to deliver signals.
+fn check_reactor() -> io::Result<()> {
+    match Current::get() {
+        Some(ref current) if !current.reactor => Err(io::Error::new(
+            io::ErrorKind::Other,
+            "reactor is disabled on this runtime, see `Builder::enable_io()`",
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl Future for CtrlC {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // the stream never ends
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            Poll::Ready(_) => Poll::Ready(()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Unix specific signals.
+#[cfg(unix)]
+pub mod unix {
+    use std::io;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    use futures::Stream;
+
+    use super::Registered;
+
+    pub use tokio_net::signal::unix::SignalKind;
+
+    /// Stream of notifications of a signal, see `signal()`.
+    #[must_use = "streams do nothing unless polled"]
+    #[derive(Debug)]
+    pub struct Signal {
+        inner: tokio_net::signal::unix::Signal,
+        _registered: Registered,
+    }
+
+    /// Create stream that yields every notification of the signal `kind`
+    /// sent to the process.
+    ///
+    /// Fails on a runtime without reactor, see the module documentation.
+    pub fn signal(kind: SignalKind) -> io::Result<Signal> {
+        super::check_reactor()?;
+        let signal = tokio_net::signal::unix::signal(kind)?;
+        Ok(Signal {
+            inner: signal,
+            _registered: Registered::new(),
+        })
+    }
+
+    impl Stream for Signal {
+        type Item = ();
+
+        fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<()>> {
+            Pin::new(&mut self.inner).poll_next(cx)
+        }
+    }
+}
@@ -0,0 +1,328 @@
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use futures::task::{waker_ref, ArcWake, AtomicWaker};
+
+use crate::tasks::Tasks;
+
+pub(crate) type Task = Pin<Box<dyn Future<Output = ()>>>;
+
+/// Task nodes of a runtime.
+///
+/// Spawned tasks are stored in nodes addressed by index and generation and
+/// polled by a single driver task on the executor. Node of a completed task
+/// is reused by later spawns, its generation is bumped so wakers that
+/// outlive the task do not wake the next one.
+pub(crate) struct TaskSlab {
+    inner: RefCell<Inner>,
+    ready: Arc<ReadyQueue>,
+    /// Nodes of tasks spawned since the last poll of the driver, spawns
+    /// happen on the runtime thread and skip the lock of the ready queue
+    spawned: Cell<Vec<(usize, usize)>>,
+    /// Buffer of the nodes polled by the driver
+    batch: Cell<Vec<(usize, usize)>>,
+    /// Driver is spawned on the executor
+    driving: Cell<bool>,
+    tasks: Arc<Tasks>,
+}
+
+struct Inner {
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+}
+
+struct Node {
+    /// `None` while the task is polled and once the node is free
+    task: Option<Task>,
+    generation: usize,
+    /// Waker of the current task, reused unless a clone outlived the task
+    waker: Arc<NodeWaker>,
+}
+
+impl Node {
+    fn new(index: usize, ready: &Arc<ReadyQueue>) -> Self {
+        Node {
+            task: None,
+            generation: 0,
+            waker: Arc::new(NodeWaker {
+                index,
+                generation: 0,
+                queued: AtomicBool::new(false),
+                ready: ready.clone(),
+            }),
+        }
+    }
+}
+
+/// Nodes woken since the last poll of the driver
+struct ReadyQueue {
+    queue: Mutex<Vec<(usize, usize)>>,
+    driver: AtomicWaker,
+}
+
+impl ReadyQueue {
+    fn push(&self, index: usize, generation: usize) {
+        let notified = {
+            let mut queue = self.queue.lock().unwrap();
+            queue.push((index, generation));
+            // first push since the driver took the queue wakes it
+            queue.len() > 1
+        };
+        if !notified {
+            self.driver.wake();
+        }
+    }
+
+    /// Move woken nodes to the end of `batch`.
+    fn append(&self, batch: &mut Vec<(usize, usize)>) {
+        let mut queue = self.queue.lock().unwrap();
+        if batch.is_empty() {
+            mem::swap(&mut *queue, batch)
+        } else {
+            batch.append(&mut queue)
+        }
+    }
+}
+
+struct NodeWaker {
+    index: usize,
+    generation: usize,
+    /// Task is in the ready queue already
+    queued: AtomicBool,
+    ready: Arc<ReadyQueue>,
+}
+
+impl ArcWake for NodeWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.queued.swap(true, Ordering::AcqRel) {
+            arc_self.ready.push(arc_self.index, arc_self.generation);
+        }
+    }
+}
+
+impl TaskSlab {
+    /// Create slab with `capacity` free nodes.
+    pub(crate) fn new(capacity: usize, tasks: Arc<Tasks>) -> Self {
+        let ready = Arc::new(ReadyQueue {
+            queue: Mutex::new(Vec::new()),
+            driver: AtomicWaker::new(),
+        });
+        TaskSlab {
+            inner: RefCell::new(Inner {
+                nodes: (0..capacity)
+                    .map(|index| Node::new(index, &ready))
+                    .collect(),
+                // lowest index is taken first
+                free: (0..capacity).rev().collect(),
+            }),
+            ready,
+            spawned: Cell::new(Vec::new()),
+            batch: Cell::new(Vec::new()),
+            driving: Cell::new(false),
+            tasks,
+        }
+    }
+
+    /// Number of allocated nodes, free or not.
+    pub(crate) fn len(&self) -> usize {
+        self.inner.borrow().nodes.len()
+    }
+
+    /// Store the task in a free node and schedule its first poll.
+    ///
+    /// Returns the driver if it has to be spawned on the executor.
+    pub(crate) fn insert(self: &Rc<Self>, task: Task) -> Option<Driver> {
+        let (index, generation) = {
+            let mut inner = self.inner.borrow_mut();
+            let index = match inner.free.pop() {
+                Some(index) => index,
+                None => {
+                    let index = inner.nodes.len();
+                    inner.nodes.push(Node::new(index, &self.ready));
+                    index
+                }
+            };
+            let node = &mut inner.nodes[index];
+            node.task = Some(task);
+            let generation = node.generation;
+            match Arc::get_mut(&mut node.waker) {
+                Some(waker) => {
+                    waker.generation = generation;
+                    *waker.queued.get_mut() = true;
+                }
+                None => {
+                    node.waker = Arc::new(NodeWaker {
+                        index,
+                        generation,
+                        queued: AtomicBool::new(true),
+                        ready: self.ready.clone(),
+                    })
+                }
+            }
+            (index, generation)
+        };
+        let mut spawned = self.spawned.take();
+        spawned.push((index, generation));
+        let first = spawned.len() == 1;
+        self.spawned.set(spawned);
+
+        if self.driving.replace(true) {
+            // first spawn since the driver took the queue wakes it
+            if first {
+                self.ready.driver.wake();
+            }
+            None
+        } else {
+            Some(Driver {
+                slab: self.clone(),
+                done: false,
+            })
+        }
+    }
+
+    /// Poll task of the node if it is still of `generation`.
+    fn poll(&self, index: usize, generation: usize) {
+        let (mut task, waker) = {
+            let mut inner = self.inner.borrow_mut();
+            let node = &mut inner.nodes[index];
+            if node.generation != generation {
+                return;
+            }
+            match node.task.take() {
+                Some(task) => (task, node.waker.clone()),
+                None => return,
+            }
+        };
+        waker.queued.store(false, Ordering::Release);
+
+        // node is freed once the task is dropped, even if the poll or the
+        // drop unwinds
+        let guard = Release { slab: self, index };
+        let res = task
+            .as_mut()
+            .poll(&mut Context::from_waker(&waker_ref(&waker)));
+        match res {
+            // task may spawn on drop, slab is not borrowed
+            Poll::Ready(()) => drop(task),
+            Poll::Pending => {
+                mem::forget(guard);
+                self.inner.borrow_mut().nodes[index].task = Some(task)
+            }
+        }
+    }
+
+    fn release(&self, index: usize) {
+        let mut inner = self.inner.borrow_mut();
+        let node = &mut inner.nodes[index];
+        node.generation = node.generation.wrapping_add(1);
+        inner.free.push(index);
+    }
+
+    fn is_empty(&self) -> bool {
+        let inner = self.inner.borrow();
+        inner.free.len() == inner.nodes.len()
+    }
+
+    /// Drop all tasks, in order of their nodes.
+    fn clear(&self) {
+        let tasks: Vec<_> = {
+            let mut inner = self.inner.borrow_mut();
+            let Inner {
+                ref mut nodes,
+                ref mut free,
+            } = *inner;
+            nodes
+                .iter_mut()
+                .enumerate()
+                .filter_map(|(index, node)| {
+                    let task = node.task.take()?;
+                    node.generation = node.generation.wrapping_add(1);
+                    free.push(index);
+                    Some(task)
+                })
+                .collect()
+        };
+        drop(tasks);
+    }
+}
+
+impl fmt::Debug for TaskSlab {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = self.inner.borrow();
+        f.debug_struct("TaskSlab")
+            .field("nodes", &inner.nodes.len())
+            .field("free", &inner.free.len())
+            .finish()
+    }
+}
+
+/// Frees the node of a completed task
+struct Release<'a> {
+    slab: &'a TaskSlab,
+    index: usize,
+}
+
+impl Drop for Release<'_> {
+    fn drop(&mut self) {
+        self.slab.release(self.index);
+    }
+}
+
+/// Task of the executor that polls woken tasks of the slab.
+///
+/// Completes once the slab has no tasks left, the next spawn spawns a new
+/// driver.
+pub(crate) struct Driver {
+    slab: Rc<TaskSlab>,
+    done: bool,
+}
+
+impl Future for Driver {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let slab = &self.slab;
+        slab.ready.driver.register(cx.waker());
+
+        // tasks woken meanwhile are polled on the next poll of the driver
+        let mut batch = slab.spawned.replace(slab.batch.take());
+        slab.ready.append(&mut batch);
+        for (index, generation) in batch.drain(..) {
+            // panic of a panic policy callback or of a task's drop must not
+            // unwind through the executor, it would lose the rest of the
+            // batch and keep the executor busy forever. It is raised after
+            // the executor's tick, like panics of `PanicPolicy::Propagate`.
+            let polled = panic::catch_unwind(AssertUnwindSafe(|| slab.poll(index, generation)));
+            if let Err(err) = polled {
+                slab.tasks.set_panic(err);
+            }
+        }
+        slab.batch.set(batch);
+
+        if slab.is_empty() {
+            slab.driving.set(false);
+            self.done = true;
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Driver {
+    fn drop(&mut self) {
+        // executor is dropped with pending tasks
+        if !self.done {
+            self.slab.driving.set(false);
+            self.slab.clear();
+        }
+    }
+}
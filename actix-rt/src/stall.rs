@@ -0,0 +1,102 @@
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Callback of `Builder::stall_warning()`
+pub(crate) type StallHook = Arc<dyn Fn(Duration, Option<&str>) + Send + Sync>;
+
+/// Shortest interval between checks of the monitor thread
+const MIN_CHECK_INTERVAL: Duration = Duration::from_millis(1);
+
+/// Polls of a runtime, observed by its monitor thread
+pub(crate) struct Heartbeat {
+    threshold: Duration,
+    hook: StallHook,
+    start: Instant,
+    /// Number of polls
+    beat: AtomicU64,
+    /// Whether a poll is running
+    busy: AtomicBool,
+    /// Start of the running poll, nanoseconds since `start`
+    since: AtomicU64,
+    /// Name of the task of the running poll
+    task: Mutex<Option<Arc<str>>>,
+}
+
+impl Heartbeat {
+    pub(crate) fn new(threshold: Duration, hook: StallHook) -> Self {
+        Heartbeat {
+            threshold,
+            hook,
+            start: Instant::now(),
+            beat: AtomicU64::new(0),
+            busy: AtomicBool::new(false),
+            since: AtomicU64::new(0),
+            task: Mutex::new(None),
+        }
+    }
+
+    /// Mark start of a poll, poll ends once the guard is dropped.
+    pub(crate) fn enter(&self, task: Option<&Arc<str>>) -> Busy<'_> {
+        *self.task.lock().unwrap() = task.cloned();
+        self.since
+            .store(self.start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        self.beat.fetch_add(1, Ordering::Release);
+        self.busy.store(true, Ordering::Release);
+        Busy(self)
+    }
+
+    /// Duration of the running poll, if it runs for longer than the threshold.
+    fn stalled(&self) -> Option<Duration> {
+        if !self.busy.load(Ordering::Acquire) {
+            return None;
+        }
+        let since = Duration::from_nanos(self.since.load(Ordering::Relaxed));
+        let elapsed = self.start.elapsed().checked_sub(since)?;
+        if elapsed >= self.threshold {
+            Some(elapsed)
+        } else {
+            None
+        }
+    }
+}
+
+pub(crate) struct Busy<'a>(&'a Heartbeat);
+
+impl Drop for Busy<'_> {
+    fn drop(&mut self) {
+        self.0.busy.store(false, Ordering::Release);
+    }
+}
+
+/// Start monitor thread, it exits once the runtime is dropped.
+pub(crate) fn watch(heartbeat: &Arc<Heartbeat>) -> io::Result<()> {
+    let interval = (heartbeat.threshold / 4).max(MIN_CHECK_INTERVAL);
+    let heartbeat: Weak<Heartbeat> = Arc::downgrade(heartbeat);
+
+    thread::Builder::new()
+        .name("actix-rt:stall-monitor".into())
+        .spawn(move || {
+            // each stalled poll is reported once
+            let mut reported = None;
+            loop {
+                thread::sleep(interval);
+                let heartbeat = match heartbeat.upgrade() {
+                    Some(heartbeat) => heartbeat,
+                    None => return,
+                };
+                let beat = heartbeat.beat.load(Ordering::Acquire);
+                if reported == Some(beat) {
+                    continue;
+                }
+                if let Some(stalled) = heartbeat.stalled() {
+                    reported = Some(beat);
+                    let task = heartbeat.task.lock().unwrap().clone();
+                    (heartbeat.hook)(stalled, task.as_ref().map(|name| &**name));
+                }
+            }
+        })
+        .map(|_| ())
+}
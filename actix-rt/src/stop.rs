@@ -0,0 +1,87 @@
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::channel::oneshot::{channel, Canceled};
+
+use crate::tasks::Tasks;
+
+/// Stop request observed by `Runtime::run()`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    Running,
+    Graceful(Duration),
+    Stop,
+}
+
+/// Outcome of a graceful stop.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StopReport {
+    /// Number of tasks that did not complete before `run()` returned,
+    /// includes tasks spawned after the stop request
+    pub abandoned: usize,
+    /// Whether tasks spawned before the stop request were still running
+    /// when `run()` returned, e.g. because the timeout elapsed
+    pub forced: bool,
+}
+
+/// Handling of unfinished tasks when a `Runtime` is dropped.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DropPolicy {
+    /// Unfinished tasks are dropped
+    Discard,
+    /// Runtime keeps running the tasks until they complete or `timeout`
+    /// elapses, the number of abandoned tasks is logged
+    RunToCompletion {
+        /// Time limit for the remaining tasks
+        timeout: Duration,
+    },
+    /// Dropping the runtime with unfinished tasks panics
+    Panic,
+}
+
+impl Default for DropPolicy {
+    fn default() -> Self {
+        DropPolicy::Discard
+    }
+}
+
+/// Handle that stops `Runtime::run()` from another task or thread.
+#[derive(Clone)]
+pub struct StopHandle(Arc<Tasks>);
+
+impl StopHandle {
+    pub(crate) fn new(tasks: Arc<Tasks>) -> Self {
+        StopHandle(tasks)
+    }
+
+    /// Make `Runtime::run()` return `Ok(())` at its next tick, remaining
+    /// tasks stay in the runtime.
+    pub fn stop(&self) {
+        self.0.request(Mode::Stop)
+    }
+
+    /// Stop `Runtime::run()` gracefully.
+    ///
+    /// Tasks spawned after this call are not polled, fallible spawns fail
+    /// with `SpawnErrorKind::Stopping`. Runtime keeps running
+    /// the existing tasks until they complete or `timeout` elapses, then
+    /// `run()` returns. Returned future resolves with the report once `run()`
+    /// returns, or with `Canceled` if the runtime is dropped first.
+    pub fn stop_graceful(
+        &self,
+        timeout: Duration,
+    ) -> impl Future<Output = Result<StopReport, Canceled>> + Send {
+        let (tx, rx) = channel();
+        self.0.reports.lock().unwrap().push(tx);
+        self.0.request(Mode::Graceful(timeout));
+        rx
+    }
+}
+
+impl fmt::Debug for StopHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("StopHandle").field(&self.0).finish()
+    }
+}
@@ -1,16 +1,23 @@
 use std::cell::RefCell;
 use std::io;
 use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
 
 use futures::channel::mpsc::UnboundedSender;
-use futures::Future;
+use futures::{future, Future};
 
-use crate::arbiter::{Arbiter, SystemCommand};
+use crate::arbiter::{Arbiter, StopHook, SystemCommand};
 use crate::builder::{Builder, SystemRunner};
 use tokio_executor::current_thread::Handle;
 
 static SYSTEM_COUNT: AtomicUsize = AtomicUsize::new(0);
 
+lazy_static::lazy_static! {
+    /// Running systems in order of creation, `System::current()` falls back to
+    /// the first one on threads without a system
+    static ref SYSTEMS: Mutex<Vec<System>> = Mutex::new(Vec::new());
+}
+
 /// System is a runtime manager.
 #[derive(Clone, Debug)]
 pub struct System {
@@ -24,21 +31,51 @@ thread_local!(
     static CURRENT: RefCell<Option<System>> = RefCell::new(None);
 );
 
+/// Keeps the system registered until dropped
+#[derive(Debug)]
+pub(crate) struct Registration(usize);
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let id = self.0;
+        if let Ok(mut systems) = SYSTEMS.lock() {
+            systems.retain(|sys| sys.id != id);
+        }
+        let _ = CURRENT.try_with(|cell| {
+            let mut cell = cell.borrow_mut();
+            if cell.as_ref().map(|sys| sys.id) == Some(id) {
+                *cell = None;
+            }
+        });
+    }
+}
+
 impl System {
-    /// Constructs new system and sets it as current
+    /// Constructs new system, registers it and sets it as current
+    ///
+    /// Panics if the current thread already has a system.
     pub(crate) fn construct(
         sys: UnboundedSender<SystemCommand>,
         arbiter: Arbiter,
         stop_on_panic: bool,
-    ) -> Self {
+    ) -> (Self, Registration) {
+        if System::is_set() {
+            panic!(
+                "System is already running on this thread, \
+                 only one System per thread is allowed"
+            );
+        }
+
         let sys = System {
             sys,
             arbiter,
             stop_on_panic,
             id: SYSTEM_COUNT.fetch_add(1, Ordering::SeqCst),
         };
+        SYSTEMS.lock().unwrap().push(sys.clone());
         System::set_current(sys.clone());
-        sys
+        let registration = Registration(sys.id);
+        (sys, registration)
     }
 
     /// Build a new system with a customized tokio runtime.
@@ -49,10 +86,24 @@ impl System {
         Builder::new()
     }
 
+    /// Run the future on a new system and tear the system down, see
+    /// `Builder::block_on()`.
+    ///
+    /// Use `System::builder()` to customize the system.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the current thread already runs a system or a
+    /// runtime.
+    pub fn block_on<F: Future>(fut: F) -> F::Output {
+        Self::builder().block_on(fut)
+    }
+
     #[allow(clippy::new_ret_no_self)]
     /// Create new system.
     ///
-    /// This method panics if it can not create tokio runtime
+    /// This method panics if it can not create tokio runtime or if the current
+    /// thread already runs a system
     pub fn new<T: Into<String>>(name: T) -> SystemRunner {
         Self::builder().name(name).build()
     }
@@ -64,7 +115,7 @@ impl System {
     pub fn run_in_executor<T: Into<String>>(
         name: T,
         executor: Handle,
-    ) -> impl Future<Output=Result<(),io::Error>> + Send {
+    ) -> impl Future<Output=Result<i32,io::Error>> + Send {
         Self::builder()
             .name(name)
             .build_async(executor)
@@ -72,10 +123,17 @@ impl System {
     }
 
     /// Get current running system.
+    ///
+    /// On threads that do not belong to a system, returns the earliest created
+    /// system that is still running. Panics if there is none.
     pub fn current() -> System {
         CURRENT.with(|cell| match *cell.borrow() {
             Some(ref sys) => sys.clone(),
-            None => panic!("System is not running"),
+            None => {
+                // panic must not poison the registry
+                let first = SYSTEMS.lock().unwrap().first().cloned();
+                first.expect("System is not running")
+            }
         })
     }
 
@@ -93,14 +151,13 @@ impl System {
     }
 
     /// Execute function with system reference.
+    ///
+    /// Same as `System::current()`, falls back to the earliest running system.
     pub fn with_current<F, R>(f: F) -> R
     where
         F: FnOnce(&System) -> R,
     {
-        CURRENT.with(|cell| match *cell.borrow() {
-            Some(ref sys) => f(sys),
-            None => panic!("System is not running"),
-        })
+        f(&System::current())
     }
 
     /// System id
@@ -114,10 +171,42 @@ impl System {
     }
 
     /// Stop the system with a particular exit code.
+    ///
+    /// The code is returned by `SystemRunner::run()`, if the system is
+    /// stopped several times the first code wins.
     pub fn stop_with_code(&self, code: i32) {
         let _ = self.sys.unbounded_send(SystemCommand::Exit(code));
     }
 
+    /// Register a function that runs on the system arbiter once the system
+    /// is stopped.
+    ///
+    /// Hooks run after all arbiters of the system are stopped and their
+    /// runtimes are dropped, in ascending `priority` order, hooks of the same
+    /// priority run in registration order. `run()` returns once all hooks are
+    /// done. A panic of a hook is logged and the next hook runs.
+    pub fn on_stop<F>(&self, priority: i32, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.on_stop_async(priority, move || {
+            f();
+            future::ready(())
+        })
+    }
+
+    /// Same as `System::on_stop()`, but awaits the future returned by `f`
+    /// before the next hook runs.
+    pub fn on_stop_async<F, R>(&self, priority: i32, f: F)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Future<Output = ()> + Send + 'static,
+    {
+        let _ = self
+            .sys
+            .unbounded_send(SystemCommand::OnStop(StopHook::new(priority, f)));
+    }
+
     pub(crate) fn sys(&self) -> &UnboundedSender<SystemCommand> {
         &self.sys
     }
@@ -136,7 +225,9 @@ impl System {
     /// This function will start tokio runtime and will finish once the
     /// `System::stop()` message get called.
     /// Function `f` get called within tokio runtime context.
-    pub fn run<F>(f: F) -> io::Result<()>
+    ///
+    /// Returns the exit code of the system, see `SystemRunner::run()`.
+    pub fn run<F>(f: F) -> io::Result<i32>
     where
         F: FnOnce() + 'static,
     {
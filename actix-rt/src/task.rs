@@ -0,0 +1,329 @@
+//! Task handles, task metadata and cooperative scheduling.
+use std::any::Any;
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+
+use futures::channel::oneshot;
+use futures::task::AtomicWaker;
+
+thread_local!(
+    /// Polls left to the running task, unlimited if unset
+    static BUDGET: Cell<Option<u32>> = Cell::new(None);
+);
+
+/// Task failed to complete.
+#[derive(Debug)]
+pub enum JoinError {
+    /// Task got aborted before completion
+    Cancelled,
+    /// Task panicked, contains the panic payload
+    Panic(Box<dyn Any + Send + 'static>),
+    /// Runtime of the task shut down before the task completed
+    Shutdown,
+}
+
+impl JoinError {
+    /// Returns `true` if the task got cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        match *self {
+            JoinError::Cancelled => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the task panicked.
+    pub fn is_panic(&self) -> bool {
+        match *self {
+            JoinError::Panic(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Returns `true` if the runtime of the task shut down.
+    pub fn is_shutdown(&self) -> bool {
+        match *self {
+            JoinError::Shutdown => true,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(fmt, "task was cancelled"),
+            JoinError::Panic(_) => write!(fmt, "task panicked"),
+            JoinError::Shutdown => write!(fmt, "runtime shut down"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// Metadata of a spawned task, passed to poll hooks.
+#[derive(Debug, Clone)]
+pub struct TaskMeta {
+    id: u64,
+    name: Option<Arc<str>>,
+}
+
+impl TaskMeta {
+    pub(crate) fn new(id: u64, name: Option<Arc<str>>) -> Self {
+        TaskMeta { id, name }
+    }
+
+    /// Id of the task, unique within its runtime.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Name of the task, if any.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_ref().map(|name| &**name)
+    }
+
+    pub(crate) fn shared_name(&self) -> Option<&Arc<str>> {
+        self.name.as_ref()
+    }
+}
+
+/// Live named task, returned by `Runtime::dump_tasks()`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TaskInfo {
+    /// Id of the task, unique within its runtime
+    pub id: u64,
+    /// Name given to the task on spawn
+    pub name: Arc<str>,
+    /// When the task got spawned
+    pub spawned: Instant,
+    /// When the task got polled last, `None` if it was never polled
+    pub last_poll: Option<Instant>,
+}
+
+/// State shared by the task and its handle
+struct Shared {
+    aborted: AtomicBool,
+    waker: AtomicWaker,
+}
+
+/// Owned handle to the spawned task, resolves with the task's output.
+///
+/// Dropping the handle detaches the task, it keeps running to completion.
+#[must_use = "dropping JoinHandle detaches the task"]
+pub struct JoinHandle<T> {
+    rx: oneshot::Receiver<Result<T, JoinError>>,
+    shared: Arc<Shared>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Abort the task.
+    ///
+    /// Task is dropped at its next yield point and the handle resolves with
+    /// `JoinError::Cancelled`, unless the task is already complete.
+    pub fn abort(&self) {
+        self.shared.aborted.store(true, Ordering::Release);
+        self.shared.waker.wake();
+    }
+}
+
+impl<T> fmt::Debug for JoinHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JoinHandle")
+            .field("aborted", &self.shared.aborted.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        match Pin::new(&mut self.rx).poll(cx) {
+            Poll::Ready(Ok(res)) => Poll::Ready(res),
+            Poll::Ready(Err(_)) => Poll::Ready(Err(JoinError::Cancelled)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Handle to the task spawned on a remote runtime with
+/// `Handle::spawn_with_handle()`.
+///
+/// Remote handle can be sent to other threads and awaited on any executor.
+#[must_use = "dropping RemoteJoinHandle detaches the task"]
+#[derive(Debug)]
+pub struct RemoteJoinHandle<T>(JoinHandle<T>);
+
+impl<T> RemoteJoinHandle<T> {
+    /// Abort the task, see `JoinHandle::abort()`.
+    pub fn abort(&self) {
+        self.0.abort()
+    }
+}
+
+impl<T> Future for RemoteJoinHandle<T> {
+    type Output = Result<T, JoinError>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        Pin::new(&mut self.0).poll(cx)
+    }
+}
+
+impl<T> From<JoinHandle<T>> for RemoteJoinHandle<T> {
+    fn from(handle: JoinHandle<T>) -> Self {
+        RemoteJoinHandle(handle)
+    }
+}
+
+/// Same as `with_handle()`, the future is polled first once `deadline`
+/// elapses. Timer gets registered with the first poll of the task.
+pub(crate) fn with_handle_at<F>(
+    deadline: Instant,
+    future: F,
+) -> (Task<impl Future<Output = F::Output>>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    with_handle(async move {
+        crate::time::delay_until(deadline).await;
+        future.await
+    })
+}
+
+/// Wraps future into a task that reports its output to the `JoinHandle`.
+pub(crate) fn with_handle<F>(future: F) -> (Task<F>, JoinHandle<F::Output>)
+where
+    F: Future,
+{
+    let (tx, rx) = oneshot::channel();
+    let shared = Arc::new(Shared {
+        aborted: AtomicBool::new(false),
+        waker: AtomicWaker::new(),
+    });
+    let task = Task {
+        future,
+        tx: Some(tx),
+        shared: shared.clone(),
+    };
+    (task, JoinHandle { rx, shared })
+}
+
+pub(crate) struct Task<F: Future> {
+    future: F,
+    tx: Option<oneshot::Sender<Result<F::Output, JoinError>>>,
+    shared: Arc<Shared>,
+}
+
+impl<F: Future> Future for Task<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // safe, `future` is never moved out of pinned task
+        let this = unsafe { self.get_unchecked_mut() };
+
+        // aborting wakes the task
+        this.shared.waker.register(cx.waker());
+        if this.shared.aborted.load(Ordering::Acquire) {
+            // dropped sender resolves the handle with `Cancelled`
+            this.tx.take();
+            return Poll::Ready(());
+        }
+
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let res = match panic::catch_unwind(AssertUnwindSafe(|| future.poll(cx))) {
+            Ok(Poll::Pending) => return Poll::Pending,
+            Ok(Poll::Ready(res)) => Ok(res),
+            Err(err) => Err(JoinError::Panic(err)),
+        };
+        if let Some(tx) = this.tx.take() {
+            let _ = tx.send(res);
+        }
+        Poll::Ready(())
+    }
+}
+
+impl<F: Future> Drop for Task<F> {
+    fn drop(&mut self) {
+        // task is dropped by its runtime before completion
+        if let Some(tx) = self.tx.take() {
+            let _ = tx.send(Err(JoinError::Shutdown));
+        }
+    }
+}
+
+/// Yield execution back to the runtime.
+///
+/// Returned future is pending once, the task is woken right away and polled
+/// again after the runtime has polled other ready tasks and turned its
+/// reactor and timer.
+pub fn yield_now() -> impl Future<Output = ()> {
+    YieldNow(false)
+}
+
+/// Consume a unit of the task's poll budget, see `Builder::cooperative_budget()`.
+///
+/// Yields like `yield_now()` once the budget is exhausted, completes
+/// immediately if no budget is configured or outside of a spawned task.
+/// Loops that are always ready, e.g. over a channel that never runs empty,
+/// should call it on each iteration so they do not starve other tasks.
+pub fn consume_budget() -> impl Future<Output = ()> {
+    ConsumeBudget
+}
+
+struct YieldNow(bool);
+
+impl Future for YieldNow {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if self.0 {
+            return Poll::Ready(());
+        }
+        self.0 = true;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+struct ConsumeBudget;
+
+impl Future for ConsumeBudget {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        BUDGET.with(|budget| match budget.get() {
+            None => Poll::Ready(()),
+            Some(0) => {
+                cx.waker().wake_by_ref();
+                Poll::Pending
+            }
+            Some(n) => {
+                budget.set(Some(n - 1));
+                Poll::Ready(())
+            }
+        })
+    }
+}
+
+/// Set budget of the task that is about to be polled, previous budget is
+/// restored on drop.
+pub(crate) fn set_budget(budget: u32) -> BudgetGuard {
+    BudgetGuard(BUDGET.with(|cell| cell.replace(Some(budget))))
+}
+
+pub(crate) struct BudgetGuard(Option<u32>);
+
+impl Drop for BudgetGuard {
+    fn drop(&mut self) {
+        let prev = self.0;
+        let _ = BUDGET.try_with(|cell| cell.set(prev));
+    }
+}
@@ -0,0 +1,771 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread::{self, ThreadId};
+use std::time::{Duration, Instant};
+
+use futures::channel::oneshot::Sender;
+use futures::task::{waker, ArcWake};
+use tokio_executor::park::Unpark;
+
+use crate::panic::PanicPolicy;
+use crate::runtime::SpawnErrorKind;
+use crate::stall::{Heartbeat, StallHook};
+use crate::stop::{DropPolicy, Mode, StopReport};
+use crate::task::{self, TaskInfo, TaskMeta};
+use crate::time;
+
+/// Callback invoked with duration of each task poll
+pub(crate) type PollHook = Arc<dyn Fn(Duration) + Send + Sync>;
+
+/// Callback invoked before each task poll
+pub(crate) type BeforePoll = Arc<dyn Fn(&TaskMeta) + Send + Sync>;
+
+/// Callback invoked after each task poll with its duration
+pub(crate) type AfterPoll = Arc<dyn Fn(&TaskMeta, Duration) + Send + Sync>;
+
+/// Task hooks configured with `Builder`
+#[derive(Clone, Default)]
+pub(crate) struct Hooks {
+    /// Unset unless any poll hook is configured
+    pub(crate) poll: Option<PollHooks>,
+    pub(crate) panic_policy: PanicPolicy,
+    pub(crate) drop_policy: DropPolicy,
+    /// Limit of live tasks, unbounded if unset
+    pub(crate) max_tasks: Option<usize>,
+    /// Cooperative budget of each task poll, unlimited if unset
+    pub(crate) budget: Option<u32>,
+    /// Threshold and callback of stalled polls, polls are not monitored
+    /// if unset
+    pub(crate) stall: Option<(Duration, StallHook)>,
+    /// Polls per executor tick, unlimited if unset
+    pub(crate) tick_budget: Option<TickBudget>,
+    /// Thread-local contexts of tasks in registration order
+    pub(crate) context: Vec<Arc<Propagator>>,
+    /// Seed of the deterministic scheduling, unset by default
+    pub(crate) seed: Option<u64>,
+    /// Number of task nodes allocated up front
+    pub(crate) task_slab_capacity: usize,
+}
+
+impl Hooks {
+    pub(crate) fn poll_hooks(&mut self) -> &mut PollHooks {
+        self.poll.get_or_insert_with(PollHooks::default)
+    }
+}
+
+/// Callbacks around task polls
+#[derive(Clone, Default)]
+pub(crate) struct PollHooks {
+    pub(crate) on_poll: Option<PollHook>,
+    pub(crate) before: Option<BeforePoll>,
+    pub(crate) after: Option<AfterPoll>,
+    /// Instrument root future of `block_on` as well
+    pub(crate) block_on: bool,
+}
+
+impl PollHooks {
+    fn poll<F: Future>(
+        &self,
+        meta: &TaskMeta,
+        future: Pin<&mut F>,
+        cx: &mut Context<'_>,
+    ) -> Poll<F::Output> {
+        if let Some(ref before) = self.before {
+            before(meta);
+        }
+        let start = Instant::now();
+        let res = future.poll(cx);
+        let elapsed = start.elapsed();
+        if let Some(ref on_poll) = self.on_poll {
+            on_poll(elapsed);
+        }
+        if let Some(ref after) = self.after {
+            after(meta, elapsed);
+        }
+        res
+    }
+}
+
+/// Type-erased callbacks of `Builder::task_context()`
+pub(crate) struct Propagator {
+    capture: Box<dyn Fn() -> Box<dyn Any + Send> + Send + Sync>,
+    restore: Box<dyn Fn(Box<dyn Any + Send>) + Send + Sync>,
+}
+
+impl Propagator {
+    pub(crate) fn new<T, C, R>(capture: C, restore: R) -> Self
+    where
+        T: Send + 'static,
+        C: Fn() -> T + Send + Sync + 'static,
+        R: Fn(T) + Send + Sync + 'static,
+    {
+        Propagator {
+            capture: Box::new(move || Box::new(capture())),
+            restore: Box::new(move |value| restore(*value.downcast::<T>().unwrap())),
+        }
+    }
+}
+
+/// Context of a task installed on the runtime thread, thread's own context
+/// gets restored on drop.
+///
+/// Each slot holds the context that is not installed, the task's one
+/// between polls and the thread's one during a poll.
+struct EnteredContext<'a> {
+    propagators: &'a [Arc<Propagator>],
+    slots: &'a mut [Box<dyn Any + Send>],
+}
+
+impl<'a> EnteredContext<'a> {
+    fn enter(propagators: &'a [Arc<Propagator>], slots: &'a mut [Box<dyn Any + Send>]) -> Self {
+        for (propagator, slot) in propagators.iter().zip(slots.iter_mut()) {
+            let task = mem::replace(slot, (propagator.capture)());
+            (propagator.restore)(task);
+        }
+        EnteredContext { propagators, slots }
+    }
+}
+
+impl Drop for EnteredContext<'_> {
+    fn drop(&mut self) {
+        let slots = self.slots.iter_mut().rev();
+        for (propagator, slot) in self.propagators.iter().rev().zip(slots) {
+            let thread = mem::replace(slot, (propagator.capture)());
+            (propagator.restore)(thread);
+        }
+    }
+}
+
+/// Seeded scheduling of `Builder::deterministic()`
+struct Deterministic {
+    /// State of the splitmix64 generator
+    state: AtomicU64,
+    /// Ids of the tasks polled in the current tick
+    polled: Mutex<Vec<u64>>,
+}
+
+impl Deterministic {
+    fn new(seed: u64) -> Self {
+        Deterministic {
+            state: AtomicU64::new(seed),
+            polled: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn next(&self) -> u64 {
+        const GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+        let mut z = self
+            .state
+            .fetch_add(GAMMA, Ordering::Relaxed)
+            .wrapping_add(GAMMA);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+/// Named task, listed by `Runtime::dump_tasks()`
+struct Entry {
+    id: u64,
+    name: Arc<str>,
+    spawned: Instant,
+    last_poll: Mutex<Option<Instant>>,
+}
+
+impl Entry {
+    fn info(&self) -> TaskInfo {
+        TaskInfo {
+            id: self.id,
+            name: self.name.clone(),
+            spawned: self.spawned,
+            last_poll: *self.last_poll.lock().unwrap(),
+        }
+    }
+}
+
+/// Tasks of a runtime, shared with the tasks, stop handles and metrics
+pub(crate) struct Tasks {
+    mode: Mutex<Mode>,
+    pub(crate) reports: Mutex<Vec<Sender<StopReport>>>,
+    /// Wakers of tasks held back by a graceful stop
+    held: Mutex<Vec<Waker>>,
+    /// Number of live tracked tasks
+    pub(crate) alive: AtomicUsize,
+    /// High-water mark of `alive`
+    pub(crate) peak: AtomicUsize,
+    /// Number of live tasks spawned during a graceful stop
+    late: AtomicUsize,
+    pub(crate) spawned: AtomicUsize,
+    pub(crate) completed: AtomicUsize,
+    /// Tasks that panicked, whatever the panic policy
+    pub(crate) panics: AtomicUsize,
+    /// Timers of `actix_rt::time` that are not complete or dropped yet
+    pub(crate) timers: AtomicUsize,
+    /// Listeners of `actix_rt::signal` registered with the reactor
+    pub(crate) signal_listeners: AtomicUsize,
+    next_id: AtomicU64,
+    /// Live named tasks by id
+    named: Mutex<HashMap<u64, Arc<Entry>>>,
+    /// Set if stalled polls are monitored
+    pub(crate) heartbeat: Option<Arc<Heartbeat>>,
+    /// Task polls of the current executor tick
+    tick_polls: AtomicUsize,
+    /// Nanoseconds the runtime spent running and parked
+    busy_time: AtomicU64,
+    park_time: AtomicU64,
+    /// Panic of a task that is not raised yet
+    panicked: Mutex<Option<Box<dyn Any + Send>>>,
+    /// Set in deterministic mode
+    deterministic: Option<Deterministic>,
+    /// Thread of the runtime
+    thread: ThreadId,
+    hooks: Hooks,
+    unpark: Box<dyn Unpark>,
+}
+
+impl Tasks {
+    pub(crate) fn new(unpark: Box<dyn Unpark>, hooks: Hooks) -> Self {
+        Tasks {
+            mode: Mutex::new(Mode::Running),
+            reports: Mutex::new(Vec::new()),
+            held: Mutex::new(Vec::new()),
+            alive: AtomicUsize::new(0),
+            peak: AtomicUsize::new(0),
+            late: AtomicUsize::new(0),
+            spawned: AtomicUsize::new(0),
+            completed: AtomicUsize::new(0),
+            panics: AtomicUsize::new(0),
+            timers: AtomicUsize::new(0),
+            signal_listeners: AtomicUsize::new(0),
+            next_id: AtomicU64::new(1),
+            named: Mutex::new(HashMap::new()),
+            tick_polls: AtomicUsize::new(0),
+            busy_time: AtomicU64::new(0),
+            park_time: AtomicU64::new(0),
+            panicked: Mutex::new(None),
+            deterministic: hooks.seed.map(Deterministic::new),
+            thread: thread::current().id(),
+            heartbeat: hooks.stall.clone().map(|(threshold, hook)| {
+                Arc::new(Heartbeat::new(threshold, hook))
+            }),
+            hooks,
+            unpark,
+        }
+    }
+
+    /// Whether the current thread is the thread of the runtime.
+    pub(crate) fn is_runtime_thread(&self) -> bool {
+        thread::current().id() == self.thread
+    }
+
+    pub(crate) fn mode(&self) -> Mode {
+        *self.mode.lock().unwrap()
+    }
+
+    /// Request stop of `Runtime::run()`.
+    pub(crate) fn request(&self, mode: Mode) {
+        {
+            let mut current = self.mode.lock().unwrap();
+            match (*current, mode) {
+                (Mode::Stop, _) | (Mode::Graceful(_), Mode::Graceful(_)) => (),
+                _ => *current = mode,
+            }
+        }
+        self.unpark.unpark();
+    }
+
+    pub(crate) fn drop_policy(&self) -> DropPolicy {
+        self.hooks.drop_policy
+    }
+
+    /// Number of live tasks spawned before the stop request.
+    pub(crate) fn pending(&self) -> usize {
+        self.alive.load(Ordering::Acquire) - self.late.load(Ordering::Acquire)
+    }
+
+    /// Reset stop request, send reports and resume held tasks.
+    pub(crate) fn finish(&self) -> StopReport {
+        *self.mode.lock().unwrap() = Mode::Running;
+        let report = StopReport {
+            abandoned: self.alive.load(Ordering::Acquire),
+            forced: self.pending() != 0,
+        };
+        for tx in self.reports.lock().unwrap().drain(..) {
+            let _ = tx.send(report);
+        }
+        for waker in self.held.lock().unwrap().drain(..) {
+            waker.wake();
+        }
+        report
+    }
+
+    fn meta(&self, name: Option<Arc<str>>) -> TaskMeta {
+        TaskMeta::new(self.next_id.fetch_add(1, Ordering::Relaxed), name)
+    }
+
+    /// Snapshot of the live named tasks, ordered by id.
+    pub(crate) fn dump(&self) -> Vec<TaskInfo> {
+        let mut tasks: Vec<_> = self
+            .named
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.info())
+            .collect();
+        tasks.sort_by_key(|task| task.id);
+        tasks
+    }
+
+    /// Log the live named tasks and the number of unnamed ones.
+    pub(crate) fn log_pending(&self) {
+        let tasks = self.dump();
+        let now = Instant::now();
+        for task in &tasks {
+            log::warn!(
+                "Pending task `{}` #{}, spawned {:?} ago, last polled {:?} ago",
+                task.name,
+                task.id,
+                time::elapsed(now, task.spawned),
+                task.last_poll.map(|at| time::elapsed(now, at)),
+            );
+        }
+        let unnamed = self
+            .alive
+            .load(Ordering::Acquire)
+            .saturating_sub(tasks.len());
+        if unnamed != 0 {
+            log::warn!("{} pending unnamed tasks", unnamed);
+        }
+    }
+
+    fn poll_task<F: Future>(
+        &self,
+        meta: &TaskMeta,
+        future: Pin<&mut F>,
+        cx: &mut Context<'_>,
+    ) -> Poll<F::Output> {
+        let _budget = self.hooks.budget.map(task::set_budget);
+        match self.hooks.poll {
+            Some(ref hooks) => hooks.poll(meta, future, cx),
+            None => future.poll(cx),
+        }
+    }
+
+    /// Check whether the runtime accepts more tasks, it does not during a
+    /// graceful stop or at capacity.
+    pub(crate) fn check_spawn(&self) -> Result<(), SpawnErrorKind> {
+        if let Mode::Graceful(_) = self.mode() {
+            return Err(SpawnErrorKind::Stopping);
+        }
+        match self.hooks.max_tasks {
+            Some(max) if self.alive.load(Ordering::Acquire) >= max => {
+                Err(SpawnErrorKind::AtCapacity { limit: Some(max) })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wrap future into accounting adapter of the runtime.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if the runtime is at capacity.
+    pub(crate) fn track<F>(self: &Arc<Self>, future: F) -> Tracked<F> {
+        self.track_named(None, future)
+    }
+
+    /// Same as `track()`, the task is listed by `dump()` if it has a name.
+    pub(crate) fn track_named<F>(
+        self: &Arc<Self>,
+        name: Option<Arc<str>>,
+        future: F,
+    ) -> Tracked<F> {
+        match self.register(name, future) {
+            Ok(task) => task,
+            Err(_) => panic!("Runtime is at capacity of {:?} tasks", self.hooks.max_tasks),
+        }
+    }
+
+    /// Count a task poll, returns `false` if the tick budget is exhausted.
+    fn take_tick_poll(&self) -> bool {
+        match self.hooks.tick_budget {
+            Some(budget) if budget.max_task_polls != std::usize::MAX => {
+                self.tick_polls.fetch_add(1, Ordering::Relaxed) < budget.max_task_polls
+            }
+            _ => true,
+        }
+    }
+
+    /// Start budget of the next tick, called whenever the runtime parks.
+    pub(crate) fn next_tick(&self) {
+        self.tick_polls.store(0, Ordering::Relaxed);
+        self.log_polls();
+    }
+
+    /// Whether a woken task is moved to the back of the run queue instead
+    /// of being polled, drawn from the seed in deterministic mode.
+    fn defer_poll(&self) -> bool {
+        match self.deterministic {
+            Some(ref deterministic) => deterministic.next() & 1 == 1,
+            None => false,
+        }
+    }
+
+    /// Log ids of the tasks polled since the last call, in deterministic
+    /// mode only.
+    pub(crate) fn log_polls(&self) {
+        if let Some(ref deterministic) = self.deterministic {
+            let polled = mem::replace(&mut *deterministic.polled.lock().unwrap(), Vec::new());
+            if !polled.is_empty() {
+                log::trace!("Polled tasks {:?}", polled);
+            }
+        }
+    }
+
+    pub(crate) fn add_busy_time(&self, time: Duration) {
+        self.busy_time
+            .fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn add_park_time(&self, time: Duration) {
+        self.park_time
+            .fetch_add(time.as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn busy_time(&self) -> Duration {
+        Duration::from_nanos(self.busy_time.load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn park_time(&self) -> Duration {
+        Duration::from_nanos(self.park_time.load(Ordering::Relaxed))
+    }
+
+    /// Keep panic of a task, it is raised by `resume_panic()`.
+    pub(crate) fn set_panic(&self, err: Box<dyn Any + Send>) {
+        self.panicked.lock().unwrap().get_or_insert(err);
+    }
+
+    /// Raise panic of a task, called outside of the executor's tick.
+    ///
+    /// The executor leaks a task that unwinds through it and never gets
+    /// idle again, so panicked tasks complete and their panic is raised
+    /// afterwards.
+    pub(crate) fn resume_panic(&self) {
+        let err = self.panicked.lock().unwrap().take();
+        if let Some(err) = err {
+            panic::resume_unwind(err);
+        }
+    }
+
+    /// Wrap future into accounting adapter of the runtime, fails during a
+    /// graceful stop or if the runtime is at capacity.
+    pub(crate) fn try_track<F>(
+        self: &Arc<Self>,
+        future: F,
+    ) -> Result<Tracked<F>, SpawnErrorKind> {
+        self.try_track_named(None, future)
+    }
+
+    /// Same as `try_track()`, the task is listed by `dump()` if it has a name.
+    pub(crate) fn try_track_named<F>(
+        self: &Arc<Self>,
+        name: Option<Arc<str>>,
+        future: F,
+    ) -> Result<Tracked<F>, SpawnErrorKind> {
+        if let Mode::Graceful(_) = self.mode() {
+            return Err(SpawnErrorKind::Stopping);
+        }
+        self.register(name, future)
+    }
+
+    /// Wrap future into accounting adapter, fails if the runtime is at
+    /// capacity.
+    fn register<F>(
+        self: &Arc<Self>,
+        name: Option<Arc<str>>,
+        future: F,
+    ) -> Result<Tracked<F>, SpawnErrorKind> {
+        let max = self.hooks.max_tasks.unwrap_or(std::usize::MAX);
+        let mut alive = self.alive.load(Ordering::Acquire);
+        loop {
+            if alive >= max {
+                return Err(SpawnErrorKind::AtCapacity {
+                    limit: self.hooks.max_tasks,
+                });
+            }
+            match self.alive.compare_exchange_weak(
+                alive,
+                alive + 1,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break,
+                Err(actual) => alive = actual,
+            }
+        }
+        let mut peak = self.peak.load(Ordering::Relaxed);
+        while peak <= alive {
+            match self.peak.compare_exchange_weak(
+                peak,
+                alive + 1,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => break,
+                Err(actual) => peak = actual,
+            }
+        }
+        self.spawned.fetch_add(1, Ordering::Relaxed);
+
+        let late = match self.mode() {
+            Mode::Running => false,
+            _ => {
+                self.late.fetch_add(1, Ordering::AcqRel);
+                true
+            }
+        };
+        let meta = self.meta(name.clone());
+        // unnamed tasks are only counted
+        let entry = name.map(|name| {
+            let entry = Arc::new(Entry {
+                id: meta.id(),
+                name,
+                spawned: Instant::now(),
+                last_poll: Mutex::new(None),
+            });
+            self.named.lock().unwrap().insert(meta.id(), entry.clone());
+            entry
+        });
+        Ok(Tracked {
+            future,
+            meta,
+            entry,
+            late,
+            #[cfg(feature = "tracing")]
+            span: tracing::Span::current(),
+            context: self
+                .hooks
+                .context
+                .iter()
+                .map(|propagator| (propagator.capture)())
+                .collect(),
+            tasks: self.clone(),
+        })
+    }
+
+    /// Wrap root future of `block_on`, instrumented only if configured.
+    pub(crate) fn root<F>(self: &Arc<Self>, future: F) -> Root<F> {
+        let meta = match self.hooks.poll {
+            Some(ref hooks) if hooks.block_on => Some(self.meta(None)),
+            _ => None,
+        };
+        Root {
+            future,
+            meta,
+            tasks: self.clone(),
+        }
+    }
+}
+
+impl fmt::Debug for Tasks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Tasks")
+            .field("mode", &self.mode())
+            .field("alive", &self.alive.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+/// Task tracked by its runtime
+pub(crate) struct Tracked<F> {
+    future: F,
+    meta: TaskMeta,
+    /// Set for named tasks
+    entry: Option<Arc<Entry>>,
+    /// Spawned during a graceful stop, not polled until the stop finishes
+    late: bool,
+    /// Span of the spawner, entered around every poll
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+    /// Context captured on spawn, installed around every poll
+    context: Vec<Box<dyn Any + Send>>,
+    tasks: Arc<Tasks>,
+}
+
+impl<F: Future<Output = ()>> Future for Tracked<F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        // safe, `future` is never moved out of pinned task
+        let this = unsafe { self.get_unchecked_mut() };
+        let tasks = &this.tasks;
+
+        if this.late {
+            if tasks.mode() != Mode::Running {
+                tasks.held.lock().unwrap().push(cx.waker().clone());
+                return Poll::Pending;
+            }
+            this.late = false;
+            tasks.late.fetch_sub(1, Ordering::AcqRel);
+        }
+        if !tasks.take_tick_poll() || tasks.defer_poll() {
+            cx.waker().wake_by_ref();
+            return Poll::Pending;
+        }
+        if let Some(ref deterministic) = tasks.deterministic {
+            deterministic.polled.lock().unwrap().push(this.meta.id());
+        }
+        if let Some(ref entry) = this.entry {
+            *entry.last_poll.lock().unwrap() = Some(Instant::now());
+        }
+
+        let _busy = tasks
+            .heartbeat
+            .as_ref()
+            .map(|heartbeat| heartbeat.enter(this.meta.shared_name()));
+        #[cfg(feature = "tracing")]
+        let _span = this.span.enter();
+        let _context = EnteredContext::enter(&tasks.hooks.context, &mut this.context);
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+        let meta = &this.meta;
+        let polled =
+            panic::catch_unwind(AssertUnwindSafe(|| tasks.poll_task(meta, future, cx)));
+        let res = match polled {
+            Ok(res) => res,
+            Err(err) => {
+                tasks.panics.fetch_add(1, Ordering::Relaxed);
+                // runtime drops completed task
+                match tasks.hooks.panic_policy {
+                    PanicPolicy::Propagate => tasks.set_panic(err),
+                    ref policy => policy.handle(err),
+                }
+                return Poll::Ready(());
+            }
+        };
+        if res.is_ready() {
+            tasks.completed.fetch_add(1, Ordering::Relaxed);
+        }
+        res
+    }
+}
+
+impl<F> Drop for Tracked<F> {
+    fn drop(&mut self) {
+        if self.late {
+            self.tasks.late.fetch_sub(1, Ordering::AcqRel);
+        }
+        if self.entry.is_some() {
+            self.tasks.named.lock().unwrap().remove(&self.meta.id());
+        }
+        self.tasks.alive.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Keep span of the caller for a future that is spawned later or on another
+/// thread, no-op without the "tracing" feature.
+#[cfg(feature = "tracing")]
+pub(crate) fn in_current_span<F>(future: F) -> tracing::instrument::Instrumented<F> {
+    tracing::Instrument::in_current_span(future)
+}
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) fn in_current_span<F>(future: F) -> F {
+    future
+}
+
+/// Number of polls between turns of the reactor and the timer, see
+/// `Builder::tick_budget()`.
+///
+/// The default polls the root future of `block_on` once and every woken
+/// task once per tick.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TickBudget {
+    /// Polls of the root future in a row while it keeps waking itself
+    pub max_root_polls: usize,
+    /// Polls of spawned tasks, tasks over the budget are polled in the next
+    /// tick
+    pub max_task_polls: usize,
+}
+
+impl Default for TickBudget {
+    fn default() -> Self {
+        TickBudget {
+            max_root_polls: 1,
+            max_task_polls: std::usize::MAX,
+        }
+    }
+}
+
+/// Root future of `block_on`
+pub(crate) struct Root<F> {
+    future: F,
+    /// Set if the root future is instrumented
+    meta: Option<TaskMeta>,
+    tasks: Arc<Tasks>,
+}
+
+impl<F: Future> Future for Root<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // safe, `future` is never moved out of pinned root
+        let this = unsafe { self.get_unchecked_mut() };
+        let (future, meta, tasks) = (&mut this.future, &this.meta, &this.tasks);
+        tasks.resume_panic();
+        let _busy = tasks
+            .heartbeat
+            .as_ref()
+            .map(|heartbeat| heartbeat.enter(None));
+        let mut poll = |cx: &mut Context<'_>| {
+            let future = unsafe { Pin::new_unchecked(&mut *future) };
+            match *meta {
+                Some(ref meta) => tasks.poll_task(meta, future, cx),
+                None => future.poll(cx),
+            }
+        };
+
+        let max_polls = tasks.hooks.tick_budget.map_or(1, |b| b.max_root_polls);
+        if max_polls <= 1 {
+            return poll(cx);
+        }
+
+        // poll again right away while the root wakes itself
+        let woken = Arc::new(Woken {
+            flag: AtomicBool::new(false),
+            waker: cx.waker().clone(),
+        });
+        let waker = waker(woken.clone());
+        let mut cx = Context::from_waker(&waker);
+        for _ in 0..max_polls {
+            woken.flag.store(false, Ordering::Relaxed);
+            if let Poll::Ready(res) = poll(&mut cx) {
+                return Poll::Ready(res);
+            }
+            if !woken.flag.load(Ordering::Relaxed) {
+                break;
+            }
+        }
+        Poll::Pending
+    }
+}
+
+/// Waker of the root future that records wake ups
+struct Woken {
+    flag: AtomicBool,
+    waker: Waker,
+}
+
+impl ArcWake for Woken {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        arc_self.flag.store(true, Ordering::Relaxed);
+        arc_self.waker.wake_by_ref();
+    }
+}
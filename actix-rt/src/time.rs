@@ -0,0 +1,545 @@
+//! Timers driven by the timer of the current runtime.
+//!
+//! Functions of this module panic if called outside of an actix-rt
+//! `Runtime` context, e.g. outside of `Runtime::block_on()` or a spawned
+//! task, and on runtimes built with `Builder::enable_time(false)`.
+//!
+//! Deadlines are rounded up to the timer resolution of the runtime, see
+//! `Builder::timer_resolution()` and `Runtime::timer_resolution()`. With the
+//! default resolution of 1ms timers fire at most 1ms late, not counting the
+//! time the runtime is busy with other tasks.
+use std::cell::Cell;
+use std::error::Error;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use futures::{Stream, StreamExt};
+use tokio_timer::clock;
+
+use crate::runtime::{spawn_local, Current};
+use crate::tasks::Tasks;
+
+/// Granularity of the timer wheel
+pub(crate) const DEFAULT_RESOLUTION: Duration = Duration::from_millis(1);
+
+/// Longest timeout the timer wheel can track
+pub(crate) const MAX_TIMEOUT: Duration = Duration::from_millis((1 << 36) - 1);
+
+/// Largest accepted timer resolution
+const MAX_RESOLUTION: Duration = Duration::from_secs(60);
+
+/// Timer settings of a runtime
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TimerConfig {
+    pub(crate) resolution: Duration,
+    pub(crate) max_timeout: Duration,
+    /// Deadlines are rounded to multiples of the resolution since this instant
+    origin: Instant,
+}
+
+impl TimerConfig {
+    pub(crate) fn new(
+        resolution: Duration,
+        max_timeout: Duration,
+        origin: Instant,
+    ) -> io::Result<TimerConfig> {
+        if resolution < DEFAULT_RESOLUTION || resolution > MAX_RESOLUTION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "timer resolution must be between {:?} and {:?}",
+                    DEFAULT_RESOLUTION, MAX_RESOLUTION
+                ),
+            ));
+        }
+        if max_timeout < resolution || max_timeout > MAX_TIMEOUT {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "max timeout must be between the timer resolution and {:?}",
+                    MAX_TIMEOUT
+                ),
+            ));
+        }
+        Ok(TimerConfig {
+            resolution,
+            max_timeout,
+            origin,
+        })
+    }
+
+    /// Round `deadline` up to the resolution, clamped to the max timeout
+    fn deadline(&self, now: Instant, deadline: Instant) -> Instant {
+        let deadline = deadline.min(now + self.max_timeout);
+        if self.resolution == DEFAULT_RESOLUTION {
+            return deadline;
+        }
+        self.align(deadline)
+    }
+
+    /// Round `instant` up to a multiple of the resolution since the origin
+    pub(crate) fn align(&self, instant: Instant) -> Instant {
+        if instant <= self.origin {
+            return instant;
+        }
+        let resolution = self.resolution.as_nanos();
+        let elapsed = (instant - self.origin).as_nanos();
+        let ticks = (elapsed + resolution - 1) / resolution;
+        self.origin + Duration::from_nanos((ticks * resolution) as u64)
+    }
+}
+
+fn current() -> Current {
+    match Current::get() {
+        Some(current) => current,
+        None => panic!("actix-rt timers must be created within a Runtime context"),
+    }
+}
+
+/// Time elapsed from `earlier` to `now`, zero if `earlier` is later.
+pub(crate) fn elapsed(now: Instant, earlier: Instant) -> Duration {
+    if now > earlier {
+        now - earlier
+    } else {
+        Duration::from_secs(0)
+    }
+}
+
+/// Create future that completes after `duration` elapses.
+///
+/// # Panics
+///
+/// This function panics if called outside of a `Runtime` context.
+pub fn delay_for(duration: Duration) -> Delay {
+    delay_until(clock::now() + duration)
+}
+
+/// Create future that completes at `deadline`.
+///
+/// Deadlines further than the max timeout of the runtime are clamped to it.
+///
+/// # Panics
+///
+/// This function panics if called outside of a `Runtime` context.
+pub fn delay_until(deadline: Instant) -> Delay {
+    Delay::new(current(), deadline)
+}
+
+/// Create stream that yields every `period`, the first tick completes
+/// immediately.
+///
+/// # Panics
+///
+/// This function panics if `period` is zero or if called outside of a
+/// `Runtime` context.
+pub fn interval(period: Duration) -> Interval {
+    interval_at(clock::now(), period)
+}
+
+/// Create stream that yields every `period`, the first tick completes at
+/// `start`.
+///
+/// # Panics
+///
+/// This function panics if `period` is zero or if called outside of a
+/// `Runtime` context.
+pub fn interval_at(start: Instant, period: Duration) -> Interval {
+    assert!(
+        period > Duration::from_millis(0),
+        "`period` must be non-zero"
+    );
+
+    Interval {
+        delay: Delay::new(current(), start),
+        tick: start,
+        period,
+        behavior: MissedTickBehavior::default(),
+    }
+}
+
+/// Require `future` to complete within `duration`.
+///
+/// The future resolves with `Err(Elapsed)` if `future` does not complete in
+/// time. The timer starts on the first poll, so a timeout can be passed to
+/// `Runtime::block_on()` directly as well as awaited in a spawned task.
+///
+/// If `future` returns a `Result` itself the output is a nested `Result`.
+/// `Elapsed` converts into `io::Error` with `io::ErrorKind::TimedOut`, so
+/// for IO futures both layers can be propagated with `??`:
+///
+/// ```rust
+/// use std::io;
+/// use std::time::Duration;
+///
+/// use actix_rt::time;
+///
+/// async fn read() -> io::Result<u32> {
+///     Ok(1)
+/// }
+///
+/// async fn read_with_timeout() -> io::Result<u32> {
+///     time::timeout(Duration::from_secs(1), read()).await?
+/// }
+///
+/// # let mut rt = actix_rt::Runtime::new().unwrap();
+/// # assert_eq!(rt.block_on(read_with_timeout()).unwrap(), 1);
+/// ```
+///
+/// Other error types can implement `From<Elapsed>` the same way, or flatten
+/// the result with `.map_err(..).and_then(|res| res)`.
+///
+/// # Panics
+///
+/// The timeout panics if polled outside of a `Runtime` context.
+pub fn timeout<F: Future>(duration: Duration, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        deadline: Deadline::After(duration),
+        delay: None,
+    }
+}
+
+/// Require `future` to complete before `deadline`, see `timeout()`.
+///
+/// # Panics
+///
+/// The timeout panics if polled outside of a `Runtime` context.
+pub fn timeout_at<F: Future>(deadline: Instant, future: F) -> Timeout<F> {
+    Timeout {
+        future,
+        deadline: Deadline::At(deadline),
+        delay: None,
+    }
+}
+
+/// Future that completes at a deadline.
+///
+/// Created with `delay_for()` or `delay_until()`. Counted by
+/// `Runtime::pending_timers()` until it completes or gets dropped.
+#[derive(Debug)]
+pub struct Delay {
+    inner: tokio_timer::Delay,
+    config: TimerConfig,
+    tasks: Arc<Tasks>,
+    /// Whether the delay is counted as a pending timer
+    pending: bool,
+}
+
+impl Delay {
+    fn new(current: Current, deadline: Instant) -> Delay {
+        let config = current.timer_config;
+        let inner = match current.timer {
+            Some(ref timer) => timer.delay(config.deadline(clock::now(), deadline)),
+            None => panic!(
+                "actix-rt timers are disabled on this runtime, see `Builder::enable_time()`"
+            ),
+        };
+        current.tasks.timers.fetch_add(1, Ordering::Relaxed);
+        Delay {
+            inner,
+            config,
+            tasks: current.tasks,
+            pending: true,
+        }
+    }
+
+    /// Instant the delay completes at, rounded to the timer resolution.
+    pub fn deadline(&self) -> Instant {
+        self.inner.deadline()
+    }
+
+    /// Returns `true` if the deadline has elapsed.
+    pub fn is_elapsed(&self) -> bool {
+        self.inner.is_elapsed()
+    }
+
+    /// Reset the delay to complete at `deadline` instead, even if it has
+    /// completed already.
+    pub fn reset(&mut self, deadline: Instant) {
+        self.inner
+            .reset(self.config.deadline(clock::now(), deadline));
+        if !self.pending {
+            self.pending = true;
+            self.tasks.timers.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Future for Delay {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Pin::new(&mut self.inner).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        if self.pending {
+            self.pending = false;
+            self.tasks.timers.fetch_sub(1, Ordering::Relaxed);
+        }
+        Poll::Ready(())
+    }
+}
+
+impl Drop for Delay {
+    fn drop(&mut self) {
+        if self.pending {
+            self.tasks.timers.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+/// Future with a deadline, created with `timeout()` or `timeout_at()`.
+#[must_use = "futures do nothing unless polled"]
+#[derive(Debug)]
+pub struct Timeout<F> {
+    future: F,
+    deadline: Deadline,
+    /// Created on the first poll
+    delay: Option<Delay>,
+}
+
+#[derive(Debug)]
+enum Deadline {
+    After(Duration),
+    At(Instant),
+}
+
+impl<F> Timeout<F> {
+    /// Get reference to the wrapped future.
+    pub fn get_ref(&self) -> &F {
+        &self.future
+    }
+
+    /// Get mutable reference to the wrapped future.
+    pub fn get_mut(&mut self) -> &mut F {
+        &mut self.future
+    }
+
+    /// Consume the timeout and return the wrapped future.
+    pub fn into_inner(self) -> F {
+        self.future
+    }
+}
+
+impl<F: Future> Future for Timeout<F> {
+    type Output = Result<F::Output, Elapsed>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        // safe, `future` is never moved out of pinned timeout
+        let this = unsafe { self.get_unchecked_mut() };
+        let future = unsafe { Pin::new_unchecked(&mut this.future) };
+
+        // output of the future wins if both are ready
+        if let Poll::Ready(output) = future.poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        let deadline = &this.deadline;
+        let delay = this.delay.get_or_insert_with(|| match *deadline {
+            Deadline::After(duration) => delay_for(duration),
+            Deadline::At(deadline) => delay_until(deadline),
+        });
+        match Pin::new(&mut *delay).poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(Elapsed {
+                deadline: delay.deadline(),
+            })),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Error of `Timeout`, the deadline elapsed before the future completed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed {
+    deadline: Instant,
+}
+
+impl Elapsed {
+    /// Deadline that elapsed, rounded to the timer resolution.
+    pub fn deadline(&self) -> Instant {
+        self.deadline
+    }
+}
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "deadline has elapsed")
+    }
+}
+
+impl Error for Elapsed {}
+
+impl From<Elapsed> for io::Error {
+    fn from(err: Elapsed) -> io::Error {
+        io::Error::new(io::ErrorKind::TimedOut, err)
+    }
+}
+
+/// Behavior of `Interval` when ticks are missed, e.g. because the task
+/// was busy for longer than the period.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MissedTickBehavior {
+    /// Yield missed ticks immediately one after another until the interval
+    /// catches up with the schedule
+    Burst,
+    /// Schedule next tick `period` after the tick that got yielded late,
+    /// shifting the schedule
+    Delay,
+    /// Drop missed ticks and yield at the next tick of the original
+    /// schedule
+    Skip,
+}
+
+impl Default for MissedTickBehavior {
+    fn default() -> Self {
+        MissedTickBehavior::Burst
+    }
+}
+
+/// Stream that yields the scheduled instant of each tick.
+///
+/// Created with `interval()` or `interval_at()`.
+#[derive(Debug)]
+pub struct Interval {
+    delay: Delay,
+    /// Scheduled instant of the next tick, deadline of `delay` is rounded
+    tick: Instant,
+    period: Duration,
+    behavior: MissedTickBehavior,
+}
+
+impl Interval {
+    /// Period of the interval.
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Behavior for missed ticks, `MissedTickBehavior::Burst` by default.
+    pub fn missed_tick_behavior(&self) -> MissedTickBehavior {
+        self.behavior
+    }
+
+    /// Set behavior for missed ticks.
+    pub fn set_missed_tick_behavior(&mut self, behavior: MissedTickBehavior) {
+        self.behavior = behavior;
+    }
+
+    /// Complete at the next tick.
+    pub fn poll_tick(&mut self, cx: &mut Context<'_>) -> Poll<Instant> {
+        if Pin::new(&mut self.delay).poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        let tick = self.tick;
+        let now = clock::now();
+        let next = match self.behavior {
+            MissedTickBehavior::Burst => tick + self.period,
+            MissedTickBehavior::Delay => now + self.period,
+            MissedTickBehavior::Skip => {
+                let period = self.period.as_nanos();
+                let missed = elapsed(now, tick).as_nanos() / period;
+                tick + Duration::from_nanos(((missed + 1) * period) as u64)
+            }
+        };
+        self.tick = next;
+        self.delay.reset(next);
+        Poll::Ready(tick)
+    }
+}
+
+impl Stream for Interval {
+    type Item = Instant;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Instant>> {
+        self.poll_tick(cx).map(Some)
+    }
+}
+
+/// Cheap clock for callers that need the current time with coarse accuracy.
+///
+/// `now()` returns a cached instant that a task on the current runtime
+/// refreshes every `resolution`. The task is started by the first `now()`
+/// call, is shared by clones of the handle and exits once the time is not
+/// read for a whole period or all handles are dropped, so it does not keep
+/// `Runtime::run()` from returning.
+///
+/// Handles are not `Send`, they belong to the runtime of the current thread.
+#[derive(Clone, Debug)]
+pub struct LowResTime(Rc<LowResInner>);
+
+#[derive(Debug)]
+struct LowResInner {
+    resolution: Duration,
+    /// Cached time, set while the refresher runs
+    current: Cell<Option<Instant>>,
+    /// Whether the time got read since the last refresh
+    used: Cell<bool>,
+}
+
+impl LowResTime {
+    /// Create clock that is refreshed every `resolution`.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if `resolution` is zero.
+    pub fn with(resolution: Duration) -> LowResTime {
+        assert!(
+            resolution > Duration::from_millis(0),
+            "`resolution` must be non-zero"
+        );
+
+        LowResTime(Rc::new(LowResInner {
+            resolution,
+            current: Cell::new(None),
+            used: Cell::new(false),
+        }))
+    }
+
+    /// Refresh period of the clock.
+    pub fn resolution(&self) -> Duration {
+        self.0.resolution
+    }
+
+    /// Get cached time, it lags behind the runtime clock by less than the
+    /// resolution plus the timer resolution of the runtime.
+    ///
+    /// # Panics
+    ///
+    /// This function panics if it has to start the refresher outside of a
+    /// `Runtime` context.
+    pub fn now(&self) -> Instant {
+        let inner = &self.0;
+        inner.used.set(true);
+        if let Some(now) = inner.current.get() {
+            return now;
+        }
+
+        let now = clock::now();
+        let interval = interval_at(now + inner.resolution, inner.resolution);
+        inner.current.set(Some(now));
+        spawn_local(refresh(Rc::downgrade(inner), interval));
+        now
+    }
+}
+
+/// Refresh cached time of `LowResTime` on every tick while it is in use.
+async fn refresh(inner: Weak<LowResInner>, mut interval: Interval) {
+    while interval.next().await.is_some() {
+        let inner = match inner.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        if !inner.used.replace(false) {
+            // next read starts a new refresher
+            inner.current.set(None);
+            return;
+        }
+        inner.current.set(Some(clock::now()));
+    }
+}
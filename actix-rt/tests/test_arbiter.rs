@@ -0,0 +1,86 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::{Arbiter, System};
+
+#[test]
+fn test_arbiter_builder() {
+    let _sys = System::new("arbiters");
+    let polls = Arc::new(AtomicUsize::new(0));
+
+    let p = polls.clone();
+    let builder = Arbiter::builder()
+        .name("worker-{}")
+        .stack_size(256 * 1024)
+        .runtime_builder(System::builder().before_poll(move |_| {
+            p.fetch_add(1, Ordering::SeqCst);
+        }));
+
+    let (tx, rx) = mpsc::channel();
+    let arbiters: Vec<_> = (0..3).map(|_| builder.start()).collect();
+    for arb in &arbiters {
+        let tx = tx.clone();
+        arb.send(async move {
+            let _ = tx.send(thread::current().name().map(String::from));
+        });
+    }
+    let mut names: Vec<_> = (0..3).map(|_| rx.recv().unwrap().unwrap()).collect();
+    names.sort();
+    assert_eq!(names, ["worker-0", "worker-1", "worker-2"]);
+
+    for arb in arbiters {
+        arb.join().unwrap();
+    }
+    // each runtime is built with the hooks of the builder
+    assert!(polls.load(Ordering::SeqCst) >= 3);
+}
+
+#[test]
+fn test_arbiter_restart_on_panic() {
+    let mut sys = System::new("restarts");
+    let restarts = Arc::new(AtomicUsize::new(0));
+    let wait_restarts = |n| {
+        while restarts.load(Ordering::SeqCst) < n {
+            thread::sleep(Duration::from_millis(1));
+        }
+    };
+
+    let r = restarts.clone();
+    let arb = Arbiter::builder()
+        .name("supervised")
+        .restart_on_panic(true)
+        .on_restart(move || {
+            Arbiter::set_item(r.fetch_add(1, Ordering::SeqCst) + 1);
+        })
+        .start();
+
+    // panic of a task and of a function sent to the arbiter
+    arb.send(async { panic!("task") });
+    wait_restarts(1);
+    arb.exec_fn(|| panic!("function"));
+    wait_restarts(2);
+
+    let (name, restarted) = sys
+        .block_on(arb.exec(|| {
+            let name = thread::current().name().map(String::from);
+            (name, Arbiter::get_item(|n: &usize| *n))
+        }))
+        .unwrap();
+    assert_eq!(name, Some("supervised".to_owned()));
+    assert_eq!(restarted, 2);
+
+    // commands queued behind the panic fail
+    let (tx, rx) = mpsc::channel::<()>();
+    arb.exec_fn(move || {
+        let _ = rx.recv();
+        panic!("blocked");
+    });
+    let undelivered = arb.exec(|| ());
+    drop(tx);
+    assert!(sys.block_on(undelivered).is_err());
+    wait_restarts(3);
+
+    arb.join().unwrap();
+}
@@ -0,0 +1,479 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::{time, RemoteError, RunError, Runtime, SpawnErrorKind, StopReport, TurnResult};
+use futures::future::{self, FutureExt};
+
+/// Run `f` on a new thread, fail if it does not finish in time
+fn watchdog<F: FnOnce() + Send + 'static>(f: F) {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        f();
+        let _ = tx.send(());
+    });
+    rx.recv_timeout(Duration::from_secs(5))
+        .expect("nested call did not return");
+}
+
+#[test]
+fn test_nested_block_on() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(async {
+            let mut inner = Runtime::new().unwrap();
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| inner.block_on(async {})))
+                .unwrap_err()
+        });
+        let msg = err.downcast_ref::<String>().unwrap();
+        assert!(msg.contains("spawn the future"), "{}", msg);
+    });
+}
+
+#[test]
+fn test_nested_run() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut inner = Runtime::new().unwrap();
+            match inner.run() {
+                Err(RunError::Nested) => (),
+                _ => panic!("expected RunError::Nested"),
+            }
+            match inner.try_block_on(async {}) {
+                Err(RunError::Nested) => (),
+                _ => panic!("expected RunError::Nested"),
+            }
+            match inner.turn(None) {
+                Err(RunError::Nested) => (),
+                _ => panic!("expected RunError::Nested"),
+            }
+        });
+    });
+}
+
+#[test]
+fn test_block_on_all() {
+    let mut rt = Runtime::new().unwrap();
+    let done = Rc::new(Cell::new(0));
+
+    let d = done.clone();
+    let res = rt.block_on_all(
+        async move {
+            for i in 0..3 {
+                let d = d.clone();
+                actix_rt::spawn(async move {
+                    time::delay_for(Duration::from_millis(i * 5)).await;
+                    d.set(d.get() + 1);
+                })
+                .unwrap();
+            }
+            7
+        },
+        None,
+    );
+    assert_eq!(res.unwrap(), (7, 3));
+    assert_eq!(done.get(), 3);
+}
+
+#[test]
+fn test_block_on_all_timeout() {
+    let mut rt = Runtime::new().unwrap();
+
+    let res = rt.block_on_all(
+        async {
+            actix_rt::spawn(future::pending()).unwrap();
+        },
+        Some(Duration::from_millis(10)),
+    );
+    assert_eq!(res.unwrap(), ((), 0));
+    assert_eq!(rt.metrics().alive_tasks(), 1);
+}
+
+#[test]
+fn test_block_on_all_stop() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        let stop = rt.stop_handle();
+
+        let res = rt.block_on_all(
+            async move {
+                actix_rt::spawn(future::pending()).unwrap();
+                actix_rt::spawn(async move {
+                    time::delay_for(Duration::from_millis(5)).await;
+                    stop.stop();
+                })
+                .unwrap();
+            },
+            None,
+        );
+        assert_eq!(res.unwrap(), ((), 1));
+        assert_eq!(rt.metrics().alive_tasks(), 1);
+    });
+}
+
+#[test]
+fn test_stop_graceful() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        let stop = rt.stop_handle();
+
+        // in-flight task is drained
+        let done = Rc::new(Cell::new(false));
+        let flag = done.clone();
+        rt.spawn(async move {
+            time::delay_for(Duration::from_millis(10)).await;
+            flag.set(true);
+        });
+        let report = stop.stop_graceful(Duration::from_secs(5));
+        rt.run().unwrap();
+        assert!(done.get());
+        assert_eq!(
+            report.now_or_never(),
+            Some(Ok(StopReport {
+                abandoned: 0,
+                forced: false
+            }))
+        );
+
+        // task that outlives the timeout is left in the runtime
+        rt.spawn(future::pending());
+        let report = stop.stop_graceful(Duration::from_millis(10));
+        rt.run().unwrap();
+        assert_eq!(
+            report.now_or_never(),
+            Some(Ok(StopReport {
+                abandoned: 1,
+                forced: true
+            }))
+        );
+        assert_eq!(rt.metrics().alive_tasks(), 1);
+    });
+}
+
+#[test]
+fn test_run_graceful() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        let stop = rt.stop_handle();
+        let rejected = Rc::new(Cell::new(false));
+        let late = Rc::new(Cell::new(false));
+
+        // fallible spawns fail while the in-flight task drains
+        let flag = rejected.clone();
+        rt.spawn(async move {
+            time::delay_for(Duration::from_millis(10)).await;
+            let res = actix_rt::spawn(async {});
+            flag.set(res == Err(SpawnErrorKind::Stopping));
+        });
+        // task spawned after the stop request is held back, not forced
+        let stopped = stop.stop_graceful(Duration::from_secs(5));
+        let flag = late.clone();
+        rt.spawn(async move { flag.set(true) });
+
+        let report = rt.run_graceful(Duration::from_secs(5)).unwrap();
+        assert_eq!(
+            report,
+            StopReport {
+                abandoned: 1,
+                forced: false
+            }
+        );
+        assert_eq!(stopped.now_or_never(), Some(Ok(report)));
+        assert!(rejected.get());
+        assert!(!late.get());
+
+        // held task resumes once the runtime runs again
+        rt.run().unwrap();
+        assert!(late.get());
+        assert_eq!(rt.metrics().alive_tasks(), 0);
+    });
+}
+
+#[test]
+fn test_nested_block_on_all() {
+    watchdog(|| {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(async {
+            let mut inner = Runtime::new().unwrap();
+            match inner.block_on_all(async {}, None) {
+                Err(RunError::Nested) => (),
+                _ => panic!("expected RunError::Nested"),
+            }
+        });
+    });
+}
+
+#[test]
+fn test_block_on_catch() {
+    let mut rt = Runtime::new().unwrap();
+    let done = Rc::new(Cell::new(false));
+
+    let err = rt
+        .block_on_catch(async {
+            time::delay_for(Duration::from_millis(1)).await;
+            panic!("root");
+        })
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"root"));
+
+    // panic of a spawned task, other tasks survive
+    let d = done.clone();
+    rt.spawn(async move {
+        time::delay_for(Duration::from_millis(5)).await;
+        d.set(true);
+    });
+    let err = rt
+        .block_on_catch(async {
+            actix_rt::spawn(async { panic!("task") }).unwrap();
+            future::pending::<()>().await
+        })
+        .unwrap_err();
+    assert_eq!(err.downcast_ref::<&str>(), Some(&"task"));
+
+    assert_eq!(rt.block_on_catch(async { 7 }).unwrap(), 7);
+    assert_eq!(rt.block_on(async { 8 }), 8);
+    rt.run().unwrap();
+    assert!(done.get());
+}
+
+#[test]
+fn test_turn() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+
+    let res = rt.turn(Some(Duration::from_millis(0))).unwrap();
+    assert_eq!(
+        res,
+        TurnResult {
+            polled: false,
+            pending: 0
+        }
+    );
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        handle
+            .spawn(async move {
+                time::delay_for(Duration::from_millis(20)).await;
+                tx.send(()).unwrap();
+            })
+            .unwrap();
+    })
+    .join()
+    .unwrap();
+
+    // frame loop
+    let mut frames = 0;
+    while rt.turn(Some(Duration::from_millis(5))).unwrap().pending != 0 {
+        frames += 1;
+    }
+    assert!(rx.try_recv().is_ok());
+    assert!((2..20).contains(&frames), "{}", frames);
+
+    rt.spawn(async { panic!("frame") });
+    match rt.turn(None) {
+        Err(RunError::Panic(_)) => (),
+        _ => panic!("expected RunError::Panic"),
+    }
+    let res = rt.turn(Some(Duration::from_millis(0))).unwrap();
+    assert_eq!(
+        res,
+        TurnResult {
+            polled: false,
+            pending: 0
+        }
+    );
+}
+
+#[test]
+fn test_handle_block_on() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+
+    // runtime thread would wait for itself
+    let res = handle.block_on(async { 1 });
+    match res {
+        Err(RemoteError::Deadlock) => (),
+        _ => panic!("expected RemoteError::Deadlock"),
+    }
+
+    let (tx, rx) = futures::channel::oneshot::channel();
+    let caller = thread::spawn(move || {
+        let res = handle.block_on(async {
+            time::delay_for(Duration::from_millis(5)).await;
+            7
+        });
+        let elapsed =
+            handle.block_on_timeout(future::pending::<()>(), Duration::from_millis(5));
+        let _ = tx.send(());
+        (res.unwrap(), elapsed)
+    });
+    rt.block_on(rx).unwrap();
+
+    let (res, elapsed) = caller.join().unwrap();
+    assert_eq!(res, 7);
+    match elapsed {
+        Err(RemoteError::Elapsed) => (),
+        _ => panic!("expected RemoteError::Elapsed"),
+    }
+    // timed out task is aborted
+    rt.run().unwrap();
+    assert_eq!(rt.metrics().alive_tasks(), 0);
+}
+
+#[test]
+fn test_enter() {
+    let mut rt = Runtime::new().unwrap();
+    let polled = Rc::new(Cell::new(false));
+
+    // timers and spawns are bound to the runtime, tasks are not polled yet
+    let flag = polled.clone();
+    let delay = rt.enter(|| {
+        actix_rt::spawn(async move { flag.set(true) }).unwrap();
+        time::delay_for(Duration::from_millis(5))
+    });
+    assert!(!polled.get());
+    assert_eq!(rt.metrics().alive_tasks(), 1);
+
+    rt.block_on(delay);
+    assert!(polled.get());
+}
+
+#[test]
+fn test_on_start() {
+    use actix_rt::System;
+
+    let (tx, rx) = mpsc::channel();
+    let builder = System::builder().on_start(move || {
+        let _ = tx.send(thread::current().id());
+    });
+
+    // first runtime only, on the thread that builds it
+    let mut rt = builder.build_rt().unwrap();
+    assert_eq!(rx.try_recv(), Ok(thread::current().id()));
+    let _second = builder.build_rt().unwrap();
+    assert!(rx.try_recv().is_err());
+    assert_eq!(rt.block_on(async { 1 }), 1);
+
+    let err = System::builder()
+        .on_start(|| panic!("init"))
+        .build_rt()
+        .unwrap_err();
+    assert!(err.to_string().contains("init"), "{}", err);
+}
+
+#[test]
+#[cfg(all(feature = "core_affinity", target_os = "linux"))]
+fn test_cpu_affinity() {
+    use actix_rt::System;
+    use std::mem;
+
+    fn current_affinity() -> Vec<usize> {
+        let mut set: libc::cpu_set_t = unsafe { mem::zeroed() };
+        let res =
+            unsafe { libc::sched_getaffinity(0, mem::size_of::<libc::cpu_set_t>(), &mut set) };
+        assert_eq!(res, 0);
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|core| unsafe { libc::CPU_ISSET(*core, &set) })
+            .collect()
+    }
+
+    // affinity is applied to the thread that builds the runtime
+    thread::spawn(|| {
+        let core = current_affinity()[0];
+        let rt = System::builder().cpu_affinity(core).build_rt().unwrap();
+        assert_eq!(rt.cpu_affinity(), Some(&[core][..]));
+        assert_eq!(current_affinity(), vec![core]);
+    })
+    .join()
+    .unwrap();
+
+    // invalid core leaves the affinity unchanged
+    thread::spawn(|| {
+        let before = current_affinity();
+        let rt = System::builder().cpu_affinity(1 << 20).build_rt().unwrap();
+        assert_eq!(rt.cpu_affinity(), None);
+        assert_eq!(current_affinity(), before);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn test_with_park() {
+    use actix_rt::System;
+    use std::io;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{Arc, Condvar, Mutex};
+    use tokio_executor::park::{Park, Unpark};
+
+    #[derive(Clone, Default)]
+    struct Notify(Arc<(Mutex<bool>, Condvar)>);
+
+    impl Unpark for Notify {
+        fn unpark(&self) {
+            *(self.0).0.lock().unwrap() = true;
+            (self.0).1.notify_one();
+        }
+    }
+
+    struct CountingPark {
+        notify: Notify,
+        parks: Arc<AtomicUsize>,
+    }
+
+    impl Park for CountingPark {
+        type Unpark = Notify;
+        type Error = io::Error;
+
+        fn unpark(&self) -> Notify {
+            self.notify.clone()
+        }
+
+        fn park(&mut self) -> io::Result<()> {
+            self.park_timeout(Duration::from_secs(60))
+        }
+
+        fn park_timeout(&mut self, duration: Duration) -> io::Result<()> {
+            self.parks.fetch_add(1, Ordering::Relaxed);
+            let (ref notified, ref condvar) = *self.notify.0;
+            let mut notified = notified.lock().unwrap();
+            if !*notified {
+                notified = condvar.wait_timeout(notified, duration).unwrap().0;
+            }
+            *notified = false;
+            Ok(())
+        }
+    }
+
+    let parks = Arc::new(AtomicUsize::new(0));
+    let builder = System::builder().with_park(CountingPark {
+        notify: Notify::default(),
+        parks: parks.clone(),
+    });
+    let mut rt = builder.build_rt().unwrap();
+    assert!(rt.reactor_handle().is_none());
+
+    // timers and wakeups from other threads go through the custom park
+    let (tx, rx) = futures::channel::oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(Duration::from_millis(10));
+        let _ = tx.send(7);
+    });
+    let res = rt.block_on(async {
+        time::delay_for(Duration::from_millis(5)).await;
+        rx.await
+    });
+    assert_eq!(res, Ok(7));
+    assert!(parks.load(Ordering::Relaxed) > 0);
+    assert_eq!(
+        rt.reactor_stats().turns,
+        parks.load(Ordering::Relaxed) as u64
+    );
+
+    // park is owned by the first runtime
+    assert!(builder.build_rt().is_err());
+}
@@ -0,0 +1,85 @@
+#![cfg(feature = "compat")]
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use actix_rt::{time, Runtime, System};
+use futures::compat::Future01CompatExt;
+use futures01::future::{self as future01, ExecuteErrorKind, Executor};
+use futures01::sync::oneshot;
+use futures01::Future as Future01;
+
+/// futures 0.1 timer, fired by a helper thread
+fn delay_01(dur: Duration) -> impl Future01<Item = (), Error = ()> {
+    let (tx, rx) = oneshot::channel();
+    thread::spawn(move || {
+        thread::sleep(dur);
+        let _ = tx.send(());
+    });
+    rx.map_err(|_| ())
+}
+
+#[test]
+fn test_spawn_01() {
+    let mut rt = Runtime::new().unwrap();
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let start = Instant::now();
+
+    let ev = events.clone();
+    rt.spawn_01(delay_01(Duration::from_millis(20)).map(move |_| ev.borrow_mut().push("01")));
+    let ev = events.clone();
+    rt.spawn(async move {
+        time::delay_for(Duration::from_millis(5)).await;
+        ev.borrow_mut().push("03");
+    });
+    rt.run().unwrap();
+
+    assert_eq!(*events.borrow(), ["03", "01"]);
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+fn test_handle_executor() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+    let (tx, rx) = mpsc::channel();
+
+    let fut: Box<dyn Future01<Item = (), Error = ()> + Send> =
+        Box::new(future01::lazy(move || {
+            delay_01(Duration::from_millis(5)).map(move |_| tx.send(()).unwrap())
+        }));
+    handle.execute(fut).unwrap();
+    rt.run().unwrap();
+
+    assert!(rx.try_recv().is_ok());
+}
+
+#[test]
+fn test_handle_executor_shutdown() {
+    let handle = Runtime::new().unwrap().handle();
+    let err = handle.execute(future01::ok::<(), ()>(())).unwrap_err();
+    assert_eq!(err.kind(), ExecuteErrorKind::Shutdown);
+}
+
+#[test]
+fn test_handle_executor_at_capacity() {
+    let mut rt = System::builder().max_spawned_tasks(1).build_rt().unwrap();
+    let handle = rt.handle();
+    let (tx, rx) = oneshot::channel::<()>();
+    handle.execute(rx.map_err(|_| ())).unwrap();
+
+    // the refused future is handed back and can still run
+    let err = handle.execute(future01::ok::<(), ()>(())).unwrap_err();
+    assert_eq!(err.kind(), ExecuteErrorKind::NoCapacity);
+    let (done_tx, done_rx) = mpsc::channel();
+    let refused = err.into_future();
+    rt.block_on(async move {
+        let _ = tx.send(());
+        refused.compat().await.unwrap();
+        done_tx.send(()).unwrap();
+    });
+    assert!(done_rx.try_recv().is_ok());
+}
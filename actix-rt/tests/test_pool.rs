@@ -0,0 +1,94 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::{time, ArbiterPool, Balance, System};
+
+#[test]
+fn test_pool_round_robin() {
+    let _sys = System::new("pool");
+    let pool = ArbiterPool::new(3);
+    let threads = Arc::new(Mutex::new(Vec::new()));
+
+    let (tx, rx) = mpsc::channel();
+    for _ in 0..6 {
+        let (threads, tx) = (threads.clone(), tx.clone());
+        pool.spawn(async move {
+            threads.lock().unwrap().push(thread::current().id());
+            let _ = tx.send(());
+        });
+    }
+    for _ in 0..6 {
+        rx.recv().unwrap();
+    }
+
+    let mut threads = threads.lock().unwrap().clone();
+    threads.sort_by_key(|id| format!("{:?}", id));
+    threads.dedup();
+    assert_eq!(threads.len(), 3);
+    assert_eq!(pool.shutdown(Duration::from_secs(1)), 0);
+}
+
+#[test]
+fn test_pool_least_loaded() {
+    let _sys = System::new("pool");
+    let pool = ArbiterPool::new(2).balance(Balance::LeastLoaded);
+
+    // first arbiter stays busy, the rest goes to the second one
+    let (tx, rx) = futures::channel::oneshot::channel::<()>();
+    pool.spawn(async move {
+        let _ = rx.await;
+    });
+    let busy = Arc::new(Mutex::new(None));
+    let b = busy.clone();
+    pool.handles()[0].exec_fn(move || *b.lock().unwrap() = Some(thread::current().id()));
+
+    let (done_tx, done_rx) = mpsc::channel();
+    for _ in 0..4 {
+        let done_tx = done_tx.clone();
+        pool.spawn(async move {
+            let _ = done_tx.send(thread::current().id());
+        });
+        let id = done_rx.recv().unwrap();
+        assert_ne!(Some(id), *busy.lock().unwrap());
+
+        // counter of the finished task drops right after it reports
+        while pool.pending() != 1 {
+            thread::yield_now();
+        }
+    }
+
+    let _ = tx.send(());
+    assert_eq!(pool.shutdown(Duration::from_secs(1)), 0);
+}
+
+#[test]
+fn test_pool_broadcast_and_shutdown() {
+    let _sys = System::new("pool");
+    let pool = ArbiterPool::new(4);
+
+    let (tx, rx) = mpsc::channel();
+    let tx = Mutex::new(tx);
+    pool.broadcast_fn(move || {
+        let _ = tx.lock().unwrap().send(thread::current().id());
+    });
+    let mut ids: Vec<_> = (0..4)
+        .map(|_| format!("{:?}", rx.recv().unwrap()))
+        .collect();
+    ids.sort();
+    ids.dedup();
+    assert_eq!(ids.len(), 4);
+
+    // unfinished tasks are abandoned after the timeout
+    let completed = Arc::new(AtomicUsize::new(0));
+    for i in 0..2u64 {
+        let completed = completed.clone();
+        pool.spawn(async move {
+            time::delay_for(Duration::from_millis(10 + i * 1000)).await;
+            completed.fetch_add(1, Ordering::SeqCst);
+        });
+    }
+    assert_eq!(pool.shutdown(Duration::from_millis(200)), 1);
+    assert_eq!(completed.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,54 @@
+#![cfg(unix)]
+use actix_rt::signal::{self, unix};
+use actix_rt::Runtime;
+use futures::{future, FutureExt, StreamExt};
+
+#[test]
+fn test_ctrl_c() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // listeners deregister on drop
+        for _ in 0..10 {
+            let ctrl_c = signal::ctrl_c().unwrap();
+            assert!(ctrl_c.now_or_never().is_none());
+        }
+
+        let first = signal::ctrl_c().unwrap();
+        let second = signal::ctrl_c().unwrap();
+        unsafe { libc::raise(libc::SIGINT) };
+        future::join(first, second).await;
+    });
+}
+
+#[test]
+fn test_unix_signal() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        for _ in 0..3 {
+            let mut first = unix::signal(unix::SignalKind::user_defined1()).unwrap();
+            let mut second = unix::signal(unix::SignalKind::user_defined1()).unwrap();
+            unsafe { libc::raise(libc::SIGUSR1) };
+            assert_eq!(first.next().await, Some(()));
+            assert_eq!(second.next().await, Some(()));
+        }
+    });
+}
+
+#[test]
+fn test_signal_listeners() {
+    let mut rt = Runtime::new().unwrap();
+    let metrics = rt.metrics();
+
+    let (ctrl_c, signal) = rt.block_on(async {
+        let ctrl_c = signal::ctrl_c().unwrap();
+        let signal = unix::signal(unix::SignalKind::user_defined2()).unwrap();
+        assert_eq!(actix_rt::try_current().unwrap().signal_listeners(), 2);
+        (ctrl_c, signal)
+    });
+    assert_eq!(rt.signal_listeners(), 2);
+
+    drop(ctrl_c);
+    assert_eq!(metrics.signal_listeners(), 1);
+    drop(signal);
+    assert_eq!(metrics.signal_listeners(), 0);
+}
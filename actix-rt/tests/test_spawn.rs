@@ -0,0 +1,843 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_rt::{
+    task, time, DropPolicy, PanicPolicy, Runtime, SpawnErrorKind, System, TaskGroup, TickBudget,
+};
+use futures::future::{self, FutureExt};
+use futures::stream::{self, StreamExt};
+use futures::task::{LocalSpawn, LocalSpawnExt, Spawn, SpawnExt};
+
+#[test]
+fn test_handle_spawn() {
+    let mut rt = Runtime::new().unwrap();
+    let mut handle = rt.handle();
+
+    let res = SpawnExt::spawn_with_handle(&mut handle, async { 7 }).unwrap();
+    assert_eq!(rt.block_on(res), 7);
+    assert_eq!(rt.metrics().spawned_tasks(), 1);
+}
+
+#[test]
+fn test_handle_spawn_dyn() {
+    let mut rt = Runtime::new().unwrap();
+    let mut handle = rt.handle();
+    let spawner: &mut dyn Spawn = &mut handle;
+
+    let (tx, rx) = futures::channel::oneshot::channel();
+    spawner
+        .spawn(async move {
+            let _ = tx.send(1);
+        })
+        .unwrap();
+    assert!(spawner.status().is_ok());
+    assert_eq!(rt.block_on(rx), Ok(1));
+}
+
+#[test]
+fn test_spawn_after() {
+    let mut rt = Runtime::new().unwrap();
+    let started = Rc::new(Cell::new(0));
+
+    rt.pause_time();
+    let s = started.clone();
+    let late = rt.spawn_after(Duration::from_secs(30), async move {
+        s.set(s.get() + 1);
+        7
+    });
+    let s = started.clone();
+    let cancelled = rt.spawn_after(Duration::from_secs(30), async move {
+        s.set(s.get() + 1);
+    });
+    rt.run_until_idle(None);
+    assert_eq!(started.get(), 0);
+
+    cancelled.abort();
+    rt.advance(Duration::from_secs(30));
+    assert_eq!(started.get(), 1);
+    assert_eq!(rt.block_on(late).unwrap(), 7);
+    assert!(rt.block_on(cancelled).unwrap_err().is_cancelled());
+}
+
+#[test]
+fn test_handle_spawn_at() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+    let start = Instant::now();
+
+    let res = std::thread::spawn(move || {
+        let deadline = Instant::now() + Duration::from_millis(20);
+        let started = handle
+            .spawn_at(deadline, async move { Instant::now() >= deadline })
+            .unwrap();
+        let aborted = handle
+            .spawn_at(deadline, futures::future::pending::<()>())
+            .unwrap();
+        (started, aborted)
+    })
+    .join()
+    .unwrap();
+
+    let (started, aborted) = res;
+    assert!(rt.block_on(started).unwrap());
+    assert!(start.elapsed() >= Duration::from_millis(20));
+
+    // started task is aborted like a regular one
+    aborted.abort();
+    assert!(rt.block_on(aborted).unwrap_err().is_cancelled());
+    assert_eq!(rt.metrics().alive_tasks(), 0);
+}
+
+#[test]
+fn test_local_spawn_concurrent() {
+    let mut rt = Runtime::new().unwrap();
+    let spawner = rt.local_spawner();
+    let sum = Rc::new(Cell::new(0));
+
+    let s = sum.clone();
+    rt.block_on(stream::iter(1..=10).for_each_concurrent(None, move |i| {
+        // `Rc` makes the task `!Send`
+        let s = s.clone();
+        let n = Rc::new(i);
+        let res = spawner
+            .clone()
+            .spawn_local_with_handle(async move { *n * 2 })
+            .unwrap();
+        async move {
+            let n = res.await;
+            s.set(s.get() + n);
+        }
+    }));
+    assert_eq!(sum.get(), 110);
+    assert_eq!(rt.metrics().completed_tasks(), 10);
+}
+
+#[test]
+fn test_local_spawn_queued() {
+    let mut rt = Runtime::new().unwrap();
+    let mut spawner = rt.local_spawner();
+    let done = Rc::new(Cell::new(false));
+
+    // runtime is not running, task waits for the next `run`
+    let d = done.clone();
+    spawner.spawn_local(async move { d.set(true) }).unwrap();
+    assert!(!done.get());
+
+    rt.run().unwrap();
+    assert!(done.get());
+}
+
+#[test]
+fn test_local_spawn_shutdown() {
+    let rt = Runtime::new().unwrap();
+    let mut spawner = rt.local_spawner();
+    drop(rt);
+
+    assert!(spawner.status_local().is_err());
+    assert!(spawner.spawn_local(async {}).is_err());
+}
+
+#[test]
+fn test_spawn_current() {
+    // outside of a runtime
+    assert_eq!(actix_rt::spawn(async {}), Err(SpawnErrorKind::Shutdown));
+    assert!(actix_rt::try_current().is_none());
+
+    let mut rt = Runtime::new().unwrap();
+    let res = rt.block_on(async {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        actix_rt::spawn(async move {
+            let _ = tx.send(1);
+        })
+        .unwrap();
+        assert!(actix_rt::try_current().is_some());
+        rx.await
+    });
+    assert_eq!(res, Ok(1));
+}
+
+#[test]
+fn test_yield_now() {
+    let mut rt = Runtime::new().unwrap();
+    let done = Rc::new(Cell::new(false));
+
+    let d = done.clone();
+    rt.spawn(async move { d.set(true) });
+    let d = done.clone();
+    let res = rt.block_on(async move {
+        let before = d.get();
+        task::yield_now().await;
+        (before, d.get())
+    });
+    assert_eq!(res, (false, true));
+}
+
+#[test]
+fn test_cooperative_budget() {
+    let mut rt = System::builder().cooperative_budget(16).build_rt().unwrap();
+    let done = Rc::new(Cell::new(false));
+    let polls = Rc::new(Cell::new(0u64));
+
+    // busy task never waits on anything but its budget
+    let (d, p) = (done.clone(), polls.clone());
+    rt.spawn(async move {
+        while !d.get() {
+            p.set(p.get() + 1);
+            task::consume_budget().await;
+        }
+    });
+    rt.block_on(async { time::delay_for(Duration::from_millis(10)).await });
+    done.set(true);
+    rt.run().unwrap();
+    assert!(polls.get() > 16);
+}
+
+#[test]
+fn test_tick_budget_root() {
+    let budget = TickBudget {
+        max_root_polls: 8,
+        ..TickBudget::default()
+    };
+    let mut rt = System::builder().tick_budget(budget).build_rt().unwrap();
+
+    let polls = rt.block_on(async {
+        let fired = Rc::new(Cell::new(false));
+        let (f, delay) = (fired.clone(), time::delay_for(Duration::from_millis(1)));
+        actix_rt::spawn(async move {
+            delay.await;
+            f.set(true);
+        })
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(5));
+
+        // chatty root gets polled repeatedly, but the timer fires within
+        // a few ticks
+        let mut polls = 0;
+        while !fired.get() {
+            polls += 1;
+            task::yield_now().await;
+        }
+        polls
+    });
+    assert!((8..=3 * 8).contains(&polls), "{}", polls);
+}
+
+#[test]
+fn test_tick_budget_tasks() {
+    let budget = TickBudget {
+        max_task_polls: 3,
+        ..TickBudget::default()
+    };
+    let mut rt = System::builder().tick_budget(budget).build_rt().unwrap();
+    let done = Rc::new(Cell::new(false));
+
+    let d = done.clone();
+    let polled = rt.block_on(async move {
+        let p = Rc::new(Cell::new(0));
+        let delay = time::delay_for(Duration::from_millis(1));
+        for _ in 0..10 {
+            let (p, d) = (p.clone(), d.clone());
+            actix_rt::spawn(async move {
+                while !d.get() {
+                    p.set(p.get() + 1);
+                    task::yield_now().await;
+                }
+            })
+            .unwrap();
+        }
+        std::thread::sleep(Duration::from_millis(5));
+
+        // timer fires after the first tick of the chatty tasks
+        delay.await;
+        p.get()
+    });
+    assert!(polled <= 3, "{}", polled);
+
+    // tasks over the budget are polled in later ticks
+    done.set(true);
+    rt.run().unwrap();
+    assert_eq!(rt.metrics().alive_tasks(), 0);
+}
+
+#[test]
+fn test_stall_warning() {
+    let stalls = Arc::new(Mutex::new(Vec::new()));
+    let s = stalls.clone();
+    let mut rt = System::builder()
+        .stall_warning(Duration::from_millis(20), move |stalled, task| {
+            s.lock().unwrap().push((stalled, task.map(String::from)));
+        })
+        .build_rt()
+        .unwrap();
+
+    // waiting on a timer is not a stall
+    rt.block_on(async { time::delay_for(Duration::from_millis(50)).await });
+    assert!(stalls.lock().unwrap().is_empty());
+
+    rt.spawn_named("blocker", async {
+        std::thread::sleep(Duration::from_millis(100));
+    });
+    rt.run().unwrap();
+
+    let stalls = stalls.lock().unwrap();
+    assert_eq!(stalls.len(), 1);
+    assert!(stalls[0].0 >= Duration::from_millis(20));
+    assert_eq!(stalls[0].1, Some("blocker".to_owned()));
+}
+
+#[test]
+fn test_busy_time() {
+    let mut rt = Runtime::new().unwrap();
+    let metrics = rt.metrics();
+
+    rt.block_on(async {
+        time::delay_for(Duration::from_millis(20)).await;
+        std::thread::sleep(Duration::from_millis(20));
+    });
+    assert!(metrics.park_time() >= Duration::from_millis(15));
+    assert!(metrics.busy_time() >= Duration::from_millis(20));
+
+    // time outside of the runtime is not accounted
+    let (busy, parked) = (metrics.busy_time(), metrics.park_time());
+    std::thread::sleep(Duration::from_millis(20));
+    rt.block_on(async {});
+    assert!(metrics.busy_time() - busy < Duration::from_millis(10));
+    assert_eq!(metrics.park_time(), parked);
+}
+
+#[test]
+fn test_utilization_callback() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let r = reports.clone();
+    let mut rt = System::builder()
+        .utilization_callback(Duration::from_millis(20), move |ratio| {
+            r.lock().unwrap().push(ratio)
+        })
+        .build_rt()
+        .unwrap();
+
+    // busy, then idle for a few intervals
+    rt.block_on(async {
+        std::thread::sleep(Duration::from_millis(25));
+        task::yield_now().await;
+        let (_tx, rx) = futures::channel::oneshot::channel::<()>();
+        let _ = time::timeout(Duration::from_millis(100), rx).await;
+    });
+
+    let reports = reports.lock().unwrap();
+    assert!(reports.len() >= 3, "{:?}", reports);
+    assert!(reports[0] > 0.9, "{:?}", reports);
+    assert!(reports[reports.len() - 1] < 0.1, "{:?}", reports);
+}
+
+thread_local! {
+    static REQUEST: Cell<u32> = Cell::new(0);
+}
+
+#[test]
+fn test_task_context() {
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let (l1, l2) = (log.clone(), log.clone());
+    let mut rt = System::builder()
+        .task_context(|| REQUEST.with(Cell::get), |id| REQUEST.with(|r| r.set(id)))
+        .task_context(
+            || (),
+            move |_| l1.lock().unwrap().push(REQUEST.with(Cell::get)),
+        )
+        .task_context(|| (), move |_| l2.lock().unwrap().push(100))
+        .build_rt()
+        .unwrap();
+
+    let seen = Rc::new(RefCell::new(Vec::new()));
+    for id in 1..=2 {
+        REQUEST.with(|r| r.set(id));
+        let seen = seen.clone();
+        rt.spawn(async move {
+            seen.borrow_mut().push(REQUEST.with(Cell::get));
+            task::yield_now().await;
+            // changes of the task stay within the task
+            REQUEST.with(|r| r.set(id * 10));
+            task::yield_now().await;
+            seen.borrow_mut().push(REQUEST.with(Cell::get));
+        });
+    }
+    REQUEST.with(|r| r.set(7));
+    rt.run().unwrap();
+
+    assert_eq!(*seen.borrow(), [1, 2, 10, 20]);
+    assert_eq!(REQUEST.with(Cell::get), 7);
+
+    // entered in registration order, left in reverse
+    let log = log.lock().unwrap();
+    assert_eq!(log[..4], [1, 100, 100, 1]);
+}
+
+#[test]
+fn test_deterministic() {
+    fn run(seed: Option<u64>) -> Vec<(usize, usize)> {
+        let builder = System::builder();
+        let builder = match seed {
+            Some(seed) => builder.deterministic(seed),
+            None => builder,
+        };
+        let mut rt = builder.build_rt().unwrap();
+        let order = Rc::new(RefCell::new(Vec::new()));
+        for task in 0..5 {
+            let order = order.clone();
+            rt.spawn(async move {
+                for step in 0..3 {
+                    order.borrow_mut().push((task, step));
+                    task::yield_now().await;
+                }
+            });
+        }
+        rt.run().unwrap();
+        let order = order.borrow().clone();
+        order
+    }
+
+    let order = run(Some(7));
+    assert_eq!(order, run(Some(7)));
+    let mut sorted = order.clone();
+    sorted.sort();
+    assert_eq!(sorted.len(), 15);
+    sorted.dedup();
+    assert_eq!(sorted.len(), 15);
+
+    // seeds lead to different interleavings
+    let default = run(None);
+    assert!((0..10).any(|seed| run(Some(seed)) != default));
+}
+
+#[test]
+fn test_panic_policy() {
+    // logged, the panicked task is dropped and others keep running
+    let mut rt = System::builder()
+        .task_panic_policy(PanicPolicy::CatchAndLog)
+        .build_rt()
+        .unwrap();
+    rt.spawn(async { panic!("logged") });
+    let (tx, rx) = futures::channel::oneshot::channel();
+    rt.spawn(async move {
+        let _ = tx.send(1);
+    });
+    assert_eq!(rt.block_on(rx), Ok(1));
+    assert_eq!(rt.metrics().panicked_tasks(), 1);
+    assert_eq!(rt.metrics().completed_tasks(), 1);
+    assert_eq!(rt.metrics().alive_tasks(), 0);
+
+    // swallowed, the callback gets the payload
+    let payloads = Arc::new(Mutex::new(Vec::new()));
+    let notified = payloads.clone();
+    let mut rt = System::builder()
+        .task_panic_policy(PanicPolicy::notify(move |err| {
+            let msg = *err.downcast::<&str>().unwrap();
+            notified.lock().unwrap().push(msg);
+        }))
+        .build_rt()
+        .unwrap();
+    rt.spawn(async { panic!("notified") });
+    rt.run().unwrap();
+    assert_eq!(*payloads.lock().unwrap(), vec!["notified"]);
+    assert_eq!(rt.metrics().panicked_tasks(), 1);
+    assert_eq!(rt.metrics().completed_tasks(), 0);
+
+    // re-raised from block_on
+    let mut rt = Runtime::new().unwrap();
+    let err = rt
+        .block_on_catch(async {
+            actix_rt::spawn(async { panic!("propagated") }).unwrap();
+            time::delay_for(Duration::from_millis(10)).await;
+        })
+        .unwrap_err();
+    assert_eq!(*err.downcast::<&str>().unwrap(), "propagated");
+    assert_eq!(rt.metrics().panicked_tasks(), 1);
+}
+
+#[test]
+fn test_drop_policy() {
+    // unfinished tasks are dropped without running
+    let done = Arc::new(Mutex::new(Vec::new()));
+    let rt = System::builder()
+        .on_drop(DropPolicy::Discard)
+        .build_rt()
+        .unwrap();
+    let log = done.clone();
+    rt.handle()
+        .spawn(async move {
+            time::delay_for(Duration::from_millis(10)).await;
+            log.lock().unwrap().push("discard");
+        })
+        .unwrap();
+    drop(rt);
+    assert!(done.lock().unwrap().is_empty());
+
+    // runtime keeps running the tasks on drop
+    let rt = System::builder()
+        .on_drop(DropPolicy::RunToCompletion {
+            timeout: Duration::from_secs(5),
+        })
+        .build_rt()
+        .unwrap();
+    let log = done.clone();
+    rt.handle()
+        .spawn(async move {
+            time::delay_for(Duration::from_millis(10)).await;
+            log.lock().unwrap().push("completed");
+        })
+        .unwrap();
+    drop(rt);
+    assert_eq!(*done.lock().unwrap(), vec!["completed"]);
+
+    // dropping with unfinished tasks panics
+    let rt = System::builder()
+        .on_drop(DropPolicy::Panic)
+        .build_rt()
+        .unwrap();
+    rt.handle().spawn(future::pending()).unwrap();
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(rt))).is_err());
+}
+
+#[test]
+fn test_max_spawned_tasks() {
+    let mut rt = System::builder().max_spawned_tasks(2).build_rt().unwrap();
+    let (tx, rx) = futures::channel::oneshot::channel::<()>();
+    rt.try_spawn(async {}).unwrap();
+    rt.try_spawn(async move {
+        let _ = rx.await;
+    })
+    .unwrap();
+
+    match rt.try_spawn(async {}) {
+        Err(SpawnErrorKind::AtCapacity { limit }) => assert_eq!(limit, Some(2)),
+        res => panic!("expected SpawnErrorKind::AtCapacity, got {:?}", res),
+    }
+    assert!(rt.handle().spawn(async {}).is_err());
+
+    // finished tasks free their slots
+    rt.run_until_idle(None);
+    assert_eq!(rt.metrics().alive_tasks(), 1);
+    rt.try_spawn(async {}).unwrap();
+    let _ = tx.send(());
+    rt.run().unwrap();
+    rt.try_spawn(async {}).unwrap();
+    rt.try_spawn(async {}).unwrap();
+    assert_eq!(rt.metrics().peak_alive_tasks(), 2);
+}
+
+#[test]
+fn test_task_group_join() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let group = TaskGroup::new();
+        assert_eq!(group.join().now_or_never(), Some(()));
+
+        let finished = Rc::new(Cell::new(0));
+        for delay in 1..3 {
+            let finished = finished.clone();
+            group
+                .spawn(async move {
+                    time::delay_for(Duration::from_millis(delay * 5)).await;
+                    finished.set(finished.get() + 1);
+                })
+                .unwrap();
+        }
+        assert_eq!(group.join().now_or_never(), None);
+        group.join().await;
+        assert_eq!(finished.get(), 2);
+    });
+}
+
+#[test]
+fn test_task_group_cancel() {
+    struct Guard(Rc<Cell<bool>>);
+
+    impl Drop for Guard {
+        fn drop(&mut self) {
+            self.0.set(true);
+        }
+    }
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let group = TaskGroup::new();
+        let dropped = Rc::new(Cell::new(false));
+        let guard = Guard(dropped.clone());
+        group
+            .spawn(async move {
+                let _guard = guard;
+                future::pending::<()>().await
+            })
+            .unwrap();
+        task::yield_now().await;
+        assert!(!dropped.get());
+
+        // pending member is dropped at its next poll
+        group.cancel();
+        assert!(group.is_cancelled());
+        group.join().await;
+        assert!(dropped.get());
+
+        assert_eq!(group.spawn(async {}), Err(SpawnErrorKind::Shutdown));
+        assert_eq!(group.join().now_or_never(), Some(()));
+    });
+}
+
+#[test]
+fn test_handle_spawn_blocking() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+    let runtime = std::thread::current().id();
+
+    let res =
+        rt.block_on(handle.spawn_blocking(move || std::thread::current().id() != runtime));
+    assert!(res.unwrap());
+
+    // handle hands out pool futures on any thread
+    let res = std::thread::spawn(move || handle.spawn_blocking(|| 7))
+        .join()
+        .unwrap();
+    assert_eq!(rt.block_on(res).unwrap(), 7);
+}
+
+#[test]
+fn test_on_task_poll() {
+    let polls = Arc::new(Mutex::new(Vec::new()));
+    let hook = polls.clone();
+    let mut rt = System::builder()
+        .on_task_poll(move |elapsed| hook.lock().unwrap().push(elapsed))
+        .build_rt()
+        .unwrap();
+
+    rt.spawn(async {
+        std::thread::sleep(Duration::from_millis(10));
+        task::yield_now().await;
+    });
+    rt.spawn(async {});
+    rt.run().unwrap();
+
+    // first task is polled twice, its first poll is the slow one
+    let polls = polls.lock().unwrap();
+    assert_eq!(polls.len(), 3);
+    assert!(polls
+        .iter()
+        .any(|elapsed| *elapsed >= Duration::from_millis(10)));
+
+    let metrics = rt.metrics();
+    assert_eq!(metrics.spawned_tasks(), 2);
+    assert_eq!(metrics.completed_tasks(), 2);
+    assert_eq!(metrics.alive_tasks(), 0);
+}
+
+#[test]
+fn test_handle_run_fn() {
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+    let runtime = std::thread::current().id();
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    // closure runs on the runtime thread, its future may hold `Rc` state
+    std::thread::spawn(move || {
+        handle
+            .run_fn(move || {
+                let state = Rc::new(Cell::new(std::thread::current().id()));
+                async move {
+                    task::yield_now().await;
+                    let _ = tx.send(state.get());
+                }
+            })
+            .unwrap();
+    })
+    .join()
+    .unwrap();
+    assert_eq!(rt.block_on(rx), Ok(runtime));
+
+    let handle = rt.handle();
+    drop(rt);
+    assert_eq!(handle.run_fn(|| async {}), Err(SpawnErrorKind::Shutdown));
+}
+
+#[test]
+fn test_dump_tasks() {
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = futures::channel::oneshot::channel::<()>();
+    rt.spawn_named("listener", async move {
+        let _ = rx.await;
+    });
+    rt.spawn_named("idle", future::pending());
+    rt.spawn(future::pending());
+    rt.handle()
+        .spawn_named("remote", future::pending())
+        .unwrap();
+
+    // unnamed tasks are not listed, nothing is polled yet
+    let tasks = rt.dump_tasks();
+    let names: Vec<_> = tasks.iter().map(|task| &*task.name).collect();
+    assert_eq!(names, vec!["listener", "idle", "remote"]);
+    assert!(tasks.windows(2).all(|pair| pair[0].id < pair[1].id));
+    assert!(tasks.iter().all(|task| task.last_poll.is_none()));
+
+    rt.run_until_idle(None);
+    assert!(rt.dump_tasks().iter().all(|task| task.last_poll.is_some()));
+    rt.log_pending_tasks();
+
+    // completed tasks leave the dump
+    let _ = tx.send(());
+    rt.run_until_idle(None);
+    let names: Vec<_> = rt
+        .dump_tasks()
+        .into_iter()
+        .map(|task| task.name.to_string())
+        .collect();
+    assert_eq!(names, vec!["idle", "remote"]);
+    assert_eq!(rt.handle().dump_tasks().len(), 2);
+}
+
+#[test]
+fn test_task_slab_reuse() {
+    let mut rt = System::builder().task_slab_capacity(4).build_rt().unwrap();
+    assert_eq!(rt.task_slab_size(), 4);
+
+    // nodes of completed tasks are reused
+    for _ in 0..100 {
+        rt.spawn(async {});
+        rt.run().unwrap();
+    }
+    rt.block_on(async {
+        for _ in 0..100 {
+            actix_rt::spawn(async {}).unwrap();
+            task::yield_now().await;
+        }
+    });
+    assert_eq!(rt.task_slab_size(), 4);
+    assert_eq!(rt.metrics().completed_tasks(), 200);
+
+    // slab grows with the number of live tasks
+    for _ in 0..6 {
+        rt.spawn(future::pending());
+    }
+    rt.run_until_idle(None);
+    assert_eq!(rt.task_slab_size(), 6);
+}
+
+#[test]
+fn test_task_slab_drop_order() {
+    struct Dropped(u32, Rc<RefCell<Vec<u32>>>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.1.borrow_mut().push(self.0);
+        }
+    }
+
+    let dropped = Rc::new(RefCell::new(Vec::new()));
+    let mut rt = Runtime::new().unwrap();
+    for i in 0..3 {
+        let d = Dropped(i, dropped.clone());
+        rt.spawn(async move {
+            let _d = d;
+            future::pending::<()>().await
+        });
+    }
+    let d = Dropped(3, dropped.clone());
+    rt.spawn(async move {
+        let _d = d;
+    });
+
+    // completed task is dropped right away, pending ones with the runtime
+    rt.run_until_idle(None);
+    assert_eq!(*dropped.borrow(), vec![3]);
+    drop(rt);
+    assert_eq!(*dropped.borrow(), vec![3, 0, 1, 2]);
+}
+
+#[test]
+fn test_task_slab_stale_waker() {
+    let mut rt = Runtime::new().unwrap();
+    let waker = Rc::new(RefCell::new(None));
+
+    let w = waker.clone();
+    rt.spawn(future::poll_fn(move |cx| {
+        *w.borrow_mut() = Some(cx.waker().clone());
+        std::task::Poll::Ready(())
+    }));
+    rt.run_until_idle(None);
+    let stale = waker.borrow_mut().take().unwrap();
+
+    // next task takes the node of the completed one
+    let polls = Rc::new(Cell::new(0));
+    let p = polls.clone();
+    rt.spawn(future::poll_fn(move |_| {
+        p.set(p.get() + 1);
+        std::task::Poll::<()>::Pending
+    }));
+    rt.run_until_idle(None);
+    assert_eq!(polls.get(), 1);
+    assert_eq!(rt.task_slab_size(), 1);
+
+    // waker that outlived its task does not wake the next one
+    std::thread::spawn(move || stale.wake()).join().unwrap();
+    rt.run_until_idle(None);
+    assert_eq!(polls.get(), 1);
+}
+
+#[test]
+fn test_task_slab_panic() {
+    struct PanicOnDrop;
+
+    impl Drop for PanicOnDrop {
+        fn drop(&mut self) {
+            panic!("drop");
+        }
+    }
+
+    // completes right away, panics once the runtime drops it
+    struct Completed(PanicOnDrop);
+
+    impl std::future::Future for Completed {
+        type Output = ();
+
+        fn poll(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<()> {
+            std::task::Poll::Ready(())
+        }
+    }
+
+    fn check<F>(mut rt: Runtime, first: F, msg: &str)
+    where
+        F: std::future::Future<Output = ()> + 'static,
+    {
+        let polled = Rc::new(Cell::new(0));
+        rt.spawn(first);
+        for _ in 0..3 {
+            let p = polled.clone();
+            rt.spawn(async move { p.set(p.get() + 1) });
+        }
+
+        // rest of the batch is polled, panic is raised after the tick
+        let err = rt.block_on_catch(task::yield_now()).unwrap_err();
+        assert_eq!(*err.downcast::<&str>().unwrap(), msg);
+        assert_eq!(polled.get(), 3);
+
+        // later spawns are still polled and the runtime gets idle
+        let p = polled.clone();
+        rt.spawn(async move { p.set(p.get() + 1) });
+        rt.run().unwrap();
+        assert_eq!(polled.get(), 4);
+        assert_eq!(rt.metrics().alive_tasks(), 0);
+    }
+
+    // panicking drop of a completed task
+    check(Runtime::new().unwrap(), Completed(PanicOnDrop), "drop");
+
+    // panicking callback of the panic policy
+    let rt = System::builder()
+        .task_panic_policy(PanicPolicy::notify(|_| panic!("callback")))
+        .build_rt()
+        .unwrap();
+    check(rt, async { panic!("task") }, "callback");
+}
@@ -0,0 +1,168 @@
+use std::panic;
+use std::sync::{mpsc, Mutex, MutexGuard};
+use std::thread;
+
+use actix_rt::System;
+use futures::channel::oneshot;
+
+/// Systems are visible from every thread, tests must not overlap
+fn serial() -> MutexGuard<'static, ()> {
+    lazy_static::lazy_static! {
+        static ref SERIAL: Mutex<()> = Mutex::new(());
+    }
+    SERIAL.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+#[test]
+fn test_current_from_non_runtime_thread() {
+    let _serial = serial();
+    let mut sys = System::new("main");
+    let id = System::current().id();
+
+    // only one system per thread
+    assert!(panic::catch_unwind(|| System::new("second")).is_err());
+    assert_eq!(System::current().id(), id);
+
+    let (tx, rx) = mpsc::channel();
+    let (arb_tx, arb_rx) = oneshot::channel();
+    thread::spawn(move || {
+        let sys = System::current();
+        sys.arbiter().send(async move {
+            let _ = arb_tx.send(System::current().id());
+        });
+        let _ = tx.send(sys.id());
+    })
+    .join()
+    .unwrap();
+    assert_eq!(rx.recv().unwrap(), id);
+
+    // future sent from the thread runs on the system arbiter
+    assert_eq!(sys.block_on(arb_rx), Ok(id));
+    drop(sys);
+
+    // system is unregistered once its runner is dropped
+    let sys = System::new("next");
+    drop(sys);
+    assert!(panic::catch_unwind(System::current).is_err());
+}
+
+#[test]
+fn test_system_block_on() {
+    let _serial = serial();
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use std::time::Duration;
+
+    let mut ids = Vec::new();
+    for i in 0..3 {
+        let done = Rc::new(Cell::new(false));
+        let d = done.clone();
+        let res = System::block_on(async move {
+            actix_rt::spawn(async move {
+                actix_rt::time::delay_for(Duration::from_millis(5)).await;
+                d.set(true);
+            })
+            .unwrap();
+            (i, System::current().id())
+        });
+        assert_eq!(res.0, i);
+        ids.push(res.1);
+        // spawned task got drained, system is torn down
+        assert!(done.get());
+        assert!(panic::catch_unwind(System::current).is_err());
+    }
+    ids.dedup();
+    assert_eq!(ids.len(), 3);
+}
+
+#[test]
+fn test_system_block_on_nested() {
+    let _serial = serial();
+    let msg = System::block_on(async {
+        let err = panic::catch_unwind(|| System::block_on(async {})).unwrap_err();
+        err.downcast_ref::<&str>().unwrap().to_string()
+    });
+    assert!(msg.contains("already runs"), "{}", msg);
+}
+
+#[test]
+fn test_stop_with_code() {
+    let _serial = serial();
+    use std::sync::Arc;
+
+    use actix_rt::Arbiter;
+
+    let sys = System::new("exit-code");
+    let stopped = Arc::new(Mutex::new(Vec::new()));
+    let (tx, rx) = mpsc::channel();
+
+    let arbiters: Vec<_> = (0..3)
+        .map(|idx| {
+            let stopped = stopped.clone();
+            let arb = Arbiter::builder()
+                .runtime_builder(
+                    System::builder().on_stop(move || stopped.lock().unwrap().push(idx)),
+                )
+                .start();
+            let tx = tx.clone();
+            arb.send(async move { tx.send(()).unwrap() });
+            arb
+        })
+        .collect();
+    // arbiters are registered with the system
+    for _ in 0..3 {
+        rx.recv().unwrap();
+    }
+
+    System::current().stop_with_code(3);
+    System::current().stop_with_code(4);
+    assert_eq!(sys.run().unwrap(), 3);
+    assert_eq!(*stopped.lock().unwrap(), [2, 1, 0]);
+    drop(arbiters);
+
+    let sys = System::new("exit-code");
+    System::current().stop();
+    assert_eq!(sys.run().unwrap(), 0);
+}
+
+#[test]
+fn test_on_stop_hooks() {
+    let _serial = serial();
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use actix_rt::Arbiter;
+
+    let sys = System::new("hooks");
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let push = |log: &Arc<Mutex<Vec<&'static str>>>, item| {
+        let log = log.clone();
+        move || log.lock().unwrap().push(item)
+    };
+
+    let (tx, rx) = mpsc::channel();
+    let arb = Arbiter::builder()
+        .runtime_builder(System::builder().on_stop(push(&log, "arbiter")))
+        .start();
+    arb.send(async move { tx.send(()).unwrap() });
+    rx.recv().unwrap();
+
+    let current = System::current();
+    current.on_stop(10, push(&log, "late"));
+    current.on_stop(0, || panic!("bad hook"));
+    let l = log.clone();
+    current.on_stop_async(0, move || async move {
+        actix_rt::time::delay_for(Duration::from_millis(5)).await;
+        l.lock().unwrap().push("async");
+    });
+    current.on_stop(10, push(&log, "later"));
+    current.on_stop(-5, push(&log, "early"));
+
+    current.stop_with_code(2);
+    assert_eq!(sys.run().unwrap(), 2);
+    assert_eq!(
+        *log.lock().unwrap(),
+        ["arbiter", "early", "async", "late", "later"]
+    );
+    drop(arb);
+}
@@ -0,0 +1,291 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use actix_rt::{time, Runtime};
+
+#[test]
+fn test_pause_advance() {
+    let mut rt = Runtime::new().unwrap();
+    let fired = Rc::new(Cell::new(0));
+    let start = Instant::now();
+
+    rt.pause_time();
+    for secs in &[60, 3600] {
+        let (f, secs) = (fired.clone(), *secs);
+        rt.spawn(async move {
+            time::delay_for(Duration::from_secs(secs)).await;
+            f.set(f.get() + 1);
+        });
+    }
+    rt.run_until_idle(None);
+
+    rt.advance(Duration::from_secs(59));
+    assert_eq!(fired.get(), 0);
+    rt.advance(Duration::from_secs(1));
+    assert_eq!(fired.get(), 1);
+    rt.advance(Duration::from_secs(3540));
+    assert_eq!(fired.get(), 2);
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_pause_resume() {
+    let mut rt = Runtime::new().unwrap();
+
+    rt.pause_time();
+    rt.advance(Duration::from_secs(10));
+    rt.resume_time();
+
+    // real time again
+    let start = Instant::now();
+    rt.block_on(async { time::delay_for(Duration::from_millis(20)).await });
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+#[test]
+#[should_panic(expected = "not paused")]
+fn test_advance_not_paused() {
+    let mut rt = Runtime::new().unwrap();
+    rt.advance(Duration::from_secs(1));
+}
+
+#[test]
+fn test_pending_timers() {
+    let mut rt = Runtime::new().unwrap();
+    let metrics = rt.metrics();
+
+    let (short, long) = rt.block_on(async {
+        let short = time::delay_for(Duration::from_millis(5));
+        let long = time::delay_for(Duration::from_secs(60));
+        let _interval = time::interval(Duration::from_secs(1));
+        assert_eq!(actix_rt::try_current().unwrap().pending_timers(), 3);
+        (short, long)
+    });
+    assert_eq!(rt.pending_timers(), 2);
+
+    // fired timer is not pending until it gets reset
+    let mut short = short;
+    rt.block_on(async { (&mut short).await });
+    assert_eq!(metrics.pending_timers(), 1);
+    rt.block_on(async { short.reset(Instant::now() + Duration::from_secs(1)) });
+    assert_eq!(metrics.pending_timers(), 2);
+
+    // cancelled timers
+    drop(long);
+    drop(short);
+    assert_eq!(metrics.pending_timers(), 0);
+}
+
+#[test]
+fn test_timeout() {
+    let mut rt = Runtime::new().unwrap();
+
+    let res = rt.block_on(time::timeout(Duration::from_secs(1), async { 7 }));
+    assert_eq!(res, Ok(7));
+
+    let start = Instant::now();
+    let res = rt.block_on(time::timeout(
+        Duration::from_millis(10),
+        futures::future::pending::<()>(),
+    ));
+    assert!(res.unwrap_err().deadline() <= Instant::now());
+    assert!(start.elapsed() >= Duration::from_millis(10));
+    assert_eq!(rt.pending_timers(), 0);
+}
+
+#[test]
+fn test_timeout_spawned() {
+    let mut rt = Runtime::new().unwrap();
+    let res = Rc::new(Cell::new(None));
+
+    let r = res.clone();
+    rt.spawn(async move {
+        let deadline = Instant::now() + Duration::from_millis(5);
+        let slow = time::delay_for(Duration::from_secs(60));
+        let err = time::timeout_at(deadline, slow).await.unwrap_err();
+        r.set(Some(err.deadline() == deadline));
+    });
+    rt.run().unwrap();
+    assert_eq!(res.get(), Some(true));
+}
+
+#[test]
+fn test_timeout_io_error() {
+    let mut rt = Runtime::new().unwrap();
+
+    let res: std::io::Result<()> = rt.block_on(async {
+        time::timeout(Duration::from_millis(1), futures::future::pending()).await?
+    });
+    assert_eq!(res.unwrap_err().kind(), std::io::ErrorKind::TimedOut);
+}
+
+#[test]
+fn test_low_res_time() {
+    let mut rt = Runtime::new().unwrap();
+
+    rt.block_on(async {
+        let time = time::LowResTime::with(Duration::from_millis(20));
+        let first = time.now();
+        let clone = time.clone();
+
+        time::delay_for(Duration::from_millis(5)).await;
+        assert_eq!(clone.now(), first);
+
+        time::delay_for(Duration::from_millis(40)).await;
+        let next = clone.now();
+        assert!(next >= first + Duration::from_millis(20));
+        assert!(next <= Instant::now());
+    });
+
+    // refresher exits once the time is not read anymore
+    rt.run().unwrap();
+    assert_eq!(rt.metrics().alive_tasks(), 0);
+}
+
+#[test]
+fn test_without_drivers() {
+    use actix_rt::{BlockOnError, System};
+
+    // timer over the condvar park
+    let mut rt = System::builder().enable_io(false).build_rt().unwrap();
+    let start = Instant::now();
+    rt.block_on(async {
+        time::delay_for(Duration::from_millis(10)).await;
+        assert!(actix_rt::signal::ctrl_c().is_err());
+    });
+    assert!(start.elapsed() >= Duration::from_millis(10));
+
+    let mut rt = System::builder()
+        .enable_io(false)
+        .enable_time(false)
+        .build_rt()
+        .unwrap();
+    let (tx, rx) = futures::channel::oneshot::channel();
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(10));
+        let _ = tx.send(7);
+    });
+    assert_eq!(rt.block_on(rx), Ok(7));
+
+    let res = rt.block_on_timeout(async {}, Duration::from_secs(1));
+    assert_eq!(res, Err(BlockOnError::NoTimer));
+    let err = rt
+        .block_on_catch(async { time::delay_for(Duration::from_millis(1)).await })
+        .unwrap_err();
+    let msg = err.downcast_ref::<&str>().unwrap();
+    assert!(msg.contains("enable_time"), "{}", msg);
+}
+
+#[test]
+fn test_driver_handles() {
+    use actix_rt::System;
+
+    let mut rt = Runtime::new().unwrap();
+    assert!(rt.reactor_handle().is_some());
+
+    // timer of the runtime fires timers created on another thread
+    let handle = rt.handle();
+    let delay = std::thread::spawn(move || {
+        let timer = handle.timer_handle().unwrap();
+        timer.delay(Instant::now() + Duration::from_millis(10))
+    })
+    .join()
+    .unwrap();
+    let start = Instant::now();
+    rt.block_on(delay);
+    assert!(start.elapsed() >= Duration::from_millis(5));
+    assert_eq!(rt.pending_timers(), 0);
+
+    let rt = System::builder()
+        .enable_io(false)
+        .enable_time(false)
+        .build_rt()
+        .unwrap();
+    assert!(rt.reactor_handle().is_none());
+    assert!(rt.handle().timer_handle().is_none());
+}
+
+#[test]
+fn test_timer_resolution() {
+    use actix_rt::System;
+
+    let mut rt = System::builder()
+        .timer_resolution(Duration::from_millis(50))
+        .build_rt()
+        .unwrap();
+    let fired = Rc::new(Cell::new(0));
+
+    // deadlines are rounded up to the next multiple of 50ms
+    rt.pause_time();
+    for ms in &[10, 30] {
+        let (f, ms) = (fired.clone(), *ms);
+        rt.spawn(async move {
+            time::delay_for(Duration::from_millis(ms)).await;
+            f.set(f.get() + 1);
+        });
+    }
+    rt.run_until_idle(None);
+
+    rt.advance(Duration::from_millis(40));
+    assert_eq!(fired.get(), 0);
+    rt.advance(Duration::from_millis(10));
+    assert_eq!(fired.get(), 2);
+
+    assert!(System::builder()
+        .timer_resolution(Duration::from_micros(100))
+        .build_rt()
+        .is_err());
+}
+
+#[test]
+fn test_reactor_stats() {
+    use actix_rt::{ReactorStats, System};
+    use std::sync::{Arc, Mutex};
+
+    let turns = Arc::new(Mutex::new(Vec::new()));
+    let hook = turns.clone();
+    let mut rt = System::builder()
+        .on_reactor_turn(move |turn| hook.lock().unwrap().push(turn))
+        .build_rt()
+        .unwrap();
+    assert_eq!(rt.reactor_stats(), ReactorStats::default());
+
+    // runtime waits for the timer in the reactor
+    rt.block_on(async { time::delay_for(Duration::from_millis(10)).await });
+    let stats = rt.reactor_stats();
+    assert!(stats.turns > 0);
+    assert_eq!(stats.errors, 0);
+    assert!(stats.poll_time >= Duration::from_millis(5));
+
+    let turns = turns.lock().unwrap();
+    assert_eq!(turns.len() as u64, stats.turns);
+    assert_eq!(turns.last().cloned(), stats.last_turn);
+    assert!(turns.iter().any(|turn| turn.max_wait.is_some()));
+}
+
+#[test]
+fn test_test_clock() {
+    use actix_rt::{test_clock, System};
+
+    let clock = test_clock();
+    let origin = clock.now();
+    let mut rt = System::builder().test_clock(&clock).build_rt().unwrap();
+    let fired = Rc::new(Cell::new(None));
+    let start = Instant::now();
+
+    let f = fired.clone();
+    rt.spawn(async move {
+        time::delay_for(Duration::from_secs(3600)).await;
+        f.set(Some(tokio_timer::clock::now()));
+    });
+    rt.run_until_idle(None);
+
+    // runtime time follows the test clock only
+    rt.advance(Duration::from_secs(3599));
+    assert_eq!(fired.get(), None);
+    rt.advance(Duration::from_secs(1));
+    assert_eq!(fired.get(), Some(origin + Duration::from_secs(3600)));
+    assert_eq!(clock.now(), origin + Duration::from_secs(3600));
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "tracing")]
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Mutex, Once};
+use std::thread;
+use std::time::Duration;
+
+use actix_rt::{time, Arbiter, Runtime, System};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{info_span, Event, Metadata, Subscriber};
+use tracing_core::span::Current;
+
+/// Minimal subscriber that keeps track of the entered spans per thread
+#[derive(Default)]
+struct Recorder {
+    next: AtomicU64,
+    spans: Mutex<HashMap<u64, &'static Metadata<'static>>>,
+}
+
+thread_local! {
+    static ENTERED: RefCell<Vec<(Id, &'static Metadata<'static>)>> = RefCell::new(Vec::new());
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let id = self.next.fetch_add(1, Ordering::Relaxed) + 1;
+        self.spans.lock().unwrap().insert(id, span.metadata());
+        Id::from_u64(id)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, _: &Event<'_>) {}
+
+    fn enter(&self, span: &Id) {
+        let meta = self.spans.lock().unwrap()[&span.into_u64()];
+        ENTERED.with(|entered| entered.borrow_mut().push((span.clone(), meta)));
+    }
+
+    fn exit(&self, _: &Id) {
+        ENTERED.with(|entered| entered.borrow_mut().pop());
+    }
+
+    fn current_span(&self) -> Current {
+        match ENTERED.with(|entered| entered.borrow().last().cloned()) {
+            Some((id, meta)) => Current::new(id, meta),
+            None => Current::none(),
+        }
+    }
+}
+
+fn init() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| tracing::subscriber::set_global_default(Recorder::default()).unwrap());
+}
+
+/// Innermost span entered on the current thread
+fn current_name() -> Option<&'static str> {
+    ENTERED.with(|entered| entered.borrow().last().map(|(_, meta)| meta.name()))
+}
+
+#[test]
+fn test_spawn_in_span() {
+    init();
+    let mut rt = Runtime::new().unwrap();
+    let (tx, rx) = mpsc::channel();
+
+    rt.block_on(async move {
+        info_span!("request").in_scope(|| {
+            actix_rt::spawn(async move {
+                tx.send(current_name()).unwrap();
+                time::delay_for(Duration::from_millis(5)).await;
+                tx.send(current_name()).unwrap();
+            })
+            .unwrap();
+        });
+        assert_eq!(current_name(), None);
+    });
+    rt.run().unwrap();
+
+    assert_eq!(rx.iter().collect::<Vec<_>>(), [Some("request"); 2]);
+    assert_eq!(current_name(), None);
+}
+
+#[test]
+fn test_handle_spawn_in_span() {
+    init();
+    let mut rt = Runtime::new().unwrap();
+    let handle = rt.handle();
+    let (tx, rx) = mpsc::channel();
+
+    thread::spawn(move || {
+        info_span!("remote").in_scope(|| {
+            handle
+                .spawn(async move { tx.send(current_name()).unwrap() })
+                .unwrap()
+        });
+    })
+    .join()
+    .unwrap();
+    rt.run().unwrap();
+
+    assert_eq!(rx.recv().unwrap(), Some("remote"));
+}
+
+#[test]
+fn test_arbiter_send_in_span() {
+    init();
+    let sys = System::new("test-tracing");
+    let arb = Arbiter::new();
+    let (tx, rx) = mpsc::channel();
+
+    info_span!("arbiter").in_scope(|| {
+        arb.send(async move {
+            tx.send(current_name()).unwrap();
+            System::current().stop();
+        })
+    });
+    sys.run().unwrap();
+
+    assert_eq!(rx.recv().unwrap(), Some("arbiter"));
+}
@@ -253,7 +253,13 @@ impl ServerBuilder {
     pub fn run(self) -> io::Result<()> {
         let sys = System::new("http-server");
         self.start();
-        sys.run()
+        match sys.run()? {
+            0 => Ok(()),
+            code => Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("Non-zero exit code: {}", code),
+            )),
+        }
     }
 
     /// Starts processing incoming connections and return server controller.
@@ -61,7 +61,7 @@ impl TestServer {
                 .start();
 
             tx.send((System::current(), local_addr)).unwrap();
-            sys.run()
+            sys.run().map(|_| ())
         });
 
         let (system, addr) = rx.recv().unwrap();
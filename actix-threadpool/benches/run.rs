@@ -0,0 +1,36 @@
+use actix_threadpool::Pool;
+use criterion::{criterion_group, criterion_main, Criterion};
+use futures::channel::oneshot;
+use futures::executor::block_on;
+
+fn bench_run(c: &mut Criterion) {
+    let pool = Pool::builder().num_threads(4).build();
+    block_on(pool.warm_up());
+
+    c.bench_function("run", |b| {
+        b.iter(|| block_on(pool.run(|| Ok::<_, ()>(1))).unwrap())
+    });
+
+    c.bench_function("run_fn", |b| {
+        b.iter(|| block_on(pool.run_fn(|| 1)).unwrap())
+    });
+
+    // baseline, fresh oneshot channel per job
+    c.bench_function("execute oneshot", |b| {
+        b.iter(|| {
+            let (tx, rx) = oneshot::channel();
+            pool.execute(move || {
+                let _ = tx.send(1);
+            })
+            .unwrap();
+            block_on(rx).unwrap()
+        })
+    });
+
+    c.bench_function("run_batch 100", |b| {
+        b.iter(|| block_on(pool.run_batch((0..100).map(|i| move || Ok::<_, ()>(i)))))
+    });
+}
+
+criterion_group!(benches, bench_run);
+criterion_main!(benches);
@@ -0,0 +1,493 @@
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use derive_more::Display;
+use parking_lot::Mutex;
+
+use crate::config;
+use crate::pool::{JobCallback, JobMetrics, JobWrapper, PanicPolicy, Pool, PoolConfig};
+use crate::semaphore::Semaphore;
+use crate::worker::{Scheduling, ThreadHook, WorkerConfig, Workers};
+use crate::DEFAULT_POOL;
+
+lazy_static::lazy_static! {
+    /// Configuration of the default pool. `Some` once either `Builder::init()`
+    /// has been called or the default pool has been created.
+    static ref CONFIG: Mutex<Option<Builder>> = Mutex::new(None);
+
+    /// Template of per-thread pools
+    static ref THREAD_CONFIG: Mutex<Builder> = Mutex::new(Builder::new());
+
+    /// Configuration of the cpu pool. `Some` once either `Builder::init_cpu()`
+    /// has been called or the cpu pool has been created.
+    static ref CPU_CONFIG: Mutex<Option<Builder>> = Mutex::new(None);
+
+    /// Default pool size
+    static ref POOL_SIZE: Mutex<PoolSize> = Mutex::new(PoolSize { size: None, used: false });
+}
+
+struct PoolSize {
+    /// Size set with `set_default_pool_size()`
+    size: Option<usize>,
+    /// Default size was used to create a pool
+    used: bool,
+}
+
+/// Every thread uses its own pool instead of the shared default pool
+static THREAD_LOCAL: AtomicBool = AtomicBool::new(false);
+
+/// Per-thread pools are used instead of the shared default pool
+pub(crate) fn thread_local_pools() -> bool {
+    THREAD_LOCAL.load(Ordering::SeqCst)
+}
+
+/// Override number of worker threads of pools that do not set it explicitly,
+/// takes precedence over the `ACTIX_THREADPOOL` env variable.
+///
+/// Must be called before the first `run()`, returns `AlreadyInitialized`
+/// error if a pool was already created with the default size.
+///
+/// # Panics
+///
+/// Panics if `size` is zero.
+pub fn set_default_pool_size(size: usize) -> Result<(), AlreadyInitialized> {
+    assert!(size > 0, "Thread pool size must be greater than zero");
+
+    let mut pool_size = POOL_SIZE.lock();
+    if pool_size.used {
+        return Err(AlreadyInitialized);
+    }
+    pool_size.size = Some(size);
+    Ok(())
+}
+
+/// Number of worker threads of pools that do not set it explicitly.
+///
+/// This is the value set with `set_default_pool_size()`, or the value of
+/// the `ACTIX_THREADPOOL` env variable, or `num_cpus * 5`. Does not apply
+/// to the cpu pool, see `run_cpu()`. Shared default pool uses the value of
+/// the `ACTIX_THREADPOOL_IO` env variable instead if it is set, see
+/// `run_io()`.
+///
+/// Env variable is either number of threads (`8`), multiplier of number
+/// of cpus (`x3`) or percentage of number of cpus (`150%`). Invalid value
+/// is logged and ignored, or causes panic if `ACTIX_THREADPOOL_STRICT`
+/// is set to `1`.
+pub fn default_pool_size() -> usize {
+    POOL_SIZE.lock().size.unwrap_or_else(env_num_threads)
+}
+
+/// Error returned if the default pool configuration can not be changed
+/// because it is already in use.
+#[derive(Debug, Display)]
+#[display(fmt = "Default thread pool is already initialized")]
+pub struct AlreadyInitialized;
+
+impl std::error::Error for AlreadyInitialized {}
+
+/// Builder struct for a blocking thread pool.
+///
+/// Use `Builder::build` to create an independent pool, `Builder::init`
+/// to configure the shared default pool or `Builder::init_thread_local` to
+/// use per-thread pools. Settings provided by the builder take
+/// precedence over the `ACTIX_THREADPOOL`, `ACTIX_THREADPOOL_MAX_QUEUE`
+/// and `ACTIX_THREADPOOL_STACK_SIZE` env variables.
+#[derive(Clone)]
+pub struct Builder {
+    /// Number of worker threads. Defaults to `default_pool_size()`.
+    num_threads: Option<usize>,
+
+    /// Name of the pool. Defaults to "actix-blocking".
+    name: String,
+
+    /// Name prefix of the worker threads.
+    thread_name: Option<String>,
+
+    /// Stack size of the worker threads. Defaults to the std default.
+    thread_stack_size: Option<usize>,
+
+    /// Max number of jobs waiting for a free worker. Defaults to unbounded.
+    max_queue: Option<usize>,
+
+    /// Job instrumentation callback
+    on_job_complete: Option<JobCallback>,
+
+    /// Job wrapper
+    wrap_job: Option<JobWrapper>,
+
+    /// What to do if a blocking function panics. Defaults to `CatchAndReturn`.
+    panic_policy: PanicPolicy,
+
+    /// Max number of jobs waiting for a free worker before `run()` futures
+    /// start to wait for admission. Defaults to no admission control.
+    admission_backlog: Option<usize>,
+
+    /// Spawn all worker threads when the pool is created. Defaults to `false`.
+    eager: bool,
+
+    /// Order of queued jobs execution. Defaults to `Fifo`.
+    scheduling: Scheduling,
+
+    /// Hook called on every worker thread when it starts
+    on_thread_start: Option<ThreadHook>,
+
+    /// Max time a job can wait in the queue. Defaults to unlimited.
+    queue_deadline: Option<Duration>,
+
+    /// Time after which idle worker threads exit. Defaults to never.
+    idle_timeout: Option<Duration>,
+
+    /// Number of worker threads kept when idle. Defaults to 0.
+    min_threads: usize,
+
+    /// Number of pinned lanes. Defaults to the number of worker threads.
+    pinned_lanes: Option<usize>,
+}
+
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builder")
+            .field("num_threads", &self.num_threads)
+            .field("name", &self.name)
+            .field("thread_name", &self.thread_name)
+            .field("thread_stack_size", &self.thread_stack_size)
+            .field("max_queue", &self.max_queue)
+            .field("panic_policy", &self.panic_policy)
+            .field("admission_backlog", &self.admission_backlog)
+            .field("eager", &self.eager)
+            .field("scheduling", &self.scheduling)
+            .field("queue_deadline", &self.queue_deadline)
+            .field("idle_timeout", &self.idle_timeout)
+            .field("min_threads", &self.min_threads)
+            .field("pinned_lanes", &self.pinned_lanes)
+            .finish()
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Self {
+        Builder::new()
+    }
+}
+
+impl Builder {
+    /// Create new builder with default settings.
+    pub fn new() -> Self {
+        Builder {
+            num_threads: None,
+            name: "actix-blocking".to_owned(),
+            thread_name: None,
+            thread_stack_size: None,
+            max_queue: None,
+            on_job_complete: None,
+            wrap_job: None,
+            panic_policy: PanicPolicy::CatchAndReturn,
+            admission_backlog: None,
+            eager: false,
+            scheduling: Scheduling::Fifo,
+            on_thread_start: None,
+            queue_deadline: None,
+            idle_timeout: None,
+            min_threads: 0,
+            pinned_lanes: None,
+        }
+    }
+
+    /// Sets the number of worker threads.
+    pub fn num_threads(mut self, num_threads: usize) -> Self {
+        self.num_threads = Some(num_threads);
+        self
+    }
+
+    /// Sets the name of the pool.
+    pub fn name<T: Into<String>>(mut self, name: T) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    /// Sets the name prefix of the worker threads.
+    ///
+    /// Threads are named `<prefix>-blk-<index>`, index of a thread does not
+    /// change during its lifetime and indices of exited threads are reused.
+    /// By default threads are named `<pool name>-<index>`, for example
+    /// `actix-blocking-0`.
+    pub fn thread_name<T: Into<String>>(mut self, name: T) -> Self {
+        self.thread_name = Some(name.into());
+        self
+    }
+
+    /// Sets the stack size (in bytes) of the worker threads.
+    pub fn thread_stack_size(mut self, size: usize) -> Self {
+        self.thread_stack_size = Some(size);
+        self
+    }
+
+    /// Sets the max number of jobs waiting for a free worker.
+    ///
+    /// Once queue is full, `run()` resolves immediately with
+    /// `BlockingError::Overloaded`.
+    pub fn max_queue(mut self, max_queue: usize) -> Self {
+        self.max_queue = Some(max_queue);
+        self
+    }
+
+    /// Sets job instrumentation callback.
+    ///
+    /// Callback is called on the worker thread after every finished job with
+    /// the time job spent in the queue and its execution time.
+    pub fn on_job_complete<F>(mut self, f: F) -> Self
+    where
+        F: Fn(JobMetrics) + Send + Sync + 'static,
+    {
+        self.on_job_complete = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets job wrapper.
+    ///
+    /// Wrapper is called on the submitting thread for every job, including
+    /// jobs of `execute()`, `run_batch()` and `run_stream()`, and returned
+    /// job is executed on the worker thread. It can be used to propagate
+    /// logging or tracing context to worker threads.
+    ///
+    /// ```rust
+    /// use actix_threadpool::Pool;
+    ///
+    /// let pool = Pool::builder()
+    ///     .wrap_job(|job| {
+    ///         let caller = std::thread::current().name().map(ToOwned::to_owned);
+    ///         Box::new(move || {
+    ///             log::trace!("Job submitted by {:?}", caller);
+    ///             job()
+    ///         })
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn wrap_job<F>(mut self, f: F) -> Self
+    where
+        F: Fn(Box<dyn FnOnce() + Send>) -> Box<dyn FnOnce() + Send> + Send + Sync + 'static,
+    {
+        self.wrap_job = Some(Arc::new(f));
+        self
+    }
+
+    /// Sets what the pool does if a blocking function panics.
+    pub fn panic_policy(mut self, policy: PanicPolicy) -> Self {
+        self.panic_policy = policy;
+        self
+    }
+
+    /// Enables admission control.
+    ///
+    /// Futures returned by `run()` wait, in FIFO order, until number of
+    /// unfinished jobs drops below number of worker threads plus `backlog`
+    /// and only then submit their job, so pool saturation turns into
+    /// backpressure for the callers. Waiting future does not hold a slot
+    /// and gives up its position once it is dropped.
+    ///
+    /// Limit is computed from the initial number of worker threads, it does
+    /// not follow `Pool::resize()`. `execute()` and `run_stream()` are not
+    /// subject to admission control.
+    pub fn admission_backlog(mut self, backlog: usize) -> Self {
+        self.admission_backlog = Some(backlog);
+        self
+    }
+
+    /// Sets default max time a job can wait for a free worker.
+    ///
+    /// Jobs queued longer are dropped without execution and their futures
+    /// resolve with `BlockingError::DeadlineExceeded`, see also
+    /// `Pool::run_with_deadline()`.
+    pub fn queue_deadline(mut self, timeout: Duration) -> Self {
+        self.queue_deadline = Some(timeout);
+        self
+    }
+
+    /// Sets time after which worker threads that did not get a job exit.
+    ///
+    /// Pool spawns threads again on demand, up to the number of worker
+    /// threads. Threads do not exit while `Pool::warm_up()` is pending.
+    pub fn idle_timeout(mut self, timeout: Duration) -> Self {
+        self.idle_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets number of worker threads that are kept regardless of
+    /// `idle_timeout()`.
+    ///
+    /// Threads are still spawned on demand unless `eager()` is set.
+    pub fn min_threads(mut self, min_threads: usize) -> Self {
+        self.min_threads = min_threads;
+        self
+    }
+
+    /// Sets number of lanes for jobs of `Pool::run_pinned()`.
+    ///
+    /// More lanes spread keys more evenly between worker threads, lanes
+    /// beyond the number of threads share threads.
+    pub fn pinned_lanes(mut self, lanes: usize) -> Self {
+        self.pinned_lanes = Some(lanes);
+        self
+    }
+
+    /// Spawn all worker threads when the pool is created instead of on
+    /// demand, see also `Pool::warm_up()`.
+    pub fn eager(mut self, eager: bool) -> Self {
+        self.eager = eager;
+        self
+    }
+
+    /// Sets order in which queued jobs are executed.
+    ///
+    /// Queue deadlines apply regardless of the order, expired jobs are
+    /// dropped once a worker picks them up.
+    pub fn scheduling(mut self, scheduling: Scheduling) -> Self {
+        self.scheduling = scheduling;
+        self
+    }
+
+    /// Sets hook that is called on every worker thread when it starts.
+    pub fn on_thread_start<F>(mut self, f: F) -> Self
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        self.on_thread_start = Some(Arc::new(f));
+        self
+    }
+
+    /// Initialize the shared default pool used by `run()` and `run_io()`
+    /// with this configuration.
+    ///
+    /// Number of worker threads defaults to the value of the
+    /// `ACTIX_THREADPOOL_IO` env variable or `default_pool_size()`. Must be called before the first `run()`, otherwise the default pool
+    /// is already initialized and `AlreadyInitialized` error is returned.
+    /// Also returns `AlreadyInitialized` if per-thread pools are used, see
+    /// `Builder::init_thread_local()`.
+    pub fn init(self) -> Result<(), AlreadyInitialized> {
+        {
+            let mut cfg = CONFIG.lock();
+            if cfg.is_some() || thread_local_pools() {
+                return Err(AlreadyInitialized);
+            }
+            *cfg = Some(self);
+        }
+        lazy_static::initialize(&DEFAULT_POOL);
+        Ok(())
+    }
+
+    /// Initialize the cpu pool used by `run_cpu()` with this configuration.
+    ///
+    /// Number of worker threads defaults to `num_cpus` and does not follow
+    /// `set_default_pool_size()`. Must be called before the first
+    /// `run_cpu()`, otherwise `AlreadyInitialized` error is returned.
+    pub fn init_cpu(mut self) -> Result<(), AlreadyInitialized> {
+        let mut cfg = CPU_CONFIG.lock();
+        if cfg.is_some() {
+            return Err(AlreadyInitialized);
+        }
+        if self.num_threads.is_none() {
+            self.num_threads = Some(num_cpus::get());
+        }
+        *cfg = Some(self);
+        Ok(())
+    }
+
+    /// Give every thread its own pool with this configuration instead of
+    /// the shared default pool.
+    ///
+    /// Pools are created lazily, on the first `run()` of each thread.
+    /// `run()`, `stats()`, `shutdown()` and other free functions act on the
+    /// current thread's pool afterwards. Must be called before the first
+    /// `run()`, otherwise the shared default pool is already in use and
+    /// `AlreadyInitialized` error is returned.
+    pub fn init_thread_local(self) -> Result<(), AlreadyInitialized> {
+        let cfg = CONFIG.lock();
+        if cfg.is_some() {
+            return Err(AlreadyInitialized);
+        }
+        *THREAD_CONFIG.lock() = self;
+        THREAD_LOCAL.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Create new independent pool.
+    pub fn build(self) -> Pool {
+        let name = self.name;
+        let thread_name_prefix = match self.thread_name {
+            Some(prefix) => format!("{}-blk", prefix),
+            None => name.clone(),
+        };
+        let num_threads = self.num_threads.unwrap_or_else(default_num_threads);
+        let workers = Workers::new(WorkerConfig {
+            num_threads,
+            thread_name_prefix,
+            stack_size: self.thread_stack_size.or_else(config::stack_size),
+            eager: self.eager,
+            scheduling: self.scheduling,
+            on_thread_start: self.on_thread_start,
+            idle_timeout: self.idle_timeout,
+            min_threads: self.min_threads,
+            lanes: self.pinned_lanes.unwrap_or(num_threads),
+        });
+        Pool::new(
+            workers,
+            PoolConfig {
+                name,
+                max_queue: self.max_queue.or_else(config::max_queue),
+                on_job_complete: self.on_job_complete,
+                wrap_job: self.wrap_job,
+                panic_policy: self.panic_policy,
+                admission: self
+                    .admission_backlog
+                    .map(|backlog| Semaphore::new(num_threads + backlog)),
+                queue_deadline: self.queue_deadline,
+            },
+        )
+    }
+}
+
+/// Create the default pool, either from the configuration installed
+/// with `Builder::init()` or from the env variables.
+pub(crate) fn default_pool() -> Pool {
+    let mut builder = CONFIG.lock().get_or_insert_with(Builder::new).clone();
+    if builder.num_threads.is_none() {
+        builder.num_threads = Some(io_num_threads());
+    }
+    builder.build()
+}
+
+/// Create the cpu pool, either from the configuration installed with
+/// `Builder::init_cpu()` or with `num_cpus` threads.
+pub(crate) fn cpu_pool() -> Pool {
+    CPU_CONFIG
+        .lock()
+        .get_or_insert_with(|| {
+            Builder::new()
+                .name("actix-cpu")
+                .num_threads(num_cpus::get())
+        })
+        .clone()
+        .build()
+}
+
+/// Create pool for the current thread.
+pub(crate) fn thread_pool() -> Pool {
+    THREAD_CONFIG.lock().clone().build()
+}
+
+/// Default pool size, it can not be changed once used
+fn default_num_threads() -> usize {
+    let mut pool_size = POOL_SIZE.lock();
+    pool_size.used = true;
+    pool_size.size.unwrap_or_else(env_num_threads)
+}
+
+fn env_num_threads() -> usize {
+    config::pool_size().unwrap_or_else(|| num_cpus::get() * 5)
+}
+
+fn io_num_threads() -> usize {
+    config::io_pool_size().unwrap_or_else(default_num_threads)
+}
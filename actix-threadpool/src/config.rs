@@ -0,0 +1,198 @@
+use std::str::FromStr;
+
+use derive_more::Display;
+
+/// Env variable for default pool size
+const ENV_POOL_VAR: &str = "ACTIX_THREADPOOL";
+
+/// Env variable for io pool size
+const ENV_IO_POOL_VAR: &str = "ACTIX_THREADPOOL_IO";
+
+/// Env variable for default max queue length
+const ENV_MAX_QUEUE_VAR: &str = "ACTIX_THREADPOOL_MAX_QUEUE";
+
+/// Env variable for default worker thread stack size
+const ENV_STACK_SIZE_VAR: &str = "ACTIX_THREADPOOL_STACK_SIZE";
+
+/// Env variable that turns invalid values into panics
+const ENV_STRICT_VAR: &str = "ACTIX_THREADPOOL_STRICT";
+
+/// Invalid env variable value
+#[derive(Debug, Display, PartialEq)]
+#[display(fmt = "Invalid {} value: {:?}", var, value)]
+pub(crate) struct InvalidValue {
+    var: &'static str,
+    value: String,
+}
+
+/// Pool size, either absolute or relative to the number of cpus
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum PoolSize {
+    /// `8`
+    Threads(usize),
+    /// `x3`
+    Multiplier(usize),
+    /// `150%`
+    Percent(usize),
+}
+
+impl PoolSize {
+    /// Number of threads for `cpus` cpus, at least 1.
+    ///
+    /// Returns `None` on overflow.
+    pub(crate) fn threads(self, cpus: usize) -> Option<usize> {
+        match self {
+            PoolSize::Threads(n) => Some(n),
+            PoolSize::Multiplier(n) => cpus.checked_mul(n),
+            PoolSize::Percent(n) => cpus.checked_mul(n).map(|n| (n / 100).max(1)),
+        }
+    }
+}
+
+impl FromStr for PoolSize {
+    type Err = ();
+
+    fn from_str(val: &str) -> Result<Self, ()> {
+        let val = val.trim();
+        let size = if val.starts_with('x') {
+            PoolSize::Multiplier(val[1..].parse().map_err(|_| ())?)
+        } else if val.ends_with('%') {
+            PoolSize::Percent(val[..val.len() - 1].parse().map_err(|_| ())?)
+        } else {
+            PoolSize::Threads(val.parse().map_err(|_| ())?)
+        };
+        match size {
+            PoolSize::Threads(0) | PoolSize::Multiplier(0) | PoolSize::Percent(0) => Err(()),
+            size => Ok(size),
+        }
+    }
+}
+
+/// Default pool size from `ACTIX_THREADPOOL`.
+pub(crate) fn pool_size() -> Option<usize> {
+    env_var_with(ENV_POOL_VAR, |val| pool_threads(val, num_cpus::get()))
+}
+
+/// Io pool size from `ACTIX_THREADPOOL_IO`.
+pub(crate) fn io_pool_size() -> Option<usize> {
+    env_var_with(ENV_IO_POOL_VAR, |val| pool_threads(val, num_cpus::get()))
+}
+
+/// Number of threads for pool size value, `None` if invalid or overflows.
+fn pool_threads(val: &str, cpus: usize) -> Option<usize> {
+    val.parse::<PoolSize>().ok()?.threads(cpus)
+}
+
+/// Default max queue length from `ACTIX_THREADPOOL_MAX_QUEUE`.
+pub(crate) fn max_queue() -> Option<usize> {
+    env_var(ENV_MAX_QUEUE_VAR)
+}
+
+/// Default stack size from `ACTIX_THREADPOOL_STACK_SIZE`.
+pub(crate) fn stack_size() -> Option<usize> {
+    env_var(ENV_STACK_SIZE_VAR)
+}
+
+/// Read and parse env variable.
+///
+/// Invalid value is logged and ignored, or causes panic in strict mode.
+fn env_var<T: FromStr>(var: &'static str) -> Option<T> {
+    let value = std::env::var(var).ok()?;
+    checked(parse(var, value))
+}
+
+/// Read env variable and convert it with `f`, `None` from `f` is an invalid value.
+fn env_var_with<T, F>(var: &'static str, f: F) -> Option<T>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    let value = std::env::var(var).ok()?;
+    checked(parse_with(var, value, f))
+}
+
+/// Log and ignore invalid value, or panic in strict mode.
+fn checked<T>(res: Result<T, InvalidValue>) -> Option<T> {
+    match res {
+        Ok(val) => Some(val),
+        Err(e) => {
+            if std::env::var(ENV_STRICT_VAR)
+                .map(|v| v == "1")
+                .unwrap_or(false)
+            {
+                panic!("{}", e);
+            }
+            log::error!("{}, ignoring", e);
+            None
+        }
+    }
+}
+
+fn parse<T: FromStr>(var: &'static str, value: String) -> Result<T, InvalidValue> {
+    parse_with(var, value, |val| val.parse().ok())
+}
+
+fn parse_with<T, F>(var: &'static str, value: String, f: F) -> Result<T, InvalidValue>
+where
+    F: FnOnce(&str) -> Option<T>,
+{
+    match f(value.trim()) {
+        Some(val) => Ok(val),
+        None => Err(InvalidValue { var, value }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_size() {
+        assert_eq!("8".parse(), Ok(PoolSize::Threads(8)));
+        assert_eq!(" 8 ".parse(), Ok(PoolSize::Threads(8)));
+        assert_eq!("x3".parse(), Ok(PoolSize::Multiplier(3)));
+        assert_eq!("150%".parse(), Ok(PoolSize::Percent(150)));
+
+        assert_eq!(PoolSize::Threads(8).threads(4), Some(8));
+        assert_eq!(PoolSize::Multiplier(3).threads(4), Some(12));
+        assert_eq!(PoolSize::Percent(150).threads(4), Some(6));
+        assert_eq!(PoolSize::Percent(10).threads(4), Some(1));
+
+        // parses, but overflows
+        let size = "x99999999999999999".parse::<PoolSize>().unwrap();
+        assert_eq!(size.threads(1000), None);
+        assert_eq!(PoolSize::Multiplier(usize::max_value()).threads(2), None);
+        assert_eq!(PoolSize::Percent(usize::max_value()).threads(2), None);
+        let value = format!("x{}", usize::max_value());
+        assert_eq!(
+            parse_with(ENV_POOL_VAR, value.clone(), |val| pool_threads(val, 2)),
+            Err(InvalidValue {
+                var: ENV_POOL_VAR,
+                value,
+            })
+        );
+
+        for val in &[
+            "", "0", "x0", "0%", "-1", "x", "%", "3x", "x1.5", "1.5", "eight",
+        ] {
+            assert_eq!(val.parse::<PoolSize>(), Err(()), "{:?}", val);
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse::<usize>(ENV_MAX_QUEUE_VAR, "100".to_owned()), Ok(100));
+        assert_eq!(
+            parse::<usize>(ENV_STACK_SIZE_VAR, "2M".to_owned()),
+            Err(InvalidValue {
+                var: ENV_STACK_SIZE_VAR,
+                value: "2M".to_owned(),
+            })
+        );
+        assert_eq!(
+            parse::<usize>(ENV_STACK_SIZE_VAR, "2M".to_owned())
+                .unwrap_err()
+                .to_string(),
+            "Invalid ACTIX_THREADPOOL_STACK_SIZE value: \"2M\""
+        );
+    }
+}
@@ -1,96 +1,1630 @@
 //! Thread pool for blocking operations
+//!
+//! By default all threads share a single default pool, configured with
+//! `Builder::init()`. `run()`, `stats()`, `resize()`, `shutdown()` and other
+//! free functions act on this pool and can be called from any thread.
+//!
+//! `Builder::init_thread_local()` gives every thread that executes blocking
+//! operations its own pool instead, created lazily from the configured
+//! template. This isolates workloads of different runtime threads, but the
+//! total number of worker threads (and memory for their stacks) grows with
+//! the number of runtime threads, so per-thread pool size should be chosen
+//! accordingly. Free functions then act on the current thread's pool.
+//!
+//! Cpu bound functions should use `run_cpu()` instead of `run()`, they run on
+//! a separate pool shared by all threads with one worker per cpu. Default
+//! pool is meant for io bound functions, `run_io()` is the same as `run()`
+//! and shared default pool can be sized with the `ACTIX_THREADPOOL_IO` env
+//! variable.
 
+use std::any::Any;
+use std::collections::HashMap;
+use std::convert::Infallible;
 use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use derive_more::Display;
-use futures::channel::oneshot;
-use futures::{Future, Poll};
-use parking_lot::Mutex;
-use threadpool::ThreadPool;
+use futures::channel::mpsc;
+use futures::future::FusedFuture;
 use futures::task::Context;
+use futures::{Future, Poll, Stream};
+use parking_lot::Mutex;
 use std::pin::Pin;
+use tokio_timer::Delay;
 
-/// Env variable for default cpu pool size
-const ENV_CPU_POOL_VAR: &str = "ACTIX_THREADPOOL";
+mod builder;
+mod config;
+mod observer;
+mod pool;
+mod queue;
+mod semaphore;
+mod serial;
+#[cfg(feature = "service")]
+mod service;
+mod slot;
+mod state;
+mod worker;
+
+pub use self::builder::{
+    default_pool_size, set_default_pool_size, AlreadyInitialized, Builder,
+};
+pub use self::observer::{set_observer, PoolObserver};
+pub use self::pool::{
+    ExecuteError, Execution, Health, InvalidSize, JobMetrics, PanicPolicy, Pool,
+    ShutdownResult, Stats,
+};
+pub use self::serial::SerialPool;
+#[cfg(feature = "service")]
+pub use self::service::BlockingService;
+pub use self::state::StatefulPool;
+pub use self::worker::{Scheduling, TaskId, TaskInfo};
 
 lazy_static::lazy_static! {
-    pub(crate) static ref DEFAULT_POOL: Mutex<ThreadPool> = {
-        let default = match std::env::var(ENV_CPU_POOL_VAR) {
-            Ok(val) => {
-                if let Ok(val) = val.parse() {
-                    val
-                } else {
-                    log::error!("Can not parse ACTIX_THREADPOOL value");
-                    num_cpus::get() * 5
-                }
-            }
-            Err(_) => num_cpus::get() * 5,
-        };
-        Mutex::new(
-            threadpool::Builder::new()
-                .thread_name("actix-web".to_owned())
-                .num_threads(default)
-                .build(),
-        )
-    };
+    pub(crate) static ref DEFAULT_POOL: Mutex<Pool> = Mutex::new(builder::default_pool());
+
+    /// Shared pool for cpu bound functions
+    static ref CPU_POOL: Mutex<Pool> = Mutex::new(builder::cpu_pool());
 }
 
 thread_local! {
-    static POOL: ThreadPool = {
-        DEFAULT_POOL.lock().clone()
-    };
+    static POOL: Pool = builder::thread_pool();
+}
+
+/// Call `f` with the pool that `run()` uses on the current thread.
+pub(crate) fn with_pool<F, R>(f: F) -> R
+where
+    F: FnOnce(&Pool) -> R,
+{
+    if builder::thread_local_pools() {
+        POOL.with(f)
+    } else {
+        let pool = DEFAULT_POOL.lock().clone();
+        f(&pool)
+    }
 }
 
 /// Blocking operation execution error
-#[derive(Debug, Display)]
-pub enum BlockingError<E: fmt::Debug> {
-    #[display(fmt = "{:?}", _0)]
+///
+/// Error type of the blocking function is not bounded, `Display` and `Debug`
+/// are implemented if it implements `Debug`.
+#[derive(Debug)]
+pub enum BlockingError<E> {
     Error(E),
-    #[display(fmt = "Thread pool is gone")]
+    Panic(String),
+    Overloaded,
+    Timeout,
+    DeadlineExceeded,
     Canceled,
 }
 
+impl<E: fmt::Debug> fmt::Display for BlockingError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockingError::Error(e) => write!(f, "{:?}", e),
+            BlockingError::Panic(msg) => write!(f, "Blocking operation panicked: {}", msg),
+            BlockingError::Overloaded => write!(f, "Thread pool is overloaded"),
+            BlockingError::Timeout => write!(f, "Blocking operation timed out"),
+            BlockingError::DeadlineExceeded => {
+                write!(f, "Blocking operation was queued past its deadline")
+            }
+            BlockingError::Canceled => write!(f, "Thread pool is gone"),
+        }
+    }
+}
+
+impl<E> From<ExecuteError> for BlockingError<E> {
+    fn from(err: ExecuteError) -> Self {
+        match err {
+            ExecuteError::Overloaded => BlockingError::Overloaded,
+            ExecuteError::Shutdown => BlockingError::Canceled,
+        }
+    }
+}
+
 /// Execute blocking function on a thread pool, returns future that resolves
 /// to result of the function execution.
 pub fn run<F, I, E>(f: F) -> CpuFuture<I, E>
 where
     F: FnOnce() -> Result<I, E> + Send + 'static,
     I: Send + 'static,
-    E: Send + fmt::Debug + 'static,
+    E: Send + 'static,
 {
-    let (tx, rx) = oneshot::channel();
-    POOL.with(|pool| {
-        pool.execute(move || {
-            if !tx.is_canceled() {
-                let _ = tx.send(f());
+    with_pool(|pool| pool.run(f))
+}
+
+/// Execute io bound function on the default pool, same as `run()`.
+///
+/// Number of worker threads of the shared default pool is the value of the
+/// `ACTIX_THREADPOOL_IO` env variable or `default_pool_size()`, unless
+/// configured with `Builder::init()`. Use `io_pool()` to get its
+/// statistics.
+pub fn run_io<F, I, E>(f: F) -> CpuFuture<I, E>
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    run(f)
+}
+
+/// Execute cpu bound function on the cpu pool, returns future that resolves
+/// to result of the function execution.
+///
+/// Cpu pool is shared by all threads and has `num_cpus` worker threads
+/// unless configured with `Builder::init_cpu()`. Use `cpu_pool()` to get
+/// its statistics.
+pub fn run_cpu<F, I, E>(f: F) -> CpuFuture<I, E>
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    cpu_pool().run(f)
+}
+
+/// Handle to the cpu pool used by `run_cpu()`.
+pub fn cpu_pool() -> Pool {
+    CPU_POOL.lock().clone()
+}
+
+/// Handle to the default pool used by `run()` and `run_io()`.
+pub fn io_pool() -> Pool {
+    with_pool(Pool::clone)
+}
+
+/// Execute blocking function on a thread pool, resolves with
+/// `BlockingError::DeadlineExceeded` if the job is still queued at
+/// `deadline`.
+///
+/// See `Pool::run_with_deadline()` for details.
+pub fn run_with_deadline<F, I, E>(deadline: Instant, f: F) -> CpuFuture<I, E>
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_with_deadline(deadline, f))
+}
+
+/// Execute blocking function on a thread pool, same as `run()`, returns
+/// id of the job along with its future.
+pub fn run_traced<F, I, E>(f: F) -> (TaskId, CpuFuture<I, E>)
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_traced(f))
+}
+
+/// Execute blocking function on a thread pool, same as `run()`.
+///
+/// `label` is passed to the job instrumentation callback, see
+/// `Builder::on_job_complete()`.
+pub fn run_named<F, I, E>(label: &'static str, f: F) -> CpuFuture<I, E>
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_named(label, f))
+}
+
+/// Execute blocking function on a thread pool on behalf of a tenant, same
+/// as `run()`.
+///
+/// See `Pool::run_as()` for details.
+pub fn run_as<K, F, I, E>(key: K, f: F) -> CpuFuture<I, E>
+where
+    K: Into<Arc<str>>,
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_as(key, f))
+}
+
+/// Set weight of a tenant of the default pool.
+///
+/// See `Pool::set_weight()` for details.
+pub fn set_weight<K: Into<Arc<str>>>(key: K, weight: usize) {
+    with_pool(|pool| pool.set_weight(key, weight))
+}
+
+/// Number of queued jobs per tenant of the default pool.
+pub fn stats_per_tenant() -> HashMap<String, usize> {
+    with_pool(|pool| pool.stats_per_tenant())
+}
+
+/// Execute blocking function that can not fail on a thread pool, returns
+/// future that resolves to the return value of the function.
+///
+/// Future resolves with `BlockingError::Panic` if the function panics,
+/// with `BlockingError::Overloaded` if job queue is full and
+/// with `BlockingError::Canceled` if thread pool is gone.
+pub fn run_fn<F, R>(f: F) -> BlockingFuture<R>
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    with_pool(|pool| pool.run_fn(f))
+}
+
+/// Execute blocking functions on a thread pool, returned future resolves
+/// to their results in submission order.
+///
+/// See `Pool::run_batch()` for details.
+pub fn run_batch<J, T, E>(jobs: J) -> impl Future<Output = Vec<Result<T, BlockingError<E>>>>
+where
+    J: IntoIterator,
+    J::Item: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_batch(jobs))
+}
+
+/// Execute blocking functions on a thread pool with at most
+/// `max_concurrency` of them submitted at once.
+///
+/// See `Pool::run_batch_limited()` for details.
+pub fn run_batch_limited<J, T, E>(
+    max_concurrency: usize,
+    jobs: J,
+) -> impl Future<Output = Vec<Result<T, BlockingError<E>>>>
+where
+    J: IntoIterator,
+    J::Item: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_batch_limited(max_concurrency, jobs))
+}
+
+/// Execute blocking functions produced by the stream on a thread pool with
+/// at most `limit` of them submitted at once, returns stream of their
+/// results in order.
+///
+/// See `Pool::run_all()` for details.
+pub fn run_all<S, T, E>(
+    limit: usize,
+    jobs: S,
+) -> impl Stream<Item = Result<T, BlockingError<E>>>
+where
+    S: Stream,
+    S::Item: FnOnce() -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_all(limit, jobs))
+}
+
+/// Execute blocking iterator on a thread pool, returns stream of its items.
+///
+/// See `Pool::run_stream()` for details.
+pub fn run_stream<F, I, T, E>(f: F) -> BlockingStream<T, E>
+where
+    F: FnOnce() -> I + Send + 'static,
+    I: IntoIterator<Item = Result<T, E>>,
+    T: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_stream(f))
+}
+
+/// Execute blocking function on a thread pool, or on the calling thread if
+/// more than `max_queue` jobs are queued.
+///
+/// See `Pool::run_or_inline()` for details.
+pub fn run_or_inline<F, I, E>(max_queue: usize, f: F) -> (Execution, CpuFuture<I, E>)
+where
+    F: FnOnce() -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_or_inline(max_queue, f))
+}
+
+/// Execute blocking function that reports its progress on a thread pool.
+///
+/// See `Pool::run_with_progress()` for details.
+pub fn run_with_progress<F, I, E>(f: F) -> (CpuFuture<I, E>, impl Stream<Item = (u64, u64)>)
+where
+    F: FnOnce(Progress) -> Result<I, E> + Send + 'static,
+    I: Send + 'static,
+    E: Send + 'static,
+{
+    with_pool(|pool| pool.run_with_progress(f))
+}
+
+/// Execute blocking function on a thread pool without waiting for its result.
+///
+/// Panics in the function are caught and logged.
+pub fn execute<F>(f: F) -> Result<(), ExecuteError>
+where
+    F: FnOnce() + Send + 'static,
+{
+    with_pool(|pool| pool.execute(f))
+}
+
+/// Snapshot of running and queued jobs of the default pool.
+///
+/// See `Pool::dump_pending()` for details.
+pub fn dump_pending() -> Vec<TaskInfo> {
+    with_pool(|pool| pool.dump_pending())
+}
+
+/// Number of jobs in the default pool waiting for a free worker.
+pub fn queue_depth() -> usize {
+    with_pool(|pool| pool.queue_depth())
+}
+
+/// Statistics snapshot of the default pool.
+///
+/// Can be called from any thread, unless per-thread pools are used.
+///
+/// ```rust
+/// use std::time::Duration;
+///
+/// std::thread::spawn(|| loop {
+///     let stats = actix_threadpool::stats();
+///     log::info!(
+///         "blocking pool: {}/{} threads busy, {} queued, {} completed",
+///         stats.active_count,
+///         stats.max_threads,
+///         stats.queued_count,
+///         stats.completed_jobs,
+///     );
+///     std::thread::sleep(Duration::from_secs(10));
+/// });
+/// ```
+pub fn stats() -> Stats {
+    with_pool(|pool| pool.stats())
+}
+
+/// Spawn all worker threads of the default pool.
+///
+/// See `Pool::warm_up()` for details.
+pub fn warm_up() -> impl Future<Output = ()> {
+    with_pool(|pool| pool.warm_up())
+}
+
+/// Wait until the default pool has no queued or running jobs.
+///
+/// See `Pool::wait_idle()` for details.
+pub fn wait_idle() -> impl Future<Output = ()> {
+    with_pool(|pool| pool.wait_idle())
+}
+
+/// Health snapshot of the default pool.
+///
+/// See `Pool::health()` for details.
+pub fn health() -> Health {
+    with_pool(|pool| pool.health())
+}
+
+/// Set saturation threshold of the default pool.
+///
+/// See `Pool::set_health_thresholds()` for details.
+pub fn set_health_thresholds(max_queued: usize) {
+    with_pool(|pool| pool.set_health_thresholds(max_queued))
+}
+
+/// Change number of worker threads of the default pool.
+///
+/// See `Pool::resize()` for details.
+pub fn resize(num_threads: usize) -> Result<(), InvalidSize> {
+    with_pool(|pool| pool.resize(num_threads))
+}
+
+/// Shutdown the default pool.
+///
+/// See `Pool::shutdown()` for details.
+pub fn shutdown(timeout: Option<Duration>) -> impl Future<Output = ShutdownResult> {
+    with_pool(|pool| pool.shutdown(timeout))
+}
+
+/// Extract message from the panic payload
+pub(crate) fn panic_message(payload: Box<dyn Any + Send>) -> String {
+    if let Some(msg) = payload.downcast_ref::<&'static str>() {
+        (*msg).to_owned()
+    } else if let Some(msg) = payload.downcast_ref::<String>() {
+        msg.clone()
+    } else {
+        "Box<Any>".to_owned()
+    }
+}
+
+/// Blocking function completion future. It resolves with the return value
+/// of blocking function.
+pub struct BlockingFuture<R> {
+    rx: slot::Receiver<Result<R, BlockingError<Infallible>>>,
+    /// Job could not be submitted
+    error: Option<ExecuteError>,
+    /// Job waits for admission before submission
+    admission: Option<(semaphore::Acquire, pool::SubmitFn)>,
+    /// Future already resolved
+    terminated: bool,
+}
+
+impl<R> BlockingFuture<R> {
+    /// Cancel the job.
+    ///
+    /// If the job is not started yet it never starts, otherwise its result
+    /// is dropped. Future resolves with `BlockingError::Canceled`.
+    pub fn cancel(&mut self) {
+        self.admission = None;
+        self.rx.close();
+    }
+
+    /// Returns `true` if the future has already resolved.
+    pub fn is_finished(&self) -> bool {
+        self.terminated
+    }
+}
+
+impl<R: Send + 'static> BlockingFuture<R> {
+    /// Future that is resolved with `res`
+    pub(crate) fn ready(res: Result<R, BlockingError<Infallible>>) -> Self {
+        BlockingFuture {
+            rx: slot::Receiver::ready(res),
+            error: None,
+            admission: None,
+            terminated: false,
+        }
+    }
+
+    /// Get handle that can abort the job before it starts.
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle(self.rx.abort_flag())
+    }
+}
+
+/// Handle that prevents queued blocking job from running.
+///
+/// Dropping the job's future also prevents queued job from running,
+/// abort handle is useful if the future is still owned by someone else.
+#[derive(Clone)]
+pub struct AbortHandle(Arc<dyn slot::Abort>);
+
+impl fmt::Debug for AbortHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AbortHandle").finish()
+    }
+}
+
+impl AbortHandle {
+    /// Abort the job, if it is not started yet it is guaranteed to never
+    /// start and its future resolves with `BlockingError::Canceled`.
+    ///
+    /// Returns `true` if the job got aborted before execution.
+    pub fn abort(&self) -> bool {
+        self.0.abort()
+    }
+}
+
+impl<R> Future for BlockingFuture<R> {
+    type Output = Result<R, BlockingError<Infallible>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        use std::task::Poll::*;
+
+        if self.terminated {
+            return Pending;
+        }
+
+        if let Some((ref mut acquire, _)) = self.admission {
+            let permit = match Pin::new(acquire).poll(cx) {
+                Ready(permit) => permit,
+                Pending => return Pending,
+            };
+            if let Some((_, submit)) = self.admission.take() {
+                self.error = submit(permit).err();
             }
-        })
-    });
+        }
 
-    CpuFuture { rx }
+        let res = match self.error {
+            Some(err) => Err(err.into()),
+            None => match Pin::new(&mut self.rx).poll(cx) {
+                Ready(Some(res)) => res,
+                Ready(None) => Err(BlockingError::Canceled),
+                Pending => return Pending,
+            },
+        };
+        self.terminated = true;
+        Ready(res)
+    }
+}
+
+impl<R> FusedFuture for BlockingFuture<R> {
+    fn is_terminated(&self) -> bool {
+        self.terminated
+    }
 }
 
 /// Blocking operation completion future. It resolves with results
 /// of blocking function execution.
 pub struct CpuFuture<I, E> {
-    rx: oneshot::Receiver<Result<I, E>>,
+    fut: BlockingFuture<Result<I, E>>,
 }
 
-impl<I, E: fmt::Debug> Future for CpuFuture<I, E> {
+impl<I: Send + 'static, E: Send + 'static> CpuFuture<I, E> {
+    /// Get handle that can abort the job before it starts.
+    pub fn abort_handle(&self) -> AbortHandle {
+        self.fut.abort_handle()
+    }
+}
+
+impl<I, E> CpuFuture<I, E> {
+    /// Cancel the job, see `BlockingFuture::cancel()`.
+    pub fn cancel(&mut self) {
+        self.fut.cancel()
+    }
+
+    /// Returns `true` if the future has already resolved.
+    pub fn is_finished(&self) -> bool {
+        self.fut.is_finished()
+    }
+
+    /// Resolve with `BlockingError::Timeout` if blocking function does not
+    /// complete within `timeout`.
+    ///
+    /// Blocking function can not be interrupted, it keeps running in the
+    /// background and its result gets dropped. Timer is driven by the timer
+    /// that is current for the task that polls returned future.
+    pub fn timeout(self, timeout: Duration) -> CpuTimeout<I, E> {
+        CpuTimeout {
+            fut: Some(self),
+            delay: tokio_timer::delay_for(timeout),
+        }
+    }
+}
+
+impl<I, E> Future for CpuFuture<I, E> {
     type Output = Result<I, BlockingError<E>>;
 
     fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         use std::task::Poll::*;
 
-        match Pin::new(&mut self.rx).poll(cx) {
-            Ready(Ok(v)) => {
-                match v {
-                    Ok(v) => Ready(Ok(v)),
-                    Err(e) => Ready(Err(BlockingError::Error(e))),
-                }
-            },
+        match Pin::new(&mut self.fut).poll(cx) {
+            Ready(Ok(Ok(v))) => Ready(Ok(v)),
+            Ready(Ok(Err(e))) => Ready(Err(BlockingError::Error(e))),
+            Ready(Err(BlockingError::Panic(msg))) => Ready(Err(BlockingError::Panic(msg))),
+            Ready(Err(BlockingError::Overloaded)) => Ready(Err(BlockingError::Overloaded)),
+            Ready(Err(BlockingError::DeadlineExceeded)) => {
+                Ready(Err(BlockingError::DeadlineExceeded))
+            }
             Ready(Err(_)) => Ready(Err(BlockingError::Canceled)),
             Pending => Pending,
         }
     }
 }
+
+impl<I, E> FusedFuture for CpuFuture<I, E> {
+    fn is_terminated(&self) -> bool {
+        self.fut.is_terminated()
+    }
+}
+
+/// Stream of items produced by blocking iterator.
+pub struct BlockingStream<T, E> {
+    rx: mpsc::Receiver<Result<T, BlockingError<E>>>,
+}
+
+impl<T, E> Stream for BlockingStream<T, E> {
+    type Item = Result<T, BlockingError<E>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.rx).poll_next(cx)
+    }
+}
+
+/// Progress reporter of a blocking function, see `run_with_progress()`.
+#[derive(Debug, Clone)]
+pub struct Progress {
+    pub(crate) tx: mpsc::Sender<(u64, u64)>,
+}
+
+impl Progress {
+    /// Report that `done` of `total` units of work are done.
+    ///
+    /// Never blocks, report is discarded if the stream is dropped or its
+    /// buffer is full.
+    pub fn report(&mut self, done: u64, total: u64) {
+        let _ = self.tx.try_send((done, total));
+    }
+}
+
+/// Blocking operation completion future with timeout.
+pub struct CpuTimeout<I, E> {
+    fut: Option<CpuFuture<I, E>>,
+    delay: Delay,
+}
+
+impl<I, E> Future for CpuTimeout<I, E> {
+    type Output = Result<I, BlockingError<E>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        if let Some(ref mut fut) = self.fut {
+            if let Poll::Ready(res) = Pin::new(fut).poll(cx) {
+                return Poll::Ready(res);
+            }
+        }
+
+        match Pin::new(&mut self.delay).poll(cx) {
+            Poll::Ready(_) => {
+                // drop receiver, so late result gets dropped on the worker thread
+                self.fut.take();
+                Poll::Ready(Err(BlockingError::Timeout))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    #[test]
+    fn test_panic() {
+        let res = block_on(run(|| -> Result<(), ()> { panic!("boom") }));
+        match res {
+            Err(BlockingError::Panic(msg)) => assert_eq!(msg, "boom"),
+            _ => panic!("expected panic error"),
+        }
+
+        let res = block_on(run(|| -> Result<(), ()> { panic!("boom {}", 1) }));
+        match res {
+            Err(BlockingError::Panic(msg)) => assert_eq!(msg, "boom 1"),
+            _ => panic!("expected panic error"),
+        }
+    }
+
+    #[test]
+    fn test_pool_usable_after_panic() {
+        let res = block_on(run(|| -> Result<(), ()> { panic!("boom") }));
+        assert!(res.is_err());
+
+        let res = block_on(run(|| Ok::<_, ()>(1)));
+        assert_eq!(res.unwrap(), 1);
+        assert_eq!(with_pool(|pool| pool.panic_count()), 0);
+    }
+
+    #[test]
+    fn test_run_fn() {
+        let res = block_on(run_fn(|| 1 + 1));
+        assert_eq!(res.unwrap(), 2);
+
+        let res = block_on(run_fn(|| -> usize { panic!("boom") }));
+        match res {
+            Err(BlockingError::Panic(msg)) => assert_eq!(msg, "boom"),
+            _ => panic!("expected panic error"),
+        }
+    }
+
+    #[test]
+    fn test_max_queue() {
+        let pool = Builder::new().num_threads(1).max_queue(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+
+        // occupy the only worker
+        assert!(pool
+            .execute(move || {
+                let _ = started_tx.send(());
+                let _ = rx.recv();
+            })
+            .is_ok());
+        started_rx.recv().unwrap();
+
+        assert!(pool.execute(|| ()).is_ok());
+        assert_eq!(pool.queue_depth(), 1);
+        assert_eq!(pool.execute(|| ()), Err(ExecuteError::Overloaded));
+
+        drop(tx);
+    }
+
+    fn start_timer() -> tokio_timer::timer::Handle {
+        let (handle_tx, handle_rx) = std::sync::mpsc::channel();
+        std::thread::spawn(move || {
+            let mut timer = tokio_timer::Timer::default();
+            let _ = handle_tx.send(timer.handle());
+            loop {
+                timer.turn(Some(Duration::from_millis(10))).unwrap();
+            }
+        });
+        handle_rx.recv().unwrap()
+    }
+
+    #[test]
+    fn test_timeout() {
+        let handle = start_timer();
+        let _timer = tokio_timer::set_default(&handle);
+
+        let res = block_on(
+            run(|| {
+                std::thread::sleep(Duration::from_millis(500));
+                Ok::<_, ()>(())
+            })
+            .timeout(Duration::from_millis(20)),
+        );
+        match res {
+            Err(BlockingError::Timeout) => (),
+            _ => panic!("expected timeout error"),
+        }
+
+        let res = block_on(run(|| Ok::<_, ()>(1)).timeout(Duration::from_secs(10)));
+        assert_eq!(res.unwrap(), 1);
+    }
+
+    #[test]
+    fn test_independent_pool() {
+        let pool = Pool::builder().num_threads(2).name("disk").build();
+        assert_eq!(pool.name(), "disk");
+
+        let res = block_on(
+            pool.run(|| Ok::<_, ()>(std::thread::current().name().map(|s| s.to_owned()))),
+        );
+        assert_eq!(res.unwrap().unwrap(), "disk-0");
+        assert_eq!(pool.stats().max_threads, 2);
+    }
+
+    #[test]
+    fn test_shutdown() {
+        let pool = Pool::builder().num_threads(1).build();
+        let done = Arc::new(AtomicBool::new(false));
+        let done2 = done.clone();
+        let fut = pool.run_fn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            done2.store(true, Ordering::SeqCst);
+        });
+
+        let res = block_on(pool.clone().shutdown(None));
+        assert!(res.is_clean());
+        assert!(done.load(Ordering::SeqCst));
+        assert!(block_on(fut).is_ok());
+
+        match block_on(pool.run_fn(|| ())) {
+            Err(BlockingError::Canceled) => (),
+            _ => panic!("expected canceled error"),
+        }
+    }
+
+    #[test]
+    fn test_shutdown_timeout() {
+        let handle = start_timer();
+        let _timer = tokio_timer::set_default(&handle);
+
+        let pool = Pool::builder().num_threads(1).build();
+        let running = pool.run_fn(|| std::thread::sleep(Duration::from_millis(200)));
+        let queued = pool.run_fn(|| ());
+
+        let res = block_on(pool.shutdown(Some(Duration::from_millis(20))));
+        assert_eq!(res.abandoned, 2);
+        assert!(block_on(running).is_ok());
+        match block_on(queued) {
+            Err(BlockingError::Canceled) => (),
+            _ => panic!("expected canceled error"),
+        }
+    }
+
+    #[test]
+    fn test_resize() {
+        let pool = Pool::builder().num_threads(1).build();
+        assert_eq!(pool.resize(0), Err(InvalidSize));
+
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let blocked = pool.run_fn(move || {
+            let _ = rx.recv();
+        });
+        let queued = pool.run_fn(|| 1);
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(pool.queue_depth(), 1);
+
+        // queued job runs on the new thread
+        pool.resize(2).unwrap();
+        assert_eq!(pool.stats().max_threads, 2);
+        assert_eq!(block_on(queued).unwrap(), 1);
+
+        drop(tx);
+        assert!(block_on(blocked).is_ok());
+    }
+
+    #[test]
+    fn test_abort() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let blocked = pool.run_fn(move || {
+            let _ = rx.recv();
+        });
+        std::thread::sleep(Duration::from_millis(20));
+
+        let executed = Arc::new(AtomicBool::new(false));
+        let executed2 = executed.clone();
+        let queued = pool.run_fn(move || executed2.store(true, Ordering::SeqCst));
+        let handle = queued.abort_handle();
+        assert!(handle.abort());
+        assert!(!handle.abort());
+
+        // running job can not be aborted
+        assert!(!blocked.abort_handle().abort());
+        drop(tx);
+        assert!(block_on(blocked).is_ok());
+
+        match block_on(queued) {
+            Err(BlockingError::Canceled) => (),
+            _ => panic!("expected canceled error"),
+        }
+        assert!(!executed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_execute() {
+        let pool = Pool::builder().num_threads(1).build();
+        pool.execute(|| panic!("boom")).unwrap();
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        pool.execute(move || {
+            let _ = tx.send(1);
+        })
+        .unwrap();
+        assert_eq!(rx.recv().unwrap(), 1);
+        assert_eq!(pool.panic_count(), 0);
+
+        let _ = block_on(pool.shutdown(None));
+        assert_eq!(pool.execute(|| ()), Err(ExecuteError::Shutdown));
+    }
+
+    #[test]
+    fn test_job_metrics() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let tx = Mutex::new(tx);
+        let pool = Pool::builder()
+            .num_threads(1)
+            .on_job_complete(move |m| {
+                let _ = tx.lock().send(m);
+            })
+            .build();
+
+        block_on(pool.run_named("sleep", || {
+            std::thread::sleep(Duration::from_millis(20));
+            Ok::<_, ()>(())
+        }))
+        .unwrap();
+
+        let metrics = rx.recv().unwrap();
+        assert_eq!(metrics.label, Some("sleep"));
+        assert!(metrics.ran >= Duration::from_millis(20));
+
+        block_on(pool.run_fn(|| ())).unwrap();
+        assert_eq!(rx.recv().unwrap().label, None);
+    }
+
+    #[test]
+    fn test_run_stream() {
+        use futures::StreamExt;
+
+        let items: Vec<_> = block_on(
+            run_stream(|| (0..100).map(|i| if i < 99 { Ok(i) } else { Err(i) })).collect(),
+        );
+        assert_eq!(items.len(), 100);
+        assert_eq!(*items[10].as_ref().unwrap(), 10);
+        match items[99] {
+            Err(BlockingError::Error(99)) => (),
+            _ => panic!("expected error item"),
+        }
+
+        let mut stream = run_stream(|| {
+            (0..).map(|i| {
+                if i < 5 {
+                    Ok::<_, ()>(i)
+                } else {
+                    panic!("boom")
+                }
+            })
+        });
+        for i in 0..5 {
+            assert_eq!(block_on(stream.next()).unwrap().unwrap(), i);
+        }
+        match block_on(stream.next()) {
+            Some(Err(BlockingError::Panic(_))) => (),
+            _ => panic!("expected panic error"),
+        }
+        assert!(block_on(stream.next()).is_none());
+    }
+
+    #[test]
+    fn test_run_stream_drop() {
+        use futures::StreamExt;
+
+        let pool = Pool::builder().num_threads(1).build();
+        let mut stream = pool.run_stream(|| (0..).map(Ok::<_, ()>));
+        assert_eq!(block_on(stream.next()).unwrap().unwrap(), 0);
+        drop(stream);
+
+        // worker is released once stream is dropped
+        assert!(block_on(pool.run_fn(|| ())).is_ok());
+    }
+
+    #[test]
+    fn test_panic_policy_restart() {
+        let pool = Pool::builder()
+            .num_threads(2)
+            .panic_policy(PanicPolicy::RestartThread)
+            .build();
+        assert_eq!(pool.panic_policy(), PanicPolicy::RestartThread);
+
+        for _ in 0..5 {
+            match block_on(pool.run_fn(|| -> usize { panic!("boom") })) {
+                Err(BlockingError::Panic(msg)) => assert_eq!(msg, "boom"),
+                _ => panic!("expected panic error"),
+            }
+        }
+        assert_eq!(pool.stats().max_threads, 2);
+
+        // both workers are alive, jobs wait for each other
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let b1 = barrier.clone();
+        let b2 = barrier.clone();
+        let f1 = pool.run_fn(move || b1.wait());
+        let f2 = pool.run_fn(move || b2.wait());
+        assert!(block_on(f1).is_ok());
+        assert!(block_on(f2).is_ok());
+        assert_eq!(pool.panic_count(), 5);
+    }
+
+    #[test]
+    fn test_non_debug_error() {
+        struct NotDebug;
+
+        match block_on(run(|| -> Result<(), NotDebug> { Err(NotDebug) })) {
+            Err(BlockingError::Error(NotDebug)) => (),
+            _ => panic!("expected error"),
+        }
+    }
+
+    #[test]
+    fn test_admission() {
+        use futures::task::noop_waker_ref;
+
+        let pool = Pool::builder().num_threads(1).admission_backlog(0).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let mut f1 = pool.run_fn(move || {
+            started_tx.send(()).unwrap();
+            rx.recv()
+        });
+        let mut f2 = pool.run_fn(|| 2);
+        let mut f3 = pool.run_fn(|| 3);
+
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(Pin::new(&mut f1).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut f2).poll(&mut cx).is_pending());
+        assert!(Pin::new(&mut f3).poll(&mut cx).is_pending());
+        // waiting jobs are not submitted
+        started_rx.recv().unwrap();
+        assert_eq!(pool.queue_depth(), 0);
+
+        // dropped waiter releases its position
+        drop(f2);
+        tx.send(()).unwrap();
+        assert!(block_on(f1).unwrap().is_ok());
+        assert_eq!(block_on(f3).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_warm_up() {
+        let started = Arc::new(AtomicUsize::new(0));
+        let started2 = started.clone();
+        let pool = Pool::builder()
+            .num_threads(4)
+            .on_thread_start(move || {
+                started2.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+
+        block_on(pool.warm_up());
+        assert_eq!(started.load(Ordering::SeqCst), 4);
+        // already warmed up
+        block_on(pool.warm_up());
+        assert_eq!(started.load(Ordering::SeqCst), 4);
+
+        let started2 = started.clone();
+        let pool = Pool::builder()
+            .num_threads(2)
+            .eager(true)
+            .on_thread_start(move || {
+                started2.fetch_add(1, Ordering::SeqCst);
+            })
+            .build();
+        block_on(pool.warm_up());
+        assert_eq!(started.load(Ordering::SeqCst), 6);
+    }
+
+    #[test]
+    fn test_set_default_pool_size() {
+        assert!(default_pool_size() > 0);
+
+        // pool created with default size
+        let _ = Pool::builder().build();
+        assert!(set_default_pool_size(2).is_err());
+    }
+
+    #[test]
+    fn test_fused() {
+        use futures::task::noop_waker_ref;
+
+        let mut fut = run(|| Ok::<_, ()>(1));
+        assert!(!fut.is_terminated());
+        assert_eq!(block_on(&mut fut).unwrap(), 1);
+        assert!(fut.is_terminated());
+        assert!(fut.is_finished());
+
+        // poll after completion
+        let mut cx = Context::from_waker(noop_waker_ref());
+        assert!(Pin::new(&mut fut).poll(&mut cx).is_pending());
+    }
+
+    #[test]
+    fn test_cancel() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let mut f1 = pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().map_err(|_| ())
+        });
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran2 = ran.clone();
+        let mut f2 = pool.run(move || {
+            ran2.store(true, Ordering::SeqCst);
+            Ok::<_, ()>(())
+        });
+
+        // job is running
+        started_rx.recv().unwrap();
+        f1.cancel();
+        // job is queued
+        f2.cancel();
+        tx.send(()).unwrap();
+
+        match block_on(f1) {
+            Err(BlockingError::Canceled) => (),
+            _ => panic!("expected canceled error"),
+        }
+        match block_on(f2) {
+            Err(BlockingError::Canceled) => (),
+            _ => panic!("expected canceled error"),
+        }
+        block_on(pool.shutdown(None));
+        assert!(!ran.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_wait_idle() {
+        let pool = Pool::builder().num_threads(2).build();
+        block_on(pool.wait_idle());
+
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let count = Arc::new(AtomicUsize::new(0));
+        let count2 = count.clone();
+        let pool2 = pool.clone();
+        pool.execute(move || {
+            rx.recv().unwrap();
+            // job enqueued while waiting
+            pool2
+                .execute(move || {
+                    std::thread::sleep(Duration::from_millis(50));
+                    count2.fetch_add(1, Ordering::SeqCst);
+                })
+                .unwrap();
+        })
+        .unwrap();
+
+        let idle = pool.wait_idle();
+        tx.send(()).unwrap();
+        block_on(idle);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_deadline() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let f1 = pool.run(move || rx.recv().map_err(|_| ()));
+        let ran = Arc::new(AtomicBool::new(false));
+        let ran2 = ran.clone();
+        let f2 =
+            pool.run_with_deadline(Instant::now() + Duration::from_millis(10), move || {
+                ran2.store(true, Ordering::SeqCst);
+                Ok::<_, ()>(())
+            });
+        std::thread::sleep(Duration::from_millis(50));
+        tx.send(()).unwrap();
+
+        assert!(block_on(f1).is_ok());
+        match block_on(f2) {
+            Err(BlockingError::DeadlineExceeded) => (),
+            _ => panic!("expected deadline error"),
+        }
+        assert!(!ran.load(Ordering::SeqCst));
+
+        // default deadline
+        let pool = Pool::builder()
+            .queue_deadline(Duration::from_secs(10))
+            .build();
+        assert_eq!(block_on(pool.run_fn(|| 1)).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_thread_names() {
+        let name = || std::thread::current().name().map(ToOwned::to_owned);
+
+        let pool = Pool::builder().num_threads(1).build();
+        assert_eq!(
+            block_on(pool.run_fn(name)).unwrap().unwrap(),
+            "actix-blocking-0"
+        );
+
+        let pool = Pool::builder().num_threads(2).thread_name("db").build();
+        block_on(pool.warm_up());
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let f1 = pool.run_fn(move || {
+            let _ = rx.recv();
+            name()
+        });
+        let f2 = pool.run_fn(name);
+        let n2 = block_on(f2).unwrap().unwrap();
+        tx.send(()).unwrap();
+        let n1 = block_on(f1).unwrap().unwrap();
+        let mut names = vec![n1, n2];
+        names.sort();
+        assert_eq!(names, vec!["db-blk-0", "db-blk-1"]);
+    }
+
+    #[test]
+    fn test_run_batch() {
+        let jobs = (0..10u64).map(|i| {
+            move || {
+                if i == 3 {
+                    panic!("boom");
+                }
+                // later jobs finish first
+                std::thread::sleep(Duration::from_millis(20 - i * 2));
+                Ok::<_, ()>(i)
+            }
+        });
+        let results = block_on(run_batch(jobs));
+        assert_eq!(results.len(), 10);
+        for (i, res) in results.into_iter().enumerate() {
+            match res {
+                Ok(v) => assert_eq!(v, i as u64),
+                Err(BlockingError::Panic(_)) => assert_eq!(i, 3),
+                Err(_) => panic!("unexpected error"),
+            }
+        }
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let jobs = (0..8).map(|i| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            move || {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut max = max_running.load(Ordering::SeqCst);
+                while max < n {
+                    match max_running.compare_exchange_weak(
+                        max,
+                        n,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    ) {
+                        Ok(_) => break,
+                        Err(actual) => max = actual,
+                    }
+                }
+                std::thread::sleep(Duration::from_millis(10));
+                running.fetch_sub(1, Ordering::SeqCst);
+                Ok::<_, ()>(i)
+            }
+        });
+        let pool = Pool::builder().num_threads(8).build();
+        let results = block_on(pool.run_batch_limited(2, jobs));
+        let results: Vec<_> = results.into_iter().map(Result::unwrap).collect();
+        assert_eq!(results, (0..8).collect::<Vec<_>>());
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+
+    #[test]
+    fn test_restart_thread() {
+        let panicked = Arc::new(AtomicBool::new(false));
+        let pool = Pool::builder()
+            .num_threads(2)
+            .on_job_complete(move |_| {
+                // kill worker thread once
+                if !panicked.swap(true, Ordering::SeqCst) {
+                    panic!("worker is lost");
+                }
+            })
+            .build();
+
+        assert_eq!(block_on(pool.run_fn(|| 1)).unwrap(), 1);
+        block_on(pool.wait_idle());
+        assert_eq!(pool.stats().restarted_threads, 1);
+
+        // both workers are alive
+        let barrier = Arc::new(std::sync::Barrier::new(2));
+        let b1 = barrier.clone();
+        let b2 = barrier.clone();
+        let f1 = pool.run_fn(move || b1.wait());
+        let f2 = pool.run_fn(move || b2.wait());
+        assert!(block_on(f1).is_ok());
+        assert!(block_on(f2).is_ok());
+    }
+
+    #[test]
+    fn test_wrap_job() {
+        use futures::StreamExt;
+        use std::cell::Cell;
+
+        thread_local! {
+            static CONTEXT: Cell<usize> = Cell::new(0);
+        }
+
+        let pool = Pool::builder()
+            .wrap_job(|job| {
+                let ctx = CONTEXT.with(|ctx| ctx.get());
+                Box::new(move || {
+                    CONTEXT.with(|c| c.set(ctx));
+                    job()
+                })
+            })
+            .build();
+        let context = || CONTEXT.with(|ctx| ctx.get());
+
+        CONTEXT.with(|ctx| ctx.set(7));
+        assert_eq!(block_on(pool.run_fn(context)).unwrap(), 7);
+
+        CONTEXT.with(|ctx| ctx.set(8));
+        let (tx, rx) = std::sync::mpsc::channel();
+        pool.execute(move || tx.send(context()).unwrap()).unwrap();
+        assert_eq!(rx.recv().unwrap(), 8);
+
+        CONTEXT.with(|ctx| ctx.set(9));
+        let items: Vec<_> = block_on(
+            pool.run_stream(move || vec![Ok::<_, ()>(context())])
+                .collect(),
+        );
+        assert_eq!(items[0].as_ref().unwrap(), &9);
+    }
+
+    #[test]
+    fn test_lifo() {
+        let pool = Pool::builder()
+            .num_threads(1)
+            .scheduling(Scheduling::Lifo)
+            .build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().unwrap();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let futs: Vec<_> = (0..3)
+            .map(|i| {
+                let order = order.clone();
+                pool.run_fn(move || order.lock().push(i))
+            })
+            .collect();
+        tx.send(()).unwrap();
+        for fut in futs {
+            block_on(fut).unwrap();
+        }
+        assert_eq!(*order.lock(), vec![2, 1, 0]);
+    }
+
+    #[test]
+    fn test_dump_pending() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let (id1, f1) = pool.run_traced(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().map_err(|_| ())
+        });
+        let f2 = pool.run_named("second", || Ok::<_, ()>(()));
+        let (id3, f3) = pool.run_traced(|| Ok::<_, ()>(()));
+        assert!(id1 < id3);
+        started_rx.recv().unwrap();
+
+        let tasks = pool.dump_pending();
+        assert_eq!(tasks.len(), 3);
+        assert_eq!(tasks[0].id, id1);
+        assert!(tasks[0].started.is_some());
+        assert_eq!(tasks[1].label, Some("second"));
+        assert!(tasks[1].started.is_none());
+        assert_eq!(tasks[2].id, id3);
+
+        tx.send(()).unwrap();
+        assert!(block_on(f1).is_ok());
+        assert!(block_on(f2).is_ok());
+        assert!(block_on(f3).is_ok());
+        block_on(pool.wait_idle());
+        assert!(pool.dump_pending().is_empty());
+    }
+
+    #[test]
+    fn test_run_as() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let blocker = pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().map_err(|_| ())
+        });
+        started_rx.recv().unwrap();
+
+        pool.set_weight("a", 3);
+        let order = Arc::new(parking_lot::Mutex::new(String::new()));
+        let mut futs = Vec::new();
+        for key in ["a", "a", "a", "a", "b", "b"].iter() {
+            let order = order.clone();
+            futs.push(pool.run_as(*key, move || {
+                order.lock().push_str(key);
+                Ok::<_, ()>(())
+            }));
+        }
+        let stats = pool.stats_per_tenant();
+        assert_eq!(stats["a"], 4);
+        assert_eq!(stats["b"], 2);
+
+        tx.send(()).unwrap();
+        assert!(block_on(blocker).is_ok());
+        for fut in futs {
+            assert!(block_on(fut).is_ok());
+        }
+        assert_eq!(*order.lock(), "aaabab");
+        assert_eq!(pool.stats_per_tenant()["a"], 0);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        let pool = Pool::builder()
+            .num_threads(4)
+            .min_threads(1)
+            .idle_timeout(Duration::from_millis(50))
+            .build();
+        let barrier = Arc::new(std::sync::Barrier::new(4));
+        let futs: Vec<_> = (0..4)
+            .map(|_| {
+                let barrier = barrier.clone();
+                pool.run_fn(move || {
+                    barrier.wait();
+                })
+            })
+            .collect();
+        for fut in futs {
+            assert!(block_on(fut).is_ok());
+        }
+        assert_eq!(pool.stats().threads, 4);
+
+        std::thread::sleep(Duration::from_millis(300));
+        let stats = pool.stats();
+        assert_eq!(stats.threads, 1);
+        assert_eq!(stats.max_threads, 4);
+
+        // pool grows again on demand
+        block_on(pool.warm_up());
+        assert!(block_on(pool.run_fn(|| ())).is_ok());
+    }
+
+    #[test]
+    fn test_run_cpu() {
+        let name = block_on(run_cpu(|| {
+            Ok::<_, ()>(std::thread::current().name().unwrap().to_owned())
+        }))
+        .unwrap();
+        assert!(name.starts_with("actix-cpu-"));
+        assert_eq!(cpu_pool().stats().max_threads, num_cpus::get());
+        assert!(Builder::new().init_cpu().is_err());
+
+        let name = block_on(run_io(|| {
+            Ok::<_, ()>(std::thread::current().name().unwrap().to_owned())
+        }))
+        .unwrap();
+        assert!(name.starts_with("actix-blocking-"));
+        assert!(Builder::new().init().is_err());
+    }
+
+    #[test]
+    fn test_run_cpu_io_threads() {
+        let thread_ids = |futs: Vec<CpuFuture<_, ()>>| {
+            futs.into_iter()
+                .map(|fut| block_on(fut).unwrap())
+                .collect::<std::collections::HashSet<_>>()
+        };
+        let cpu = thread_ids(
+            (0..20)
+                .map(|_| run_cpu(|| Ok(std::thread::current().id())))
+                .collect(),
+        );
+        let io = thread_ids(
+            (0..20)
+                .map(|_| run_io(|| Ok(std::thread::current().id())))
+                .collect(),
+        );
+        assert!(cpu.is_disjoint(&io));
+
+        // each pool reports its own stats
+        block_on(io_pool().wait_idle());
+        assert!(io_pool().stats().completed_jobs >= 20);
+        assert_eq!(io_pool().stats().max_threads, num_cpus::get() * 5);
+        assert_eq!(io_pool().name(), "actix-blocking");
+    }
+
+    #[test]
+    fn test_run_pinned() {
+        let pool = Pool::builder().num_threads(4).pinned_lanes(8).build();
+        let futs: Vec<_> = (0..40)
+            .map(|i| {
+                pool.run_pinned(i % 4, move || {
+                    Ok::<_, ()>((i % 4, i, std::thread::current().id()))
+                })
+            })
+            .collect();
+        let mut threads = HashMap::new();
+        let mut last = HashMap::new();
+        for fut in futs {
+            let (key, i, thread) = block_on(fut).unwrap();
+            assert_eq!(*threads.entry(key).or_insert(thread), thread);
+            if let Some(prev) = last.insert(key, i) {
+                assert!(prev < i);
+            }
+        }
+        assert_eq!(pool.stats_per_lane(), vec![0; 8]);
+    }
+
+    #[test]
+    fn test_health() {
+        let pool = Pool::builder().num_threads(1).max_queue(2).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let blocker = pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().map_err(|_| ())
+        });
+        started_rx.recv().unwrap();
+        let queued: Vec<_> = (0..2).map(|_| pool.run_fn(|| panic!("test"))).collect();
+
+        let health = pool.health();
+        assert_eq!(health.queued, 2);
+        assert_eq!(health.active, 1);
+        assert!(health.saturated);
+        pool.set_health_thresholds(2);
+        assert!(!pool.health().saturated);
+
+        assert!(pool.execute(|| ()).is_err());
+        tx.send(()).unwrap();
+        assert!(block_on(blocker).is_ok());
+        for fut in queued {
+            assert!(block_on(fut).is_err());
+        }
+        block_on(pool.wait_idle());
+
+        let health = pool.health();
+        assert_eq!(health.panics_since_start, 2);
+        assert_eq!(health.rejections_since_start, 1);
+        assert!(!health.saturated);
+    }
+
+    #[test]
+    fn test_run_with_progress() {
+        use futures::StreamExt;
+
+        let pool = Pool::builder().num_threads(1).build();
+        let (fut, progress) = pool.run_with_progress(|mut progress| {
+            for i in 0..5 {
+                progress.report(i + 1, 5);
+            }
+            Ok::<_, ()>(5)
+        });
+        assert_eq!(block_on(fut).unwrap(), 5);
+        let reports: Vec<_> = block_on(progress.collect());
+        assert_eq!(reports, (1..=5).map(|i| (i, 5)).collect::<Vec<_>>());
+
+        // dropped stream does not affect the job
+        let (fut, progress) = pool.run_with_progress(|mut progress| {
+            for i in 0..100 {
+                progress.report(i + 1, 100);
+            }
+            Ok::<_, ()>(100)
+        });
+        drop(progress);
+        assert_eq!(block_on(fut).unwrap(), 100);
+    }
+
+    #[test]
+    fn test_run_or_inline() {
+        let pool = Pool::builder().num_threads(1).build();
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        let blocker = pool.run(move || {
+            started_tx.send(()).unwrap();
+            rx.recv().map_err(|_| ())
+        });
+        started_rx.recv().unwrap();
+
+        let current = std::thread::current().id();
+        let (exec, queued) = pool.run_or_inline(0, || Ok::<_, ()>(std::thread::current().id()));
+        assert_eq!(exec, Execution::Pool);
+
+        // one job is queued now
+        let (exec, inline) = pool.run_or_inline(0, || Ok::<_, ()>(std::thread::current().id()));
+        assert_eq!(exec, Execution::Inline);
+        assert_eq!(block_on(inline).unwrap(), current);
+        let (exec, fut) = pool.run_or_inline(0, || -> Result<(), ()> { panic!("inline") });
+        assert_eq!(exec, Execution::Inline);
+        match block_on(fut) {
+            Err(BlockingError::Panic(msg)) => assert_eq!(msg, "inline"),
+            _ => panic!("panic is expected"),
+        }
+
+        tx.send(()).unwrap();
+        assert!(block_on(blocker).is_ok());
+        assert_ne!(block_on(queued).unwrap(), current);
+    }
+
+    #[test]
+    fn test_run_all() {
+        use futures::StreamExt;
+
+        let pool = Pool::builder().num_threads(4).build();
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let jobs = futures::stream::iter(0..20u64).map(|i| {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            move || {
+                let n = running.fetch_add(1, Ordering::SeqCst) + 1;
+                let mut max = max_running.load(Ordering::SeqCst);
+                while max < n {
+                    match max_running.compare_exchange_weak(
+                        max,
+                        n,
+                        Ordering::SeqCst,
+                        Ordering::SeqCst,
+                    ) {
+                        Ok(_) => break,
+                        Err(actual) => max = actual,
+                    }
+                }
+                // later jobs finish first
+                std::thread::sleep(Duration::from_millis(20 - i));
+                running.fetch_sub(1, Ordering::SeqCst);
+                if i == 7 {
+                    Err(i)
+                } else {
+                    Ok(i)
+                }
+            }
+        });
+
+        let results: Vec<_> = block_on(pool.run_all(2, jobs).collect());
+        assert_eq!(results.len(), 20);
+        for (i, res) in results.into_iter().enumerate() {
+            match res {
+                Ok(v) => assert_eq!(v, i as u64),
+                Err(BlockingError::Error(7)) => assert_eq!(i, 7),
+                Err(_) => panic!("unexpected error"),
+            }
+        }
+        assert!(max_running.load(Ordering::SeqCst) <= 2);
+    }
+}
@@ -0,0 +1,81 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::RwLock;
+
+use crate::builder::AlreadyInitialized;
+
+lazy_static::lazy_static! {
+    static ref OBSERVER: RwLock<Option<Arc<dyn PoolObserver>>> = RwLock::new(None);
+}
+
+/// Observer is used by submitted jobs and can not be replaced
+static USED: AtomicBool = AtomicBool::new(false);
+
+/// Receiver of pool lifecycle events, see `set_observer()`.
+///
+/// Callbacks are called for every pool without holding any pool lock.
+/// Job and thread callbacks run on worker threads, so a slow observer
+/// delays jobs but does not block the pool. Callbacks must not panic.
+/// All callbacks do nothing by default.
+pub trait PoolObserver: Send + Sync + 'static {
+    /// Job got queued, `depth` is the queue length including the job.
+    ///
+    /// Called on the submitting thread.
+    fn job_enqueued(&self, _depth: usize) {}
+
+    /// Job started after waiting in the queue for `wait`.
+    fn job_started(&self, _wait: Duration) {}
+
+    /// Job finished after running for `run`.
+    fn job_finished(&self, _run: Duration, _panicked: bool) {}
+
+    /// Worker thread started, called on the new thread.
+    fn thread_spawned(&self) {}
+
+    /// Worker thread is about to exit, called on the exiting thread.
+    fn thread_exited(&self) {}
+}
+
+/// Register observer of lifecycle events of all pools.
+///
+/// Observer can be replaced until the first job is submitted, afterwards
+/// `AlreadyInitialized` error is returned.
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicUsize, Ordering};
+/// use std::time::Duration;
+///
+/// #[derive(Default)]
+/// struct Metrics {
+///     finished: AtomicUsize,
+/// }
+///
+/// impl actix_threadpool::PoolObserver for Metrics {
+///     fn job_finished(&self, _run: Duration, _panicked: bool) {
+///         self.finished.fetch_add(1, Ordering::Relaxed);
+///     }
+/// }
+///
+/// actix_threadpool::set_observer(Metrics::default()).unwrap();
+/// ```
+pub fn set_observer<T: PoolObserver>(observer: T) -> Result<(), AlreadyInitialized> {
+    let mut current = OBSERVER.write();
+    if USED.load(Ordering::SeqCst) {
+        return Err(AlreadyInitialized);
+    }
+    *current = Some(Arc::new(observer));
+    Ok(())
+}
+
+/// Current observer.
+pub(crate) fn current() -> Option<Arc<dyn PoolObserver>> {
+    OBSERVER.read().clone()
+}
+
+/// Current observer, it can not be replaced afterwards.
+pub(crate) fn for_job() -> Option<Arc<dyn PoolObserver>> {
+    USED.store(true, Ordering::SeqCst);
+    current()
+}
@@ -0,0 +1,971 @@
+use std::any::Any;
+use std::cell::Cell;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use derive_more::Display;
+use futures::channel::{mpsc, oneshot};
+use futures::future::{self, Either, FutureExt};
+use futures::stream::{FuturesUnordered, Stream, StreamExt};
+use futures::SinkExt;
+
+use crate::builder::Builder;
+use crate::observer;
+use crate::semaphore::{Permit, Semaphore};
+use crate::slot;
+use crate::state::StatefulPool;
+use crate::worker::{Job, Route, Task, TaskId, TaskInfo, Workers};
+use crate::{
+    panic_message, BlockingError, BlockingFuture, BlockingStream, CpuFuture, Progress,
+};
+
+/// Number of items buffered by `run_stream()`
+const STREAM_BUFFER: usize = 16;
+
+/// Number of reports buffered by `run_with_progress()`
+const PROGRESS_BUFFER: usize = 16;
+
+thread_local! {
+    /// Worker thread has to be restarted after current job
+    static RESTART: Cell<bool> = Cell::new(false);
+
+    /// Current job panicked
+    static PANICKED: Cell<bool> = Cell::new(false);
+}
+
+/// What the pool does if a blocking function panics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PanicPolicy {
+    /// Catch the panic and resolve the job future with
+    /// `BlockingError::Panic`. This is the default.
+    CatchAndReturn,
+    /// Abort the process, for code where unwinding is unsafe.
+    Abort,
+    /// Resolve the job future with `BlockingError::Panic`, log the panic
+    /// and replace the worker thread with a new one.
+    RestartThread,
+}
+
+impl PanicPolicy {
+    /// Apply policy to the caught panic, returns panic message.
+    fn handle(self, payload: Box<dyn Any + Send>) -> String {
+        let msg = panic_message(payload);
+        PANICKED.with(|panicked| panicked.set(true));
+        match self {
+            PanicPolicy::CatchAndReturn => (),
+            PanicPolicy::Abort => {
+                log::error!("Blocking function panicked, aborting: {}", msg);
+                std::process::abort();
+            }
+            PanicPolicy::RestartThread => RESTART.with(|restart| restart.set(true)),
+        }
+        msg
+    }
+}
+
+/// Where `run_or_inline()` executed the function
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Execution {
+    /// Function was queued on the pool
+    Pool,
+    /// Function was executed on the calling thread
+    Inline,
+}
+
+/// Blocking pool statistics snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Stats {
+    /// Max number of worker threads
+    pub max_threads: usize,
+    /// Number of spawned worker threads
+    pub threads: usize,
+    /// Number of worker threads executing a job
+    pub active_count: usize,
+    /// Number of jobs waiting for a free worker
+    pub queued_count: usize,
+    /// Total number of finished jobs
+    pub completed_jobs: usize,
+    /// Number of worker threads that died and got replaced
+    pub restarted_threads: usize,
+}
+
+/// Blocking pool health snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    /// Number of jobs waiting for a free worker
+    pub queued: usize,
+    /// Number of worker threads executing a job
+    pub active: usize,
+    /// Number of blocking functions that panicked
+    pub panics_since_start: usize,
+    /// Number of jobs rejected because the queue was full or the pool was
+    /// shut down
+    pub rejections_since_start: usize,
+    /// Queue is longer than the threshold, see `Pool::set_health_thresholds()`
+    pub saturated: bool,
+}
+
+/// Timings of a finished blocking job
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JobMetrics {
+    /// Time spent in the queue
+    pub queued: Duration,
+    /// Execution time
+    pub ran: Duration,
+    /// Label passed to `run_named()`
+    pub label: Option<&'static str>,
+}
+
+pub(crate) type JobCallback = Arc<dyn Fn(JobMetrics) + Send + Sync>;
+
+pub(crate) type JobWrapper = Arc<dyn Fn(Job) -> Job + Send + Sync>;
+
+/// Result of the pool shutdown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShutdownResult {
+    /// Number of jobs that did not finish before shutdown timeout
+    pub abandoned: usize,
+}
+
+impl ShutdownResult {
+    /// Returns `true` if all jobs finished before shutdown timeout.
+    pub fn is_clean(&self) -> bool {
+        self.abandoned == 0
+    }
+}
+
+/// Error returned by `resize()` if new pool size is zero
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+#[display(fmt = "Thread pool size must be greater than zero")]
+pub struct InvalidSize;
+
+impl std::error::Error for InvalidSize {}
+
+/// Error returned if the job can not be submitted to the pool
+#[derive(Debug, Display, Clone, Copy, PartialEq, Eq)]
+pub enum ExecuteError {
+    #[display(fmt = "Thread pool is overloaded")]
+    Overloaded,
+    #[display(fmt = "Thread pool is shut down")]
+    Shutdown,
+}
+
+impl std::error::Error for ExecuteError {}
+
+/// Handle to an independent blocking thread pool.
+///
+/// Handle is cheap to clone, all clones refer to the same pool.
+///
+/// ```rust
+/// use actix_threadpool::Pool;
+///
+/// let pool = Pool::builder().num_threads(4).name("disk").build();
+/// let fut = pool.run(|| std::fs::read_to_string("Cargo.toml"));
+/// ```
+#[derive(Clone)]
+pub struct Pool {
+    inner: Workers,
+    shared: Arc<Shared>,
+}
+
+/// Pool configuration
+pub(crate) struct PoolConfig {
+    pub(crate) name: String,
+    pub(crate) max_queue: Option<usize>,
+    pub(crate) on_job_complete: Option<JobCallback>,
+    pub(crate) wrap_job: Option<JobWrapper>,
+    pub(crate) panic_policy: PanicPolicy,
+    pub(crate) admission: Option<Semaphore>,
+    pub(crate) queue_deadline: Option<Duration>,
+}
+
+struct Shared {
+    name: String,
+    /// Max number of jobs waiting for a free worker
+    max_queue: Option<usize>,
+    /// Job instrumentation callback
+    on_job_complete: Option<JobCallback>,
+    /// Job wrapper, called on the submitting thread
+    wrap_job: Option<JobWrapper>,
+    panic_policy: PanicPolicy,
+    /// Jobs wait for a permit before they are submitted
+    admission: Option<Semaphore>,
+    /// Default queue deadline
+    queue_deadline: Option<Duration>,
+    /// Number of submitted jobs that have not finished yet
+    pending: AtomicUsize,
+    /// Number of finished jobs
+    completed: AtomicUsize,
+    /// Pool does not accept new jobs
+    closed: AtomicBool,
+    /// Queued jobs get dropped instead of execution
+    abandoned: AtomicBool,
+    /// Number of jobs that panicked
+    panics: AtomicUsize,
+    /// Number of jobs that were not accepted
+    rejections: AtomicUsize,
+    /// Queue length at which the pool is reported as saturated,
+    /// `usize::MAX` for the number of worker threads
+    saturation_queue: AtomicUsize,
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("name", &self.shared.name)
+            .field("max_queue", &self.shared.max_queue)
+            .field("panic_policy", &self.shared.panic_policy)
+            .finish()
+    }
+}
+
+impl Pool {
+    /// Create pool builder.
+    pub fn builder() -> Builder {
+        Builder::new()
+    }
+
+    pub(crate) fn new(inner: Workers, cfg: PoolConfig) -> Self {
+        Pool {
+            inner,
+            shared: Arc::new(Shared {
+                name: cfg.name,
+                max_queue: cfg.max_queue,
+                on_job_complete: cfg.on_job_complete,
+                wrap_job: cfg.wrap_job,
+                panic_policy: cfg.panic_policy,
+                admission: cfg.admission,
+                queue_deadline: cfg.queue_deadline,
+                pending: AtomicUsize::new(0),
+                completed: AtomicUsize::new(0),
+                closed: AtomicBool::new(false),
+                abandoned: AtomicBool::new(false),
+                panics: AtomicUsize::new(0),
+                rejections: AtomicUsize::new(0),
+                saturation_queue: AtomicUsize::new(std::usize::MAX),
+            }),
+        }
+    }
+
+    /// Name of the pool.
+    pub fn name(&self) -> &str {
+        &self.shared.name
+    }
+
+    /// Panic policy of the pool.
+    pub fn panic_policy(&self) -> PanicPolicy {
+        self.shared.panic_policy
+    }
+
+    /// Execute blocking function on the pool, returns future that resolves
+    /// to result of the function execution.
+    pub fn run<F, I, E>(&self, f: F) -> CpuFuture<I, E>
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        CpuFuture {
+            fut: self.run_fn(f),
+        }
+    }
+
+    /// Execute blocking function on the pool, same as `run()`.
+    ///
+    /// If the job is still queued at `deadline`, it gets dropped on the
+    /// worker thread without execution and the future resolves with
+    /// `BlockingError::DeadlineExceeded`. Overrides the default deadline
+    /// set with `Builder::queue_deadline()`.
+    pub fn run_with_deadline<F, I, E>(&self, deadline: Instant, f: F) -> CpuFuture<I, E>
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        CpuFuture {
+            fut: self.spawn_fn(None, Route::default(), Some(deadline), f).1,
+        }
+    }
+
+    /// Execute blocking function on the pool, same as `run()`.
+    ///
+    /// `label` is passed to the job instrumentation callback, see
+    /// `Builder::on_job_complete()`.
+    pub fn run_named<F, I, E>(&self, label: &'static str, f: F) -> CpuFuture<I, E>
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        CpuFuture {
+            fut: self
+                .spawn_fn(Some(label), Route::default(), self.default_deadline(), f)
+                .1,
+        }
+    }
+
+    /// Execute blocking function that can not fail on the pool, returns
+    /// future that resolves to the return value of the function.
+    ///
+    /// Future resolves with `BlockingError::Panic` if the function panics,
+    /// with `BlockingError::Overloaded` if job queue is full and
+    /// with `BlockingError::Canceled` if thread pool is shut down.
+    pub fn run_fn<F, R>(&self, f: F) -> BlockingFuture<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.spawn_fn(None, Route::default(), self.default_deadline(), f)
+            .1
+    }
+
+    /// Get handle to the pool whose workers own a state of type `T`, see
+    /// `StatefulPool::run_with_state()`.
+    ///
+    /// Every worker thread creates its own state with `init` when it runs
+    /// the first job that needs it. State never leaves its thread, so it
+    /// does not have to be `Send` or `Sync`, and it is dropped once the
+    /// thread exits.
+    ///
+    /// ```rust
+    /// use actix_threadpool::Pool;
+    ///
+    /// let pool = Pool::builder().build().with_state(|| Vec::<u8>::with_capacity(4096));
+    /// let fut = pool.run_with_state(|buf: &mut Vec<u8>| {
+    ///     buf.clear();
+    ///     buf.extend_from_slice(b"data");
+    ///     Ok::<_, ()>(buf.len())
+    /// });
+    /// ```
+    pub fn with_state<T, N>(&self, init: N) -> StatefulPool<T>
+    where
+        T: 'static,
+        N: Fn() -> T + Send + Sync + 'static,
+    {
+        StatefulPool::new(self.clone(), init)
+    }
+
+    /// Execute blocking function on the pool on behalf of a tenant, same
+    /// as `run()`.
+    ///
+    /// Every tenant has its own queue, free workers take jobs from tenant
+    /// queues in weighted round-robin order, so a tenant with many queued
+    /// jobs can not starve the others. Jobs submitted without tenant form
+    /// a queue of their own with weight 1.
+    pub fn run_as<K, F, I, E>(&self, key: K, f: F) -> CpuFuture<I, E>
+    where
+        K: Into<Arc<str>>,
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let route = Route {
+            tenant: Some(key.into()),
+            lane: None,
+        };
+        CpuFuture {
+            fut: self.spawn_fn(None, route, self.default_deadline(), f).1,
+        }
+    }
+
+    /// Execute blocking function on the pool, jobs with the same `key` run
+    /// on the same worker thread in submission order.
+    ///
+    /// Key is hashed to one of the pinned lanes, see
+    /// `Builder::pinned_lanes()`, and lane `i` is served by worker thread
+    /// `i % num_threads`. Worker threads run jobs of their lanes before
+    /// other jobs. Lane owners change if the pool is resized, and with
+    /// `Builder::idle_timeout()` an idle owner thread exits and gets
+    /// replaced, so thread local caches should tolerate being rebuilt.
+    pub fn run_pinned<K, F, I, E>(&self, key: K, f: F) -> CpuFuture<I, E>
+    where
+        K: Hash,
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let route = Route {
+            tenant: None,
+            lane: Some((hasher.finish() % self.inner.lanes().max(1) as u64) as usize),
+        };
+        CpuFuture {
+            fut: self.spawn_fn(None, route, self.default_deadline(), f).1,
+        }
+    }
+
+    /// Set weight of a tenant, number of its jobs dispatched in a row
+    /// before workers move to the next tenant.
+    ///
+    /// Tenants have weight 1 unless set otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `weight` is 0.
+    pub fn set_weight<K: Into<Arc<str>>>(&self, key: K, weight: usize) {
+        self.inner.set_weight(key.into(), weight)
+    }
+
+    /// Execute blocking function on the pool, same as `run()`, returns id
+    /// of the job along with its future.
+    ///
+    /// Id can be matched against `dump_pending()` output.
+    pub fn run_traced<F, I, E>(&self, f: F) -> (TaskId, CpuFuture<I, E>)
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let (id, fut) = self.spawn_fn(None, Route::default(), self.default_deadline(), f);
+        (id, CpuFuture { fut })
+    }
+
+    fn default_deadline(&self) -> Option<Instant> {
+        self.shared
+            .queue_deadline
+            .map(|timeout| Instant::now() + timeout)
+    }
+
+    fn spawn_fn<F, R>(
+        &self,
+        label: Option<&'static str>,
+        route: Route,
+        deadline: Option<Instant>,
+        f: F,
+    ) -> (TaskId, BlockingFuture<R>)
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let id = TaskId::next();
+        let (tx, rx) = slot::channel();
+        let policy = self.shared.panic_policy;
+        let job = move || {
+            // job could be aborted while it was queued
+            if tx.claim() {
+                let res = if deadline.map(|d| Instant::now() > d).unwrap_or(false) {
+                    // closure is dropped on the worker, destructors may block
+                    drop(f);
+                    Err(BlockingError::DeadlineExceeded)
+                } else {
+                    panic::catch_unwind(AssertUnwindSafe(f))
+                        .map_err(|e| BlockingError::Panic(policy.handle(e)))
+                };
+                tx.send(res);
+            }
+        };
+
+        let fut = match self.shared.admission {
+            Some(ref sem) => {
+                // job is submitted by the future once it gets a permit
+                let pool = self.clone();
+                let submit: SubmitFn = Box::new(move |permit| {
+                    pool.submit_task(id, label, route, move || {
+                        let _permit = permit;
+                        job()
+                    })
+                });
+                BlockingFuture {
+                    rx,
+                    error: None,
+                    admission: Some((sem.acquire(), submit)),
+                    terminated: false,
+                }
+            }
+            None => BlockingFuture {
+                rx,
+                error: self.submit_task(id, label, route, job).err(),
+                admission: None,
+                terminated: false,
+            },
+        };
+        (id, fut)
+    }
+
+    /// Execute blocking functions on the pool, returned future resolves to
+    /// their results in submission order.
+    ///
+    /// Jobs are submitted once the future is polled. A panic in one job
+    /// does not affect the others.
+    pub fn run_batch<J, T, E>(
+        &self,
+        jobs: J,
+    ) -> impl Future<Output = Vec<Result<T, BlockingError<E>>>>
+    where
+        J: IntoIterator,
+        J::Item: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        self.run_batch_limited(std::usize::MAX, jobs)
+    }
+
+    /// Execute blocking functions on the pool, same as `run_batch()`, but
+    /// with at most `max_concurrency` of them submitted at once.
+    pub fn run_batch_limited<J, T, E>(
+        &self,
+        max_concurrency: usize,
+        jobs: J,
+    ) -> impl Future<Output = Vec<Result<T, BlockingError<E>>>>
+    where
+        J: IntoIterator,
+        J::Item: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let pool = self.clone();
+        let jobs: Vec<_> = jobs.into_iter().collect();
+        let limit = std::cmp::max(max_concurrency, 1);
+
+        async move {
+            let mut results: Vec<_> = jobs.iter().map(|_| None).collect();
+            let mut running = FuturesUnordered::new();
+
+            for (idx, job) in jobs.into_iter().enumerate() {
+                if running.len() >= limit {
+                    if let Some((idx, res)) = running.next().await {
+                        results[idx] = Some(res);
+                    }
+                }
+                running.push(pool.run(job).map(move |res| (idx, res)));
+            }
+            while let Some((idx, res)) = running.next().await {
+                results[idx] = Some(res);
+            }
+
+            results
+                .into_iter()
+                .map(|res| res.unwrap_or(Err(BlockingError::Canceled)))
+                .collect()
+        }
+    }
+
+    /// Execute blocking functions produced by the stream on the pool,
+    /// returns stream of their results in the order of `jobs`.
+    ///
+    /// At most `limit` functions are submitted at once, next one is taken
+    /// from `jobs` once the earliest running one finishes, so at most
+    /// `limit` results are buffered. Dropping the stream cancels functions
+    /// that have not started yet.
+    pub fn run_all<S, T, E>(
+        &self,
+        limit: usize,
+        jobs: S,
+    ) -> impl Stream<Item = Result<T, BlockingError<E>>>
+    where
+        S: Stream,
+        S::Item: FnOnce() -> Result<T, E> + Send + 'static,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let pool = self.clone();
+        jobs.map(move |job| pool.run(job))
+            .buffered(std::cmp::max(limit, 1))
+    }
+
+    /// Execute blocking iterator on the pool, returns stream of its items.
+    ///
+    /// Iterator is created and consumed on a worker thread. Worker pauses
+    /// while the stream buffer is full, dropping the stream stops the
+    /// iteration at the next item.
+    pub fn run_stream<F, I, T, E>(&self, f: F) -> BlockingStream<T, E>
+    where
+        F: FnOnce() -> I + Send + 'static,
+        I: IntoIterator<Item = Result<T, E>>,
+        T: Send + 'static,
+        E: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER);
+        let mut err_tx = tx.clone();
+        let policy = self.shared.panic_policy;
+
+        let res = self.submit(None, move || {
+            let mut tx = tx;
+            if tx.is_closed() {
+                return;
+            }
+            let res = panic::catch_unwind(AssertUnwindSafe(|| {
+                for item in f() {
+                    let item = item.map_err(BlockingError::Error);
+                    if futures::executor::block_on(tx.send(item)).is_err() {
+                        // stream is dropped
+                        return;
+                    }
+                }
+            }));
+            if let Err(e) = res {
+                let err = BlockingError::Panic(policy.handle(e));
+                let _ = futures::executor::block_on(tx.send(Err(err)));
+            }
+        });
+        if let Err(e) = res {
+            // every sender has guaranteed slot in the channel
+            let _ = err_tx.try_send(Err(e.into()));
+        }
+
+        BlockingStream { rx }
+    }
+
+    /// Execute blocking function on the pool, or on the calling thread if
+    /// more than `max_queue` jobs are queued.
+    ///
+    /// Inline execution blocks the calling thread, and so every future of
+    /// its event loop, until the function returns, so it must be used only
+    /// for functions that finish in well under a millisecond. Returned
+    /// future of inline execution is ready immediately, panics are caught
+    /// and reported as `BlockingError::Panic` in both cases.
+    pub fn run_or_inline<F, I, E>(&self, max_queue: usize, f: F) -> (Execution, CpuFuture<I, E>)
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        if self.inner.queued_count() <= max_queue {
+            return (Execution::Pool, self.run(f));
+        }
+
+        let policy = self.shared.panic_policy;
+        let res = panic::catch_unwind(AssertUnwindSafe(f)).map_err(|e| match policy {
+            PanicPolicy::Abort => BlockingError::Panic(policy.handle(e)),
+            // calling thread is not a worker, it can not be restarted
+            _ => BlockingError::Panic(panic_message(e)),
+        });
+        (
+            Execution::Inline,
+            CpuFuture {
+                fut: BlockingFuture::ready(res),
+            },
+        )
+    }
+
+    /// Execute blocking function that reports its progress on the pool,
+    /// returns future of the function result and stream of progress
+    /// reports.
+    ///
+    /// Reports are `(done, total)` pairs, see `Progress::report()`. Stream
+    /// ends once the function finishes. Dropping the stream does not affect
+    /// the function, its reports are discarded.
+    ///
+    /// ```rust
+    /// use actix_threadpool::Pool;
+    ///
+    /// let pool = Pool::builder().build();
+    /// let (fut, progress) = pool.run_with_progress(|mut progress| {
+    ///     for i in 0..10 {
+    ///         // import chunk
+    ///         progress.report(i + 1, 10);
+    ///     }
+    ///     Ok::<_, ()>(())
+    /// });
+    /// ```
+    pub fn run_with_progress<F, I, E>(
+        &self,
+        f: F,
+    ) -> (CpuFuture<I, E>, impl Stream<Item = (u64, u64)>)
+    where
+        F: FnOnce(Progress) -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel(PROGRESS_BUFFER);
+        let progress = Progress { tx };
+        (self.run(move || f(progress)), rx)
+    }
+
+    /// Change number of worker threads.
+    ///
+    /// New threads are spawned on demand (immediately for eager pools), on
+    /// shrinking extra threads exit once they finish their current job.
+    /// Queued jobs are preserved.
+    pub fn resize(&self, num_threads: usize) -> Result<(), InvalidSize> {
+        if num_threads == 0 {
+            return Err(InvalidSize);
+        }
+        self.inner.set_num_threads(num_threads);
+        Ok(())
+    }
+
+    /// Stop accepting new jobs and wait until all submitted jobs finish.
+    ///
+    /// If `timeout` elapses first, jobs that are still queued are dropped
+    /// without execution and returned result reports number of unfinished
+    /// jobs. Jobs that are already running can not be interrupted.
+    ///
+    /// Shutdown affects all clones of the pool handle.
+    pub fn shutdown(&self, timeout: Option<Duration>) -> impl Future<Output = ShutdownResult> {
+        self.shared.closed.store(true, Ordering::SeqCst);
+
+        // wait for jobs on a separate thread, `Workers::join()` blocks
+        let (tx, rx) = oneshot::channel();
+        let inner = self.inner.clone();
+        let shared = self.shared.clone();
+        let _ = thread::Builder::new()
+            .name(format!("{}-shutdown", self.shared.name))
+            .spawn(move || {
+                while shared.pending.load(Ordering::SeqCst) != 0 {
+                    inner.join();
+                    thread::yield_now();
+                }
+                let _ = tx.send(());
+            });
+
+        let shared = self.shared.clone();
+        let delay = timeout.map(tokio_timer::delay_for);
+        async move {
+            match delay {
+                Some(delay) => {
+                    if let Either::Right(_) = future::select(rx, delay).await {
+                        shared.abandoned.store(true, Ordering::SeqCst);
+                    }
+                }
+                None => {
+                    let _ = rx.await;
+                }
+            }
+            ShutdownResult {
+                abandoned: shared.pending.load(Ordering::SeqCst),
+            }
+        }
+    }
+
+    /// Execute blocking function on the pool without waiting for its result.
+    ///
+    /// Panics in the function are logged and handled according to the
+    /// panic policy of the pool.
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let policy = self.shared.panic_policy;
+        self.submit(None, move || {
+            if let Err(e) = panic::catch_unwind(AssertUnwindSafe(f)) {
+                log::error!("Blocking function panicked: {}", policy.handle(e));
+            }
+        })
+    }
+
+    /// Submit job to the pool.
+    pub(crate) fn submit<F>(
+        &self,
+        label: Option<&'static str>,
+        f: F,
+    ) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit_task(TaskId::next(), label, Route::default(), f)
+    }
+
+    /// Submit job with preallocated id to the pool.
+    fn submit_task<F>(
+        &self,
+        id: TaskId,
+        label: Option<&'static str>,
+        route: Route,
+        f: F,
+    ) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let pending = &self.shared.pending;
+        if let Some(max_queue) = self.shared.max_queue {
+            // reserve slot, pending jobs beyond number of workers are queued
+            let limit = self.inner.max_count() + max_queue;
+            let mut count = pending.load(Ordering::Acquire);
+            loop {
+                if count >= limit {
+                    self.shared.rejections.fetch_add(1, Ordering::Relaxed);
+                    return Err(ExecuteError::Overloaded);
+                }
+                match pending.compare_exchange_weak(
+                    count,
+                    count + 1,
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => break,
+                    Err(val) => count = val,
+                }
+            }
+        } else {
+            pending.fetch_add(1, Ordering::AcqRel);
+        }
+
+        // job is counted as pending before the check, so shutdown can not miss it
+        let guard = PendingGuard(self.shared.clone());
+        if self.shared.closed.load(Ordering::SeqCst) {
+            self.shared.rejections.fetch_add(1, Ordering::Relaxed);
+            return Err(ExecuteError::Shutdown);
+        }
+
+        let enqueued = Instant::now();
+        let observer = observer::for_job();
+        let observer2 = observer.clone();
+
+        let job: Job = Box::new(move || {
+            let shared = &guard.0;
+            if shared.abandoned.load(Ordering::Acquire) {
+                return;
+            }
+
+            let started = Instant::now();
+            if let Some(ref observer) = observer2 {
+                observer.job_started(started - enqueued);
+            }
+            f();
+            let ran = started.elapsed();
+            let panicked = PANICKED.with(|panicked| panicked.replace(false));
+            if panicked {
+                shared.panics.fetch_add(1, Ordering::Relaxed);
+            }
+            shared.completed.fetch_add(1, Ordering::AcqRel);
+
+            if let Some(ref observer) = observer2 {
+                observer.job_finished(ran, panicked);
+            }
+            if let Some(ref on_complete) = shared.on_job_complete {
+                on_complete(JobMetrics {
+                    label,
+                    queued: started - enqueued,
+                    ran,
+                });
+            }
+
+            if RESTART.with(|restart| restart.replace(false)) {
+                // worker thread that panicked gets replaced
+                panic::resume_unwind(Box::new("blocking function panicked"));
+            }
+        });
+        let job = match self.shared.wrap_job {
+            Some(ref wrap) => wrap(job),
+            None => job,
+        };
+        let depth = self.inner.execute(Task {
+            info: TaskInfo {
+                id,
+                label,
+                enqueued,
+                started: None,
+            },
+            route,
+            job,
+        });
+        if let Some(observer) = observer {
+            observer.job_enqueued(depth);
+        }
+        Ok(())
+    }
+
+    /// Spawn all worker threads and run their start hooks.
+    ///
+    /// Returned future resolves once every worker thread has started.
+    /// Calling it again, or for a pool that is already warmed up, does not
+    /// spawn extra threads.
+    pub fn warm_up(&self) -> impl Future<Output = ()> {
+        self.inner.warm_up()
+    }
+
+    /// Returned future resolves once no job is queued or running.
+    ///
+    /// Jobs submitted while waiting postpone resolution, future resolves
+    /// only at the moment the pool becomes idle.
+    pub fn wait_idle(&self) -> impl Future<Output = ()> {
+        self.inner.wait_idle()
+    }
+
+    /// Snapshot of running and queued jobs.
+    ///
+    /// Job queue is locked only for the time of copying, so it is cheap
+    /// enough to be called periodically from a watchdog thread.
+    pub fn dump_pending(&self) -> Vec<TaskInfo> {
+        self.inner.tasks()
+    }
+
+    /// Number of jobs waiting for a free worker.
+    pub fn queue_depth(&self) -> usize {
+        self.inner.queued_count()
+    }
+
+    /// Returned future resolves once at most `depth` jobs are waiting for a
+    /// free worker.
+    pub(crate) fn wait_queue_depth(&self, depth: usize) -> impl Future<Output = ()> {
+        self.inner.wait_queue(depth)
+    }
+
+    /// Pool health snapshot, suitable for readiness probes.
+    pub fn health(&self) -> Health {
+        let queued = self.inner.queued_count();
+        let threshold = match self.shared.saturation_queue.load(Ordering::Relaxed) {
+            std::usize::MAX => self.inner.max_count(),
+            threshold => threshold,
+        };
+        Health {
+            queued,
+            active: self.inner.active_count(),
+            panics_since_start: self.shared.panics.load(Ordering::Relaxed),
+            rejections_since_start: self.shared.rejections.load(Ordering::Relaxed),
+            saturated: queued > threshold,
+        }
+    }
+
+    /// Set number of queued jobs above which `health()` reports the pool
+    /// as saturated.
+    ///
+    /// Defaults to the number of worker threads.
+    pub fn set_health_thresholds(&self, max_queued: usize) {
+        self.shared
+            .saturation_queue
+            .store(max_queued, Ordering::Relaxed);
+    }
+
+    /// Number of queued jobs per pinned lane, see `run_pinned()`.
+    pub fn stats_per_lane(&self) -> Vec<usize> {
+        self.inner.lane_depths()
+    }
+
+    /// Number of queued jobs per tenant.
+    ///
+    /// Tenants with no queued jobs are reported only if their weight
+    /// is set.
+    pub fn stats_per_tenant(&self) -> HashMap<String, usize> {
+        self.inner.tenants()
+    }
+
+    /// Number of worker threads that panicked.
+    #[cfg(test)]
+    pub(crate) fn panic_count(&self) -> usize {
+        self.inner.panic_count()
+    }
+
+    /// Pool statistics snapshot.
+    pub fn stats(&self) -> Stats {
+        Stats {
+            max_threads: self.inner.max_count(),
+            threads: self.inner.thread_count(),
+            active_count: self.inner.active_count(),
+            queued_count: self.inner.queued_count(),
+            completed_jobs: self.shared.completed.load(Ordering::Acquire),
+            restarted_threads: self.inner.restarted_count(),
+        }
+    }
+}
+
+/// Submits job that waited for admission
+pub(crate) type SubmitFn = Box<dyn FnOnce(Permit) -> Result<(), ExecuteError> + Send>;
+
+/// Decrements pending jobs counter once job is finished or dropped
+struct PendingGuard(Arc<Shared>);
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.0.pending.fetch_sub(1, Ordering::AcqRel);
+    }
+}
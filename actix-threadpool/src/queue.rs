@@ -0,0 +1,238 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+
+use crate::worker::{Scheduling, Task};
+
+/// Job queue, jobs of different tenants are dispatched in weighted
+/// round-robin order, pinned jobs wait in lanes of their worker threads
+pub(crate) struct Queue {
+    /// Jobs submitted without tenant
+    default: Lane,
+    tenants: HashMap<Arc<str>, Lane>,
+    /// Lanes with queued jobs in dispatch order, `None` is the default lane
+    ready: VecDeque<Option<Arc<str>>>,
+    /// Pinned jobs, lane `i` is served by thread `i % max_threads`
+    lanes: Vec<VecDeque<Task>>,
+    /// Number of pinned jobs
+    pinned: usize,
+    len: usize,
+}
+
+struct Lane {
+    /// Number of jobs dispatched in a row
+    weight: usize,
+    /// Number of jobs dispatched in the current turn
+    served: usize,
+    jobs: VecDeque<Task>,
+}
+
+impl Lane {
+    fn new() -> Self {
+        Lane {
+            weight: 1,
+            served: 0,
+            jobs: VecDeque::new(),
+        }
+    }
+}
+
+impl Queue {
+    pub(crate) fn new(lanes: usize) -> Self {
+        Queue {
+            default: Lane::new(),
+            tenants: HashMap::new(),
+            ready: VecDeque::new(),
+            lanes: (0..lanes).map(|_| VecDeque::new()).collect(),
+            pinned: 0,
+            len: 0,
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Number of jobs that can run on any thread
+    pub(crate) fn unpinned_len(&self) -> usize {
+        self.len - self.pinned
+    }
+
+    /// Number of pinned lanes
+    pub(crate) fn lanes(&self) -> usize {
+        self.lanes.len()
+    }
+
+    /// Thread `index` has pinned jobs queued.
+    pub(crate) fn has_pinned(&self, index: usize, max_threads: usize) -> bool {
+        self.owned_lanes(index, max_threads)
+            .any(|lane| !self.lanes[lane].is_empty())
+    }
+
+    fn owned_lanes(&self, index: usize, max_threads: usize) -> impl Iterator<Item = usize> {
+        // excess threads do not own lanes
+        let start = if index < max_threads {
+            index
+        } else {
+            self.lanes.len()
+        };
+        (start..self.lanes.len()).step_by(max_threads.max(1))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = &Task> {
+        self.default
+            .jobs
+            .iter()
+            .chain(self.tenants.values().flat_map(|lane| lane.jobs.iter()))
+            .chain(self.lanes.iter().flatten())
+    }
+
+    pub(crate) fn push(&mut self, task: Task) {
+        if let Some(lane) = task.route.lane {
+            self.lanes[lane].push_back(task);
+            self.pinned += 1;
+            self.len += 1;
+            return;
+        }
+
+        let lane = match task.route.tenant {
+            Some(ref key) => self.tenants.entry(key.clone()).or_insert_with(Lane::new),
+            None => &mut self.default,
+        };
+        if lane.jobs.is_empty() {
+            self.ready.push_back(task.route.tenant.clone());
+        }
+        lane.jobs.push_back(task);
+        self.len += 1;
+    }
+
+    /// Take next job for thread `index`, pinned jobs of the thread go
+    /// first. `scheduling` selects job within a tenant lane, pinned lanes
+    /// are always FIFO.
+    pub(crate) fn pop(
+        &mut self,
+        scheduling: Scheduling,
+        index: usize,
+        max_threads: usize,
+    ) -> Option<Task> {
+        let lane = self
+            .owned_lanes(index, max_threads)
+            .find(|lane| !self.lanes[*lane].is_empty());
+        if let Some(lane) = lane {
+            self.pinned -= 1;
+            self.len -= 1;
+            return self.lanes[lane].pop_front();
+        }
+
+        let key = self.ready.front()?.clone();
+        let lane = match key {
+            Some(ref key) => self.tenants.get_mut(key).unwrap(),
+            None => &mut self.default,
+        };
+        // lanes in ready list are not empty
+        let task = match scheduling {
+            Scheduling::Fifo => lane.jobs.pop_front(),
+            Scheduling::Lifo => lane.jobs.pop_back(),
+        }?;
+        self.len -= 1;
+
+        lane.served += 1;
+        if lane.jobs.is_empty() || lane.served >= lane.weight {
+            lane.served = 0;
+            self.ready.pop_front();
+            if !lane.jobs.is_empty() {
+                self.ready.push_back(key);
+            } else if lane.weight == 1 {
+                // do not keep tenants with default settings around
+                if let Some(ref key) = key {
+                    self.tenants.remove(key);
+                }
+            }
+        }
+        Some(task)
+    }
+
+    /// Set number of jobs dispatched for the tenant in a row.
+    pub(crate) fn set_weight(&mut self, key: Arc<str>, weight: usize) {
+        assert!(weight > 0, "Tenant weight must be greater than 0");
+        match self.tenants.get_mut(&key) {
+            Some(lane) => {
+                lane.weight = weight;
+                if weight == 1 && lane.jobs.is_empty() {
+                    self.tenants.remove(&key);
+                }
+            }
+            None if weight != 1 => {
+                let mut lane = Lane::new();
+                lane.weight = weight;
+                self.tenants.insert(key, lane);
+            }
+            None => (),
+        }
+    }
+
+    /// Number of queued jobs per pinned lane.
+    pub(crate) fn lane_depths(&self) -> Vec<usize> {
+        self.lanes.iter().map(|lane| lane.len()).collect()
+    }
+
+    /// Number of queued jobs per tenant.
+    pub(crate) fn tenants(&self) -> HashMap<String, usize> {
+        self.tenants
+            .iter()
+            .map(|(key, lane)| (key.to_string(), lane.jobs.len()))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::worker::{Route, TaskId, TaskInfo};
+    use std::time::Instant;
+
+    fn task(tenant: &'static str) -> Task {
+        Task {
+            info: TaskInfo {
+                id: TaskId::next(),
+                label: Some(tenant),
+                enqueued: Instant::now(),
+                started: None,
+            },
+            route: Route {
+                tenant: Some(tenant.into()),
+                lane: None,
+            },
+            job: Box::new(|| ()),
+        }
+    }
+
+    #[test]
+    fn test_weighted_round_robin() {
+        let mut queue = Queue::new(0);
+        queue.set_weight("a".into(), 2);
+        for _ in 0..5 {
+            queue.push(task("a"));
+        }
+        for _ in 0..3 {
+            queue.push(task("b"));
+        }
+        assert_eq!(queue.len(), 8);
+        assert_eq!(queue.tenants()["a"], 5);
+        assert_eq!(queue.tenants()["b"], 3);
+
+        let mut order = String::new();
+        while let Some(task) = queue.pop(Scheduling::Fifo, 0, 1) {
+            order.push_str(task.info.label.unwrap());
+        }
+        assert_eq!(order, "aabaabab");
+        assert!(queue.is_empty());
+
+        // tenant with default weight is forgotten once its queue is empty
+        assert_eq!(queue.tenants().len(), 1);
+        assert_eq!(queue.tenants()["a"], 0);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::task::{Context, Poll, Waker};
+use futures::Future;
+use parking_lot::Mutex;
+
+/// Async counting semaphore, waiters acquire permits in FIFO order
+#[derive(Clone)]
+pub(crate) struct Semaphore(Arc<Mutex<State>>);
+
+struct State {
+    permits: usize,
+    next_id: usize,
+    waiters: VecDeque<(usize, Waker)>,
+}
+
+impl State {
+    /// Wake first waiter if there is a permit for it
+    fn notify(&self) {
+        if self.permits > 0 {
+            if let Some((_, waker)) = self.waiters.front() {
+                waker.wake_by_ref();
+            }
+        }
+    }
+}
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        Semaphore(Arc::new(Mutex::new(State {
+            permits,
+            next_id: 0,
+            waiters: VecDeque::new(),
+        })))
+    }
+
+    /// Future that resolves once permit is acquired.
+    pub(crate) fn acquire(&self) -> Acquire {
+        Acquire {
+            sem: self.clone(),
+            id: None,
+        }
+    }
+}
+
+/// Permit is returned to the semaphore on drop
+pub(crate) struct Permit(Semaphore);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut st = (self.0).0.lock();
+        st.permits += 1;
+        st.notify();
+    }
+}
+
+pub(crate) struct Acquire {
+    sem: Semaphore,
+    /// Position in the waiters queue
+    id: Option<usize>,
+}
+
+impl Future for Acquire {
+    type Output = Permit;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Permit> {
+        let this = &mut *self;
+        let mut st = this.sem.0.lock();
+
+        match this.id {
+            None => {
+                if st.permits > 0 && st.waiters.is_empty() {
+                    st.permits -= 1;
+                    return Poll::Ready(Permit(this.sem.clone()));
+                }
+                let id = st.next_id;
+                st.next_id = id.wrapping_add(1);
+                st.waiters.push_back((id, cx.waker().clone()));
+                this.id = Some(id);
+                Poll::Pending
+            }
+            Some(id) => {
+                if st.permits > 0 && st.waiters.front().map(|w| w.0) == Some(id) {
+                    st.permits -= 1;
+                    st.waiters.pop_front();
+                    st.notify();
+                    this.id = None;
+                    return Poll::Ready(Permit(this.sem.clone()));
+                }
+                if let Some(w) = st.waiters.iter_mut().find(|w| w.0 == id) {
+                    if !w.1.will_wake(cx.waker()) {
+                        w.1 = cx.waker().clone();
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        if let Some(id) = self.id {
+            let mut st = self.sem.0.lock();
+            if let Some(idx) = st.waiters.iter().position(|w| w.0 == id) {
+                st.waiters.remove(idx);
+                if idx == 0 {
+                    st.notify();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    fn poll(fut: &mut Acquire) -> Poll<Permit> {
+        Pin::new(fut).poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn test_fifo() {
+        let sem = Semaphore::new(1);
+        let mut a = sem.acquire();
+        let mut b = sem.acquire();
+        let mut c = sem.acquire();
+
+        let permit = match poll(&mut a) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("permit is available"),
+        };
+        assert!(poll(&mut b).is_pending());
+        assert!(poll(&mut c).is_pending());
+        drop(permit);
+
+        // permit goes to the first waiter
+        assert!(poll(&mut c).is_pending());
+        let permit = match poll(&mut b) {
+            Poll::Ready(permit) => permit,
+            Poll::Pending => panic!("b is first in queue"),
+        };
+
+        // new acquire can not overtake waiters
+        drop(permit);
+        let mut d = sem.acquire();
+        assert!(poll(&mut d).is_pending());
+
+        // dropped waiter gives up its position
+        drop(c);
+        assert!(poll(&mut d).is_ready());
+    }
+}
@@ -0,0 +1,150 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::{BlockingFuture, CpuFuture, ExecuteError, Pool};
+
+thread_local! {
+    /// State created with `SerialPool::run_init()`
+    static STATE: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Handle to a single-thread blocking executor.
+///
+/// All jobs run on the same worker thread in submission order, so it can
+/// own resources that are not `Sync` or bound to a thread. Handle is cheap
+/// to clone, worker thread finishes queued jobs and exits once the last
+/// handle is dropped.
+///
+/// ```rust
+/// use actix_threadpool::SerialPool;
+///
+/// let pool = SerialPool::new("sqlite");
+/// let fut = pool.run_init(
+///     || std::collections::HashMap::<String, String>::new(),
+///     |cache| Ok::<_, ()>(cache.get("key").cloned()),
+/// );
+/// ```
+#[derive(Clone, Debug)]
+pub struct SerialPool {
+    pool: Pool,
+}
+
+impl SerialPool {
+    /// Create new executor, worker thread is named after the pool.
+    pub fn new<T: Into<String>>(name: T) -> Self {
+        SerialPool {
+            pool: Pool::builder().name(name).num_threads(1).build(),
+        }
+    }
+
+    /// Name of the pool.
+    pub fn name(&self) -> &str {
+        self.pool.name()
+    }
+
+    /// Execute blocking function on the worker thread, see `Pool::run()`.
+    pub fn run<F, I, E>(&self, f: F) -> CpuFuture<I, E>
+    where
+        F: FnOnce() -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool.run(f)
+    }
+
+    /// Execute blocking function that can not fail on the worker thread,
+    /// see `Pool::run_fn()`.
+    pub fn run_fn<F, R>(&self, f: F) -> BlockingFuture<R>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.pool.run_fn(f)
+    }
+
+    /// Execute blocking function with mutable access to the worker thread
+    /// state of type `S`.
+    ///
+    /// State is created with `init` by the first job that needs it and
+    /// lives until the worker thread exits. If `init` or `f` panics, the
+    /// job resolves with `BlockingError::Panic`, state is dropped and next
+    /// job calls `init` again.
+    pub fn run_init<S, N, F, I, E>(&self, init: N, f: F) -> CpuFuture<I, E>
+    where
+        S: 'static,
+        N: FnOnce() -> S + Send + 'static,
+        F: FnOnce(&mut S) -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        self.pool.run(move || {
+            let key = TypeId::of::<S>();
+            // take state out, so `f` can not observe borrowed map
+            let mut state = STATE
+                .with(|state| state.borrow_mut().remove(&key))
+                .unwrap_or_else(|| Box::new(init()));
+            let res = f(state.downcast_mut::<S>().unwrap());
+            STATE.with(|st| st.borrow_mut().insert(key, state));
+            res
+        })
+    }
+
+    /// Execute blocking function on the worker thread without waiting
+    /// for its result, see `Pool::execute()`.
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.pool.execute(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_serial_pool() {
+        let pool = SerialPool::new("serial");
+        let order = Arc::new(Mutex::new(Vec::new()));
+        for i in 0..10 {
+            let order = order.clone();
+            pool.execute(move || order.lock().push((i, std::thread::current().id())))
+                .unwrap();
+        }
+        let thread = block_on(pool.run_fn(|| std::thread::current().id())).unwrap();
+
+        let order = order.lock();
+        assert_eq!(
+            order.iter().map(|v| v.0).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+        assert!(order.iter().all(|v| v.1 == thread));
+    }
+
+    #[test]
+    fn test_run_init() {
+        let pool = SerialPool::new("serial");
+        let inits = Arc::new(Mutex::new(0));
+
+        for i in 1..4 {
+            let inits = inits.clone();
+            let res = block_on(pool.run_init(
+                move || {
+                    *inits.lock() += 1;
+                    Vec::new()
+                },
+                move |state: &mut Vec<usize>| {
+                    state.push(i);
+                    Ok::<_, ()>(state.len())
+                },
+            ));
+            assert_eq!(res.unwrap(), i);
+        }
+        assert_eq!(*inits.lock(), 1);
+    }
+}
@@ -0,0 +1,233 @@
+use std::fmt;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use actix_service::{NewService, Service};
+use futures::compat::Compat;
+use futures::FutureExt;
+use futures01::future::{ok, FutureResult};
+use futures01::{Async, Future as Future01, Poll};
+
+use crate::{with_pool, BlockingError, CpuFuture, Pool};
+
+/// Service that executes synchronous function on a thread pool.
+///
+/// ```rust
+/// use actix_service::Service;
+/// use actix_threadpool::BlockingService;
+///
+/// let mut srv = BlockingService::new(|path: String| std::fs::read_to_string(path));
+/// let fut = srv.call("Cargo.toml".to_owned());
+/// ```
+pub struct BlockingService<F, Req> {
+    f: Arc<F>,
+    pool: Option<Pool>,
+    queue_threshold: Option<usize>,
+    /// Waits for the pool queue to drain below the threshold
+    drained: Option<Compat<QueueDrained>>,
+    _t: PhantomData<fn(Req)>,
+}
+
+type QueueDrained = Pin<Box<dyn Future<Output = Result<(), ()>> + Send>>;
+
+impl<F, Req, Res, E> BlockingService<F, Req>
+where
+    F: Fn(Req) -> Result<Res, E> + Send + Sync + 'static,
+{
+    /// Create new service that uses current thread's pool.
+    pub fn new(f: F) -> Self {
+        BlockingService {
+            f: Arc::new(f),
+            pool: None,
+            queue_threshold: None,
+            drained: None,
+            _t: PhantomData,
+        }
+    }
+
+    /// Create new service that uses specified pool.
+    pub fn with_pool(pool: Pool, f: F) -> Self {
+        BlockingService {
+            f: Arc::new(f),
+            pool: Some(pool),
+            queue_threshold: None,
+            drained: None,
+            _t: PhantomData,
+        }
+    }
+
+    /// Sets pool queue depth above which service is not ready.
+    ///
+    /// Task that polls readiness is woken once workers take enough jobs off
+    /// the queue. By default service is always ready.
+    pub fn queue_threshold(mut self, threshold: usize) -> Self {
+        self.queue_threshold = Some(threshold);
+        self
+    }
+
+    fn queue_depth(&self) -> usize {
+        match self.pool {
+            Some(ref pool) => pool.queue_depth(),
+            None => with_pool(|pool| pool.queue_depth()),
+        }
+    }
+
+    fn queue_drained(&self, threshold: usize) -> QueueDrained {
+        let fut = match self.pool {
+            Some(ref pool) => pool.wait_queue_depth(threshold),
+            None => with_pool(|pool| pool.wait_queue_depth(threshold)),
+        };
+        Box::pin(fut.map(Ok))
+    }
+}
+
+impl<F, Req> Clone for BlockingService<F, Req> {
+    fn clone(&self) -> Self {
+        BlockingService {
+            f: self.f.clone(),
+            pool: self.pool.clone(),
+            queue_threshold: self.queue_threshold,
+            drained: None,
+            _t: PhantomData,
+        }
+    }
+}
+
+impl<F, Req> fmt::Debug for BlockingService<F, Req> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BlockingService")
+            .field("pool", &self.pool)
+            .field("queue_threshold", &self.queue_threshold)
+            .finish()
+    }
+}
+
+impl<F, Req, Res, E> Service for BlockingService<F, Req>
+where
+    F: Fn(Req) -> Result<Res, E> + Send + Sync + 'static,
+    Req: Send + 'static,
+    Res: Send + 'static,
+    E: Send + 'static,
+{
+    type Request = Req;
+    type Response = Res;
+    type Error = BlockingError<E>;
+    type Future = Compat<CpuFuture<Res, E>>;
+
+    fn poll_ready(&mut self) -> Poll<(), Self::Error> {
+        let threshold = match self.queue_threshold {
+            Some(threshold) => threshold,
+            None => return Ok(Async::Ready(())),
+        };
+        loop {
+            if let Some(ref mut drained) = self.drained {
+                if let Ok(Async::NotReady) = drained.poll() {
+                    return Ok(Async::NotReady);
+                }
+                self.drained = None;
+            }
+            if self.queue_depth() <= threshold {
+                return Ok(Async::Ready(()));
+            }
+            // pool wakes the task once a worker takes enough jobs
+            self.drained = Some(Compat::new(self.queue_drained(threshold)));
+        }
+    }
+
+    fn call(&mut self, req: Req) -> Self::Future {
+        let f = self.f.clone();
+        let fut = match self.pool {
+            Some(ref pool) => pool.run(move || f(req)),
+            None => crate::run(move || f(req)),
+        };
+        Compat::new(fut)
+    }
+}
+
+impl<F, Req, Res, E> NewService for BlockingService<F, Req>
+where
+    F: Fn(Req) -> Result<Res, E> + Send + Sync + 'static,
+    Req: Send + 'static,
+    Res: Send + 'static,
+    E: Send + 'static,
+{
+    type Request = Req;
+    type Response = Res;
+    type Error = BlockingError<E>;
+    type Config = ();
+    type Service = BlockingService<F, Req>;
+    type InitError = ();
+    type Future = FutureResult<Self::Service, Self::InitError>;
+
+    fn new_service(&self, _: &()) -> Self::Future {
+        ok(self.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::executor::block_on;
+    use futures01::executor::{spawn, Notify, NotifyHandle};
+    use futures01::future::poll_fn;
+
+    #[test]
+    fn test_blocking_service() {
+        let mut srv = BlockingService::new(|n: u32| if n > 0 { Ok(n * 2) } else { Err(()) });
+        assert!(srv.poll_ready().unwrap().is_ready());
+        assert_eq!(srv.call(2).wait().unwrap(), 4);
+        match srv.call(0).wait() {
+            Err(BlockingError::Error(())) => (),
+            _ => panic!("expected service error"),
+        }
+
+        let mut srv = srv.new_service(&()).wait().unwrap();
+        assert_eq!(srv.call(3).wait().unwrap(), 6);
+    }
+
+    struct Notified(AtomicUsize);
+
+    impl Notify for Notified {
+        fn notify(&self, _: usize) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_queue_threshold() {
+        let pool = Pool::builder().num_threads(1).build();
+        let mut srv = BlockingService::with_pool(pool.clone(), |n: u32| Ok::<_, ()>(n))
+            .queue_threshold(0);
+
+        // occupy the only worker and queue one more job
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let (started_tx, started_rx) = std::sync::mpsc::channel();
+        pool.execute(move || {
+            let _ = started_tx.send(());
+            let _ = rx.recv();
+        })
+        .unwrap();
+        started_rx.recv().unwrap();
+        let queued = pool.run_fn(|| ());
+
+        let notified = Arc::new(Notified(AtomicUsize::new(0)));
+        let handle = NotifyHandle::from(notified.clone());
+        let mut ready = spawn(poll_fn(|| srv.poll_ready()));
+        assert!(ready.poll_future_notify(&handle, 0).unwrap().is_not_ready());
+        assert!(ready.poll_future_notify(&handle, 0).unwrap().is_not_ready());
+
+        // task is not woken until the queue drains
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(notified.0.load(Ordering::SeqCst), 0);
+
+        drop(tx);
+        assert!(block_on(queued).is_ok());
+        assert_eq!(notified.0.load(Ordering::SeqCst), 1);
+        assert!(ready.poll_future_notify(&handle, 0).unwrap().is_ready());
+    }
+}
@@ -0,0 +1,365 @@
+use std::any::{Any, TypeId};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use futures::task::{AtomicWaker, Context, Poll};
+use futures::Future;
+use parking_lot::Mutex;
+
+/// Job is started or aborted
+const CLAIMED: usize = 0b001;
+/// Receiver does not wait for the result
+const CLOSED: usize = 0b010;
+/// Sender is gone, result is set unless the job got canceled
+const COMPLETE: usize = 0b100;
+/// State flags, the rest of the state is the generation of the slot
+const FLAGS: usize = CLAIMED | CLOSED | COMPLETE;
+/// Generation increment
+const GENERATION: usize = FLAGS + 1;
+
+thread_local! {
+    /// Slabs of the current thread, one per result type
+    static SLABS: RefCell<HashMap<TypeId, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Create result slot in the current thread's slab.
+pub(crate) fn channel<T: Send + 'static>() -> (Sender<T>, Receiver<T>) {
+    SLABS
+        .try_with(|slabs| {
+            slabs
+                .borrow_mut()
+                .entry(TypeId::of::<T>())
+                .or_insert_with(|| Box::new(Slab::<T>::new()))
+                .downcast_ref::<Slab<T>>()
+                .expect("slab of a different type")
+                .channel()
+        })
+        // thread-local storage is being destroyed
+        .unwrap_or_else(|_| Slab::new().channel())
+}
+
+/// Slab of reusable result slots.
+///
+/// Slot is addressed by its index and generation. It is returned to the
+/// slab once both sender and receiver are gone, its generation is bumped
+/// so stale handles can not observe the next job.
+pub(crate) struct Slab<T>(Arc<Mutex<SlabInner<T>>>);
+
+struct SlabInner<T> {
+    slots: Vec<Arc<Slot<T>>>,
+    free: Vec<usize>,
+}
+
+impl<T> Clone for Slab<T> {
+    fn clone(&self) -> Self {
+        Slab(self.0.clone())
+    }
+}
+
+impl<T> Slab<T> {
+    pub(crate) fn new() -> Self {
+        Slab(Arc::new(Mutex::new(SlabInner {
+            slots: Vec::new(),
+            free: Vec::new(),
+        })))
+    }
+
+    /// Take a free slot, or grow the slab.
+    pub(crate) fn channel(&self) -> (Sender<T>, Receiver<T>) {
+        let (index, slot) = {
+            let mut inner = self.0.lock();
+            match inner.free.pop() {
+                Some(index) => (index, inner.slots[index].clone()),
+                None => {
+                    let slot = Arc::new(Slot {
+                        state: AtomicUsize::new(0),
+                        refs: AtomicUsize::new(0),
+                        value: Mutex::new(None),
+                        waker: AtomicWaker::new(),
+                    });
+                    inner.slots.push(slot.clone());
+                    (inner.slots.len() - 1, slot)
+                }
+            }
+        };
+        slot.refs.store(2, Ordering::Release);
+        let generation = slot.state.load(Ordering::Acquire) & !FLAGS;
+
+        let handle = |slot| Handle {
+            slab: self.clone(),
+            slot,
+            index,
+            generation,
+        };
+        (Sender(handle(slot.clone())), Receiver(handle(slot)))
+    }
+
+    /// Reset the slot for the next job and put it to the free list.
+    fn release(&self, index: usize, slot: &Slot<T>) {
+        let value = {
+            let mut value = slot.value.lock();
+            let state = slot.state.load(Ordering::Acquire);
+            slot.state
+                .store((state & !FLAGS).wrapping_add(GENERATION), Ordering::Release);
+            value.take()
+        };
+        drop(value);
+        drop(slot.waker.take());
+        self.0.lock().free.push(index);
+    }
+}
+
+/// Result slot of a blocking job.
+///
+/// Holds the result, waker of the job future and the abort flag of the
+/// current job.
+struct Slot<T> {
+    /// Generation of the current job and its state flags
+    state: AtomicUsize,
+    /// Number of live sender and receiver of the current job
+    refs: AtomicUsize,
+    value: Mutex<Option<T>>,
+    waker: AtomicWaker,
+}
+
+impl<T> Slot<T> {
+    /// State flags of the job, `None` if the slot is reused by another job.
+    fn flags(&self, generation: usize) -> Option<usize> {
+        let state = self.state.load(Ordering::Acquire);
+        if state & !FLAGS == generation {
+            Some(state & FLAGS)
+        } else {
+            None
+        }
+    }
+
+    /// Set state flags of the job, returns previous flags or `None` if the
+    /// slot is reused by another job.
+    fn set(&self, generation: usize, flags: usize) -> Option<usize> {
+        let mut state = self.state.load(Ordering::Acquire);
+        loop {
+            if state & !FLAGS != generation {
+                return None;
+            }
+            match self.state.compare_exchange_weak(
+                state,
+                state | flags,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => return Some(state & FLAGS),
+                Err(s) => state = s,
+            }
+        }
+    }
+
+    /// Take the result of the job.
+    fn take(&self, generation: usize) -> Option<T> {
+        let mut value = self.value.lock();
+        self.flags(generation).and_then(|_| value.take())
+    }
+}
+
+/// Abort flag of a job, see `AbortHandle`
+pub(crate) trait Abort: Send + Sync {
+    /// Returns `true` if the job is aborted before it started.
+    fn abort(&self) -> bool;
+}
+
+struct AbortFlag<T> {
+    slot: Arc<Slot<T>>,
+    generation: usize,
+}
+
+impl<T: Send> Abort for AbortFlag<T> {
+    fn abort(&self) -> bool {
+        self.slot
+            .set(self.generation, CLAIMED)
+            .map(|flags| flags & CLAIMED == 0)
+            .unwrap_or(false)
+    }
+}
+
+/// Counted reference to the slot of a job
+struct Handle<T> {
+    slab: Slab<T>,
+    slot: Arc<Slot<T>>,
+    index: usize,
+    generation: usize,
+}
+
+impl<T> Drop for Handle<T> {
+    fn drop(&mut self) {
+        if self.slot.refs.fetch_sub(1, Ordering::AcqRel) == 1 {
+            self.slab.release(self.index, &self.slot);
+        }
+    }
+}
+
+/// Sending side, owned by the job
+pub(crate) struct Sender<T>(Handle<T>);
+
+impl<T> Sender<T> {
+    /// Claim the job for execution, returns `false` if the job is aborted
+    /// or nobody waits for its result.
+    pub(crate) fn claim(&self) -> bool {
+        self.0
+            .slot
+            .set(self.0.generation, CLAIMED)
+            .map(|flags| flags & (CLAIMED | CLOSED) == 0)
+            .unwrap_or(false)
+    }
+
+    /// Set the result, it is dropped right away if receiver is closed.
+    pub(crate) fn send(self, value: T) {
+        let slot = &self.0.slot;
+        let mut cell = slot.value.lock();
+        if let Some(flags) = slot.flags(self.0.generation) {
+            if flags & CLOSED == 0 {
+                *cell = Some(value);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.0.slot.set(self.0.generation, COMPLETE);
+        self.0.slot.waker.wake();
+    }
+}
+
+/// Receiving side, resolves with `None` if the job is canceled
+pub(crate) struct Receiver<T>(Handle<T>);
+
+impl<T> Receiver<T> {
+    /// Abort the job and stop waiting for its result, receiver resolves
+    /// with the result only if it is already set.
+    pub(crate) fn close(&mut self) {
+        self.0.slot.set(self.0.generation, CLAIMED | CLOSED);
+    }
+}
+
+impl<T: Send + 'static> Receiver<T> {
+    /// Receiver with the result already set.
+    pub(crate) fn ready(value: T) -> Self {
+        let (tx, rx) = channel();
+        tx.claim();
+        tx.send(value);
+        rx
+    }
+
+    pub(crate) fn abort_flag(&self) -> Arc<dyn Abort> {
+        Arc::new(AbortFlag {
+            slot: self.0.slot.clone(),
+            generation: self.0.generation,
+        })
+    }
+}
+
+impl<T> Future for Receiver<T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        let Handle {
+            ref slot,
+            generation,
+            ..
+        } = self.0;
+        let pending = |slot: &Slot<T>| {
+            slot.flags(generation)
+                .map(|flags| flags & (COMPLETE | CLOSED) == 0)
+                .unwrap_or(false)
+        };
+        if pending(slot) {
+            slot.waker.register(cx.waker());
+            // sender could complete before registration
+            if pending(slot) {
+                return Poll::Pending;
+            }
+        }
+        Poll::Ready(slot.take(generation))
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.0.slot.set(self.0.generation, CLOSED);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::task::noop_waker_ref;
+
+    fn poll<T>(rx: &mut Receiver<T>) -> Poll<Option<T>> {
+        Pin::new(rx).poll(&mut Context::from_waker(noop_waker_ref()))
+    }
+
+    #[test]
+    fn test_slot() {
+        let (tx, mut rx) = channel();
+        assert!(poll(&mut rx).is_pending());
+        assert!(tx.claim());
+        tx.send(1);
+        assert_eq!(poll(&mut rx), Poll::Ready(Some(1)));
+
+        // dropped sender cancels the job
+        let (tx, mut rx) = channel::<()>();
+        drop(tx);
+        assert_eq!(poll(&mut rx), Poll::Ready(None));
+
+        // aborted job can not be claimed
+        let (tx, mut rx) = channel::<()>();
+        assert!(rx.abort_flag().abort());
+        assert!(!rx.abort_flag().abort());
+        assert!(!tx.claim());
+        drop(tx);
+        assert_eq!(poll(&mut rx), Poll::Ready(None));
+
+        // closed receiver does not wait for the result
+        let (tx, mut rx) = channel();
+        assert!(tx.claim());
+        rx.close();
+        assert_eq!(poll(&mut rx), Poll::Ready(None));
+        tx.send(1);
+        assert_eq!(poll(&mut rx), Poll::Ready(None));
+
+        // job of dropped receiver is not started
+        let (tx, rx) = channel::<()>();
+        drop(rx);
+        assert!(!tx.claim());
+    }
+
+    #[test]
+    fn test_slot_reuse() {
+        let slab = Slab::new();
+        let (tx, mut rx) = slab.channel();
+        let (index, generation) = (rx.0.index, rx.0.generation);
+        let stale = rx.0.slot.clone();
+        let abort = rx.abort_flag();
+        assert!(tx.claim());
+        tx.send(1);
+        assert_eq!(poll(&mut rx), Poll::Ready(Some(1)));
+
+        // slot is in use until both sides are gone
+        let (_tx, busy) = slab.channel();
+        assert_ne!(busy.0.index, index);
+        drop(rx);
+
+        let (tx, mut rx) = slab.channel();
+        assert_eq!(rx.0.index, index);
+        assert_ne!(rx.0.generation, generation);
+
+        // stale handles can not abort or read the next job
+        assert!(!abort.abort());
+        assert!(tx.claim());
+        tx.send(2);
+        assert_eq!(stale.take(generation), None);
+        assert_eq!(poll(&mut rx), Poll::Ready(Some(2)));
+    }
+}
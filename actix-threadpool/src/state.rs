@@ -0,0 +1,137 @@
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use crate::{CpuFuture, Pool};
+
+/// Source of state keys
+static NEXT_KEY: AtomicUsize = AtomicUsize::new(0);
+
+thread_local! {
+    /// Worker state of every `StatefulPool` that used this thread
+    static STATE: RefCell<HashMap<usize, Box<dyn Any>>> = RefCell::new(HashMap::new());
+}
+
+/// Handle to a pool whose workers own a lazily created state of type `T`,
+/// see `Pool::with_state()`.
+pub struct StatefulPool<T> {
+    pool: Pool,
+    key: usize,
+    init: Arc<dyn Fn() -> T + Send + Sync>,
+}
+
+impl<T> Clone for StatefulPool<T> {
+    fn clone(&self) -> Self {
+        StatefulPool {
+            pool: self.pool.clone(),
+            key: self.key,
+            init: self.init.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for StatefulPool<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("StatefulPool")
+            .field("pool", &self.pool)
+            .finish()
+    }
+}
+
+impl<T: 'static> StatefulPool<T> {
+    pub(crate) fn new<N>(pool: Pool, init: N) -> Self
+    where
+        N: Fn() -> T + Send + Sync + 'static,
+    {
+        StatefulPool {
+            pool,
+            key: NEXT_KEY.fetch_add(1, Ordering::Relaxed),
+            init: Arc::new(init),
+        }
+    }
+
+    /// Underlying pool.
+    pub fn pool(&self) -> &Pool {
+        &self.pool
+    }
+
+    /// Execute blocking function with mutable access to the state of the
+    /// worker thread it runs on.
+    ///
+    /// State is created by the first job that runs on the thread. If the
+    /// state initializer or `f` panics, the job resolves with
+    /// `BlockingError::Panic`, state is dropped and next job on the thread
+    /// creates it again.
+    pub fn run_with_state<F, I, E>(&self, f: F) -> CpuFuture<I, E>
+    where
+        F: FnOnce(&mut T) -> Result<I, E> + Send + 'static,
+        I: Send + 'static,
+        E: Send + 'static,
+    {
+        let key = self.key;
+        let init = self.init.clone();
+        self.pool.run(move || {
+            // take state out, so `f` can not observe borrowed map
+            let mut state = STATE
+                .with(|state| state.borrow_mut().remove(&key))
+                .unwrap_or_else(|| Box::new(init()));
+            let res = f(state.downcast_mut::<T>().unwrap());
+            STATE.with(|st| st.borrow_mut().insert(key, state));
+            res
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+    use std::time::Duration;
+
+    struct Counter(Arc<AtomicUsize>);
+
+    impl Drop for Counter {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn test_worker_state() {
+        let inits = Arc::new(AtomicUsize::new(0));
+        let drops = Arc::new(AtomicUsize::new(0));
+        let pool = Pool::builder()
+            .num_threads(2)
+            .idle_timeout(Duration::from_millis(50))
+            .build();
+
+        let inits2 = inits.clone();
+        let drops2 = drops.clone();
+        let pool = pool.with_state(move || {
+            inits2.fetch_add(1, Ordering::SeqCst);
+            (Counter(drops2.clone()), 0)
+        });
+
+        let futs: Vec<_> = (0..10)
+            .map(|_| {
+                pool.run_with_state(|state: &mut (Counter, usize)| {
+                    state.1 += 1;
+                    Ok::<_, ()>(state.1)
+                })
+            })
+            .collect();
+        for fut in futs {
+            assert!(block_on(fut).is_ok());
+        }
+        let created = inits.load(Ordering::SeqCst);
+        assert!((1..=2).contains(&created));
+
+        // state is dropped once idle worker thread exits
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(pool.pool().stats().threads, 0);
+        assert_eq!(drops.load(Ordering::SeqCst), created);
+    }
+}
@@ -0,0 +1,575 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use std::{fmt, thread};
+
+use futures::channel::oneshot;
+use parking_lot::{Condvar, Mutex, MutexGuard};
+
+use crate::observer;
+use crate::queue::Queue;
+
+pub(crate) type Job = Box<dyn FnOnce() + Send>;
+
+/// Source of task ids, shared by all pools
+static NEXT_TASK_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Unique identifier of a blocking job
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TaskId(u64);
+
+impl TaskId {
+    pub(crate) fn next() -> TaskId {
+        TaskId(NEXT_TASK_ID.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl fmt::Display for TaskId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Queued or running blocking job, see `dump_pending()`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub id: TaskId,
+    /// Label passed to `run_named()`
+    pub label: Option<&'static str>,
+    /// Time the job was queued
+    pub enqueued: Instant,
+    /// Time the job started, `None` if it is still queued
+    pub started: Option<Instant>,
+}
+
+/// Job with its metadata
+pub(crate) struct Task {
+    pub(crate) info: TaskInfo,
+    pub(crate) route: Route,
+    pub(crate) job: Job,
+}
+
+/// Queue the job is placed in
+#[derive(Clone, Default)]
+pub(crate) struct Route {
+    /// Tenant the job is queued for, see `Pool::run_as()`
+    pub(crate) tenant: Option<Arc<str>>,
+    /// Lane of a pinned job, see `Pool::run_pinned()`
+    pub(crate) lane: Option<usize>,
+}
+
+pub(crate) type ThreadHook = Arc<dyn Fn() + Send + Sync>;
+
+/// Order in which queued jobs are executed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheduling {
+    /// Oldest job first. This is the default.
+    Fifo,
+    /// Most recently submitted job first.
+    ///
+    /// Improves cache locality and under overload sheds the oldest jobs,
+    /// which are the most likely to exceed their queue deadline.
+    Lifo,
+}
+
+/// Worker threads configuration
+pub(crate) struct WorkerConfig {
+    pub(crate) num_threads: usize,
+    /// Threads are named `<prefix>-<index>`
+    pub(crate) thread_name_prefix: String,
+    pub(crate) stack_size: Option<usize>,
+    /// Spawn all threads at once instead of on demand
+    pub(crate) eager: bool,
+    pub(crate) scheduling: Scheduling,
+    pub(crate) on_thread_start: Option<ThreadHook>,
+    /// Threads idle for longer exit
+    pub(crate) idle_timeout: Option<Duration>,
+    /// Number of threads kept regardless of `idle_timeout`
+    pub(crate) min_threads: usize,
+    /// Number of pinned lanes
+    pub(crate) lanes: usize,
+}
+
+/// Worker threads and job queue of a pool.
+///
+/// Threads are spawned on demand, they finish queued jobs and exit once
+/// the last handle is dropped.
+#[derive(Clone)]
+pub(crate) struct Workers {
+    inner: Arc<Inner>,
+    _handle: Arc<Handle>,
+}
+
+struct Inner {
+    thread_name_prefix: String,
+    stack_size: Option<usize>,
+    eager: bool,
+    scheduling: Scheduling,
+    on_thread_start: Option<ThreadHook>,
+    idle_timeout: Option<Duration>,
+    min_threads: usize,
+    state: Mutex<State>,
+    /// Notified when job is queued or threads have to exit
+    job_ready: Condvar,
+    /// Notified when queue is empty and no job is running
+    idle: Condvar,
+}
+
+struct State {
+    queue: Queue,
+    /// Jobs that are running, by thread index
+    running: Vec<Option<TaskInfo>>,
+    max_threads: usize,
+    /// Number of spawned threads
+    threads: usize,
+    /// Thread indices in use
+    indices: Vec<bool>,
+    /// Number of threads that finished start hook
+    started: usize,
+    /// Number of threads waiting for a job
+    idle: usize,
+    /// Number of threads executing a job
+    active: usize,
+    /// Number of jobs that panicked
+    panics: usize,
+    /// Number of replaced threads
+    restarted: usize,
+    /// All handles are dropped
+    closed: bool,
+    /// Pending `warm_up()` calls
+    warm_up: Vec<oneshot::Sender<()>>,
+    /// Pending `wait_idle()` calls
+    wait_idle: Vec<oneshot::Sender<()>>,
+    /// Pending `wait_queue()` calls, by queue length they wait for
+    wait_queue: Vec<(usize, oneshot::Sender<()>)>,
+}
+
+impl State {
+    /// Queued jobs can not be picked up by existing threads
+    fn needs_thread(&self) -> bool {
+        self.threads < self.max_threads
+            && self.queue.unpinned_len() > self.idle + (self.threads - self.started)
+    }
+
+    /// Allocate smallest unused thread index
+    fn alloc_index(&mut self) -> usize {
+        let idx = self
+            .indices
+            .iter()
+            .position(|used| !used)
+            .unwrap_or(self.indices.len());
+        self.claim_index(idx);
+        idx
+    }
+
+    fn claim_index(&mut self, idx: usize) {
+        if idx >= self.indices.len() {
+            self.indices.resize(idx + 1, false);
+            self.running.resize(idx + 1, None);
+        }
+        self.indices[idx] = true;
+    }
+
+    fn has_thread(&self, idx: usize) -> bool {
+        self.indices.get(idx).cloned().unwrap_or(false)
+    }
+
+    fn is_idle(&self) -> bool {
+        self.active == 0 && self.queue.is_empty()
+    }
+
+    fn check_wait_queue(&mut self) {
+        let len = self.queue.len();
+        let mut i = 0;
+        while i < self.wait_queue.len() {
+            if len <= self.wait_queue[i].0 {
+                let (_, tx) = self.wait_queue.swap_remove(i);
+                let _ = tx.send(());
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    fn check_warm_up(&mut self) {
+        if self.started >= self.max_threads {
+            for tx in self.warm_up.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+/// Closes the queue once all `Workers` handles are dropped
+struct Handle(Arc<Inner>);
+
+impl Drop for Handle {
+    fn drop(&mut self) {
+        self.0.state.lock().closed = true;
+        self.0.job_ready.notify_all();
+    }
+}
+
+impl Workers {
+    pub(crate) fn new(cfg: WorkerConfig) -> Self {
+        let inner = Arc::new(Inner {
+            thread_name_prefix: cfg.thread_name_prefix,
+            stack_size: cfg.stack_size,
+            eager: cfg.eager,
+            scheduling: cfg.scheduling,
+            on_thread_start: cfg.on_thread_start,
+            idle_timeout: cfg.idle_timeout,
+            min_threads: cfg.min_threads,
+            state: Mutex::new(State {
+                queue: Queue::new(cfg.lanes),
+                running: Vec::new(),
+                max_threads: cfg.num_threads,
+                threads: 0,
+                indices: Vec::new(),
+                started: 0,
+                idle: 0,
+                active: 0,
+                panics: 0,
+                restarted: 0,
+                closed: false,
+                warm_up: Vec::new(),
+                wait_idle: Vec::new(),
+                wait_queue: Vec::new(),
+            }),
+            job_ready: Condvar::new(),
+            idle: Condvar::new(),
+        });
+        if inner.eager {
+            inner.spawn_all(&mut inner.state.lock());
+        }
+
+        Workers {
+            _handle: Arc::new(Handle(inner.clone())),
+            inner,
+        }
+    }
+
+    /// Queue job for execution, returns queue length.
+    pub(crate) fn execute(&self, task: Task) -> usize {
+        let mut st = self.inner.state.lock();
+        let lane = task.route.lane;
+        st.queue.push(task);
+        match lane {
+            Some(lane) => {
+                // only the owner of the lane can run the job
+                let index = lane % st.max_threads.max(1);
+                if !st.has_thread(index) {
+                    st.claim_index(index);
+                    self.inner.spawn_thread(&mut st, index);
+                }
+                self.inner.job_ready.notify_all();
+            }
+            None => {
+                if st.needs_thread() {
+                    self.inner.spawn(&mut st);
+                }
+                self.inner.job_ready.notify_one();
+            }
+        }
+        st.queue.len()
+    }
+
+    /// Number of pinned lanes.
+    pub(crate) fn lanes(&self) -> usize {
+        self.inner.state.lock().queue.lanes()
+    }
+
+    pub(crate) fn lane_depths(&self) -> Vec<usize> {
+        self.inner.state.lock().queue.lane_depths()
+    }
+
+    /// Spawn all threads, returned future resolves once every thread
+    /// has started.
+    pub(crate) fn warm_up(&self) -> impl Future<Output = ()> {
+        let mut st = self.inner.state.lock();
+        self.inner.spawn_all(&mut st);
+
+        let rx = if st.started >= st.max_threads {
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            st.warm_up.push(tx);
+            Some(rx)
+        };
+        async move {
+            if let Some(rx) = rx {
+                let _ = rx.await;
+            }
+        }
+    }
+
+    /// Returned future resolves once queue is empty and no job is running.
+    pub(crate) fn wait_idle(&self) -> impl Future<Output = ()> {
+        let mut st = self.inner.state.lock();
+        let rx = if st.is_idle() {
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            st.wait_idle.push(tx);
+            Some(rx)
+        };
+        async move {
+            if let Some(rx) = rx {
+                let _ = rx.await;
+            }
+        }
+    }
+
+    /// Returned future resolves once at most `len` jobs are queued.
+    pub(crate) fn wait_queue(&self, len: usize) -> impl Future<Output = ()> {
+        let mut st = self.inner.state.lock();
+        let rx = if st.queue.len() <= len {
+            None
+        } else {
+            let (tx, rx) = oneshot::channel();
+            st.wait_queue.push((len, tx));
+            Some(rx)
+        };
+        async move {
+            if let Some(rx) = rx {
+                let _ = rx.await;
+            }
+        }
+    }
+
+    /// Change max number of threads.
+    ///
+    /// Extra threads exit once they finish their current job.
+    pub(crate) fn set_num_threads(&self, num_threads: usize) {
+        let mut st = self.inner.state.lock();
+        st.max_threads = num_threads;
+        if self.inner.eager {
+            self.inner.spawn_all(&mut st);
+        } else {
+            while st.needs_thread() && self.inner.spawn(&mut st) {}
+        }
+        st.check_warm_up();
+        self.inner.job_ready.notify_all();
+    }
+
+    /// Block until queue is empty and no job is running.
+    pub(crate) fn join(&self) {
+        let mut st = self.inner.state.lock();
+        while !st.is_idle() {
+            self.inner.idle.wait(&mut st);
+        }
+    }
+
+    /// Snapshot of queued and running jobs.
+    pub(crate) fn tasks(&self) -> Vec<TaskInfo> {
+        let st = self.inner.state.lock();
+        st.running
+            .iter()
+            .filter_map(|info| *info)
+            .chain(st.queue.iter().map(|task| task.info))
+            .collect()
+    }
+
+    pub(crate) fn max_count(&self) -> usize {
+        self.inner.state.lock().max_threads
+    }
+
+    pub(crate) fn thread_count(&self) -> usize {
+        self.inner.state.lock().threads
+    }
+
+    pub(crate) fn active_count(&self) -> usize {
+        self.inner.state.lock().active
+    }
+
+    pub(crate) fn queued_count(&self) -> usize {
+        self.inner.state.lock().queue.len()
+    }
+
+    pub(crate) fn set_weight(&self, key: Arc<str>, weight: usize) {
+        self.inner.state.lock().queue.set_weight(key, weight);
+    }
+
+    pub(crate) fn tenants(&self) -> HashMap<String, usize> {
+        self.inner.state.lock().queue.tenants()
+    }
+
+    pub(crate) fn restarted_count(&self) -> usize {
+        self.inner.state.lock().restarted
+    }
+
+    #[cfg(test)]
+    pub(crate) fn panic_count(&self) -> usize {
+        self.inner.state.lock().panics
+    }
+}
+
+impl Inner {
+    /// Spawn new worker thread, returns `false` if thread can not be spawned.
+    fn spawn(self: &Arc<Self>, st: &mut State) -> bool {
+        let index = st.alloc_index();
+        self.spawn_thread(st, index)
+    }
+
+    /// Spawn worker thread with claimed `index`.
+    fn spawn_thread(self: &Arc<Self>, st: &mut State, index: usize) -> bool {
+        let mut builder =
+            thread::Builder::new().name(format!("{}-{}", self.thread_name_prefix, index));
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+
+        let inner = self.clone();
+        match builder.spawn(move || inner.run(index)) {
+            Ok(_) => {
+                st.threads += 1;
+                true
+            }
+            Err(e) => {
+                st.indices[index] = false;
+                log::error!("Can not spawn worker thread: {}", e);
+                false
+            }
+        }
+    }
+
+    fn spawn_all(self: &Arc<Self>, st: &mut State) {
+        while st.threads < st.max_threads && self.spawn(st) {}
+    }
+
+    /// Worker thread main loop
+    fn run(self: Arc<Self>, index: usize) {
+        let mut sentinel = Sentinel {
+            inner: &self,
+            index,
+            started: false,
+            active: false,
+        };
+        if let Some(observer) = observer::current() {
+            observer.thread_spawned();
+        }
+        if let Some(ref hook) = self.on_thread_start {
+            hook();
+        }
+
+        let mut st = self.state.lock();
+        st.started += 1;
+        sentinel.started = true;
+        st.check_warm_up();
+
+        loop {
+            let max_threads = st.max_threads;
+            if st.threads > max_threads && !st.queue.has_pinned(index, max_threads) {
+                break;
+            }
+            if let Some(task) = st.queue.pop(self.scheduling, index, max_threads) {
+                st.check_wait_queue();
+                st.active += 1;
+                st.running[index] = Some(TaskInfo {
+                    started: Some(Instant::now()),
+                    ..task.info
+                });
+                sentinel.active = true;
+                MutexGuard::unlocked(&mut st, task.job);
+                sentinel.active = false;
+                st.active -= 1;
+                st.running[index] = None;
+                self.notify_idle(&mut st);
+            } else if st.closed {
+                break;
+            } else {
+                st.idle += 1;
+                let timed_out = match self.idle_timeout {
+                    Some(timeout) => self.job_ready.wait_for(&mut st, timeout).timed_out(),
+                    None => {
+                        self.job_ready.wait(&mut st);
+                        false
+                    }
+                };
+                st.idle -= 1;
+
+                // pending `warm_up()` expects all threads to start
+                if timed_out
+                    && st.queue.is_empty()
+                    && st.threads > self.min_threads
+                    && st.warm_up.is_empty()
+                {
+                    break;
+                }
+            }
+        }
+
+        st.threads -= 1;
+        st.started -= 1;
+        st.indices[index] = false;
+        st.check_warm_up();
+        sentinel.started = false;
+        drop(st);
+
+        if let Some(observer) = observer::current() {
+            observer.thread_exited();
+        }
+    }
+
+    fn notify_idle(&self, st: &mut State) {
+        if st.is_idle() {
+            self.idle.notify_all();
+            for tx in st.wait_idle.drain(..) {
+                let _ = tx.send(());
+            }
+        }
+    }
+}
+
+/// Replaces worker thread that died
+struct Sentinel<'a> {
+    inner: &'a Arc<Inner>,
+    index: usize,
+    started: bool,
+    active: bool,
+}
+
+impl<'a> Sentinel<'a> {
+    fn release(&self) {
+        let name = thread::current().name().unwrap_or("").to_owned();
+
+        let mut st = self.inner.state.lock();
+        st.threads -= 1;
+        st.indices[self.index] = false;
+        if !self.started {
+            // do not respawn thread, start hook would panic again
+            log::error!("Start hook of worker thread {} panicked", name);
+            for tx in st.warm_up.drain(..) {
+                let _ = tx.send(());
+            }
+            return;
+        }
+
+        st.started -= 1;
+        if self.active {
+            st.active -= 1;
+            st.panics += 1;
+            st.running[self.index] = None;
+        }
+        if !st.closed && st.threads < st.max_threads {
+            log::error!("Worker thread {} died, spawning replacement", name);
+            if self.inner.spawn(&mut st) {
+                st.restarted += 1;
+            }
+        }
+        self.inner.notify_idle(&mut st);
+    }
+}
+
+impl<'a> Drop for Sentinel<'a> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.release();
+            if let Some(observer) = observer::current() {
+                observer.thread_exited();
+            }
+        }
+    }
+}
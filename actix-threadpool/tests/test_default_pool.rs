@@ -0,0 +1,25 @@
+use actix_threadpool::{BlockingError, Builder};
+use futures::executor::block_on;
+
+#[test]
+fn test_default_pool_shared() {
+    block_on(actix_threadpool::run(|| Ok::<_, ()>(()))).unwrap();
+    assert!(Builder::new().init().is_err());
+    assert!(Builder::new().init_thread_local().is_err());
+
+    // free functions act on the pool of `run()` from any thread
+    std::thread::spawn(move || {
+        block_on(actix_threadpool::wait_idle());
+        assert_eq!(actix_threadpool::stats().completed_jobs, 1);
+
+        let res = block_on(actix_threadpool::shutdown(None));
+        assert!(res.is_clean());
+    })
+    .join()
+    .unwrap();
+
+    match block_on(actix_threadpool::run(|| Ok::<_, ()>(()))) {
+        Err(BlockingError::Canceled) => (),
+        _ => panic!("expected canceled error"),
+    }
+}
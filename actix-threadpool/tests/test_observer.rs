@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use actix_threadpool::{Pool, PoolObserver};
+use futures::executor::block_on;
+
+#[derive(Default)]
+struct Counters {
+    enqueued: AtomicUsize,
+    started: AtomicUsize,
+    finished: AtomicUsize,
+    panicked: AtomicUsize,
+    spawned: AtomicUsize,
+    exited: AtomicUsize,
+}
+
+struct Observer(Arc<Counters>);
+
+impl PoolObserver for Observer {
+    fn job_enqueued(&self, depth: usize) {
+        assert!(depth > 0);
+        self.0.enqueued.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn job_started(&self, _wait: Duration) {
+        self.0.started.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn job_finished(&self, _run: Duration, panicked: bool) {
+        self.0.finished.fetch_add(1, Ordering::SeqCst);
+        if panicked {
+            self.0.panicked.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    fn thread_spawned(&self) {
+        self.0.spawned.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn thread_exited(&self) {
+        self.0.exited.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn test_observer() {
+    let counters = Arc::new(Counters::default());
+    actix_threadpool::set_observer(Observer(counters.clone())).unwrap();
+
+    let pool = Pool::builder()
+        .num_threads(1)
+        .idle_timeout(Duration::from_millis(50))
+        .build();
+    assert!(block_on(pool.run(|| Ok::<_, ()>(()))).is_ok());
+    assert!(block_on(pool.run_fn(|| panic!("test"))).is_err());
+    block_on(pool.wait_idle());
+
+    // observer can not be replaced once jobs are submitted
+    assert!(actix_threadpool::set_observer(Observer(counters.clone())).is_err());
+
+    // idle worker thread exits
+    std::thread::sleep(Duration::from_millis(300));
+    assert_eq!(pool.stats().threads, 0);
+
+    assert_eq!(counters.enqueued.load(Ordering::SeqCst), 2);
+    assert_eq!(counters.started.load(Ordering::SeqCst), 2);
+    assert_eq!(counters.finished.load(Ordering::SeqCst), 2);
+    assert_eq!(counters.panicked.load(Ordering::SeqCst), 1);
+    assert_eq!(counters.spawned.load(Ordering::SeqCst), 1);
+    assert_eq!(counters.exited.load(Ordering::SeqCst), 1);
+}
@@ -0,0 +1,34 @@
+use actix_threadpool::Builder;
+use futures::executor::block_on;
+
+fn worker_id() -> std::thread::ThreadId {
+    block_on(actix_threadpool::run(|| {
+        Ok::<_, ()>(std::thread::current().id())
+    }))
+    .unwrap()
+}
+
+#[test]
+fn test_thread_local_pools() {
+    Builder::new().num_threads(2).init_thread_local().unwrap();
+    assert!(Builder::new().init().is_err());
+
+    let id = worker_id();
+    block_on(actix_threadpool::wait_idle());
+    assert_eq!(actix_threadpool::stats().completed_jobs, 1);
+    assert_eq!(actix_threadpool::stats().max_threads, 2);
+
+    std::thread::spawn(move || {
+        // every thread has its own pool
+        assert_eq!(actix_threadpool::stats().completed_jobs, 0);
+        assert_ne!(worker_id(), id);
+
+        let _ = block_on(actix_threadpool::shutdown(None));
+        assert!(block_on(actix_threadpool::run(|| Ok::<_, ()>(()))).is_err());
+    })
+    .join()
+    .unwrap();
+
+    // pool of this thread is still running
+    assert!(block_on(actix_threadpool::run(|| Ok::<_, ()>(()))).is_ok());
+}
@@ -73,5 +73,5 @@ fn main() -> io::Result<()> {
         .unwrap()
         .start();
 
-    sys.run()
+    sys.run().map(|_| ())
 }
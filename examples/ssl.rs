@@ -47,5 +47,5 @@ fn main() -> io::Result<()> {
         })?
         .start();
 
-    sys.run()
+    sys.run().map(|_| ())
 }